@@ -0,0 +1,28 @@
+.global _start
+
+.data
+    counter: .dword 0
+    lock: .dword 0
+
+.text
+
+_start:
+    MOV r0, =counter;
+    MOV r1, =lock;
+    MOV r4, #1;
+    MOV r5, #100;
+loop:
+acquire:
+    LDXR r2, [r1];
+    CBNZ r2, acquire;
+    STXR r3, r4, [r1];
+    CBNZ r3, acquire;
+    LDR r2, [r0];
+    ADD r2, r2, #1;
+    STR r2, [r0];
+    MOV r2, #0;
+    SWP r2, r3, [r1];
+    SUB r5, r5, #1;
+    CBNZ r5, loop;
+    LDR r2, [r0];
+    PRINTR r2;
@@ -0,0 +1,18 @@
+.global _start
+.text
+_start:
+    MOV r0, #12345;
+    MOV r1, #1103515245;
+    MOV r2, #2147483647;
+    MOV r3, #1000;
+    MOV r4, #0;
+_loop:
+    MUL r0, r0, r1;
+    ADD r0, r0, #12345;
+    AND r0, r0, r2;
+    AND r5, r0, #65536;
+    CMP r5, #0;
+    CSINC r4, r4, r4, EQ;
+    SUB r3, r3, #1;
+    CBNZ r3, _loop;
+    PRINTR r4;
@@ -5,7 +5,7 @@
     MOV r1, =var_a;
     MOV r2, #0;
 loop:
-    ADD r1, r1, #1;
+    ADD r1, r1, #8;
     SUB r0, r0, #1;
     STR r2, [r1];
     CBNZ r0, loop;
\ No newline at end of file
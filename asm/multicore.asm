@@ -0,0 +1,21 @@
+.global _start
+
+.data
+    flag: .dword 0
+
+.text
+
+_start:
+    MOV r1, =flag;
+    MRS r0, MPIDR_EL1;
+    CBZ r0, producer;
+consumer:
+    LDR r2, [r1];
+    CBZ r2, consumer;
+    PRINTR r2;
+    B end;
+producer:
+    MOV r2, #42;
+    STR r2, [r1];
+end:
+    NOP;
@@ -10,9 +10,11 @@ generate_random:
 
 _start:
     MOV r0, #12345;
-    MOV r1, #1103515245;
+    MOVZ r1, #20077;
+    MOVK r1, #16838, LSL #16;
     MOV r2, #12345;
-    MOV r3, #80000000;
+    MOVZ r3, #46080;
+    MOVK r3, #1220, LSL #16;
 
     BL generate_random;
     PRINTR r0;
@@ -6,241 +6,453 @@ use crate::backend::physical_register::PhysRegFile;
 use crate::backend::register_alias_table::RAT;
 use crate::backend::reorder_buffer::{ROB, ROBSlotState};
 use crate::backend::reservation_station::{RSState, RSTable};
-use crate::cpu::{ArgRegFile, CPUConfig, PC, PerfCounters, Trace};
+use crate::cpu::{ArgRegFile, CPUConfig, HardwareThread, PC, PerfCounters, ROBPartitioning, ThreadPerfCounters, Trace, ZR};
+use crate::frontend::branch_predictor::BranchPredictor;
+use crate::frontend::branch_target_buffer::BTB;
 use crate::frontend::frontend::FrontendControl;
-use crate::instructions::instructions::{DWordType, InstrQueue, Opcode, Operand, RegisterType};
-use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
+use crate::frontend::return_address_stack::{RAS, RASCheckpoint};
+use crate::instructions::instructions::{DWordType, InstrQueue, Opcode, Operand, RegisterType, SystemRegisterType};
+use crate::backend::reservation_station::RS;
+use crate::memory_subsystem::memory_subsystem::{AtomicOp, LoadIssue, MemorySubsystem};
 
 struct CDBBroadcast {
     phys_reg: RegisterType,
     value: DWordType,
 }
 
-pub(crate) struct Backend {
+// The state of a hardware thread in the backend. Every thread renames its instructions with
+// its own RAT and retires them in order from its own ROB.
+struct BackendThread {
     instr_queue: Rc<RefCell<InstrQueue>>,
     arch_reg_file: Rc<RefCell<ArgRegFile>>,
-    memory_subsystem: Rc<RefCell<MemorySubsystem>>,
     frontend_control: Rc<RefCell<FrontendControl>>,
-    rs_table: RSTable,
-    phys_reg_file: Rc<RefCell<PhysRegFile>>,
+    ras: Rc<RefCell<RAS>>,
     rat: RAT,
     rob: ROB,
+    // true if the thread has retired the EXIT
+    exit: bool,
+}
+
+impl BackendThread {
+    // True if the load in the rs can't be performed yet: an older DMB that orders the loads
+    // hasn't retired or an older load-acquire doesn't have its data. A load-acquire also waits
+    // for the older store-releases to be written to memory, and a load that only partially
+    // overlaps with an older store waits for that store to be written to memory.
+    fn is_load_blocked(&self, rs: &RS, memory_subsystem: &MemorySubsystem) -> bool {
+        let rob_slot_index = rs.rob_slot_index.unwrap();
+        let rob_slot = self.rob.get(rob_slot_index);
+        let Some(lq_pos) = rob_slot.lq_pos else {
+            return false;
+        };
+
+        let instr = rob_slot.instr.as_ref().unwrap();
+        if instr.opcode == Opcode::LDAR && memory_subsystem.is_acquire_blocked(rs.thread_id, lq_pos) {
+            return true;
+        }
+
+        let (addr, _) = rs.agen(instr);
+        let size = instr.mem_size;
+        if (0..instr.mem_loads).any(|k| memory_subsystem.is_forward_blocked(rs.thread_id, lq_pos, addr.wrapping_add(k as DWordType * size as DWordType), size)) {
+            return true;
+        }
+
+        (self.rob.seq_retired..self.rob.to_seq(rob_slot_index)).any(|seq| {
+            let older_rob_slot = self.rob.get(self.rob.to_index(seq));
+            let older_instr = older_rob_slot.instr.as_ref().unwrap();
+            older_instr.is_load_barrier()
+                && (older_instr.opcode == Opcode::DMB || older_rob_slot.state != ROBSlotState::EXECUTED)
+        })
+    }
+}
+
+/// The backend of a core. With SMT, the backend serves multiple hardware threads: the
+/// reservation stations, execution units and physical registers are shared, every thread
+/// has its own RAT and ROB. Every cycle, another thread goes first when the issue, rename
+/// and retire bandwidth is divided over the threads.
+pub(crate) struct Backend {
+    core_id: u8,
+    threads: Vec<BackendThread>,
+    memory_subsystem: Rc<RefCell<MemorySubsystem>>,
+    rs_table: RSTable,
+    phys_reg_file: Rc<RefCell<PhysRegFile>>,
     eu_table: EUTable,
+    rob_capacity: u16,
+    rob_partitioning: ROBPartitioning,
     trace: Trace,
     retire_n_wide: u8,
     dispatch_n_wide: u8,
     issue_n_wide: u8,
     cdb_broadcast_buffer: Vec<CDBBroadcast>,
-    pub(crate) exit: bool,
+    cycle_cnt: u64,
     perf_counters: Rc<RefCell<PerfCounters>>,
+    branch_predictor: Rc<RefCell<dyn BranchPredictor>>,
+    btb: Rc<RefCell<BTB>>,
 }
 
 impl Backend {
     pub(crate) fn new(
         cpu_config: &CPUConfig,
-        instr_queue: &Rc<RefCell<InstrQueue>>,
+        core_id: u8,
+        threads: &[HardwareThread],
         memory_subsystem: &Rc<RefCell<MemorySubsystem>>,
-        arch_reg_file: &Rc<RefCell<ArgRegFile>>,
-        frontend_control: &Rc<RefCell<FrontendControl>>,
         perf_counters: &Rc<RefCell<PerfCounters>>,
+        branch_predictor: &Rc<RefCell<dyn BranchPredictor>>,
+        btb: &Rc<RefCell<BTB>>,
     ) -> Backend {
         let phys_reg_file = Rc::new(RefCell::new(PhysRegFile::new(cpu_config.phys_reg_count)));
 
+        // with static partitioning every thread gets its own share of the slots. With dynamic
+        // partitioning a thread can use all slots, but the total is limited; see has_rob_space.
+        let thread_rob_capacity = match cpu_config.rob_partitioning {
+            ROBPartitioning::STATIC => cpu_config.rob_capacity / threads.len() as u16,
+            ROBPartitioning::DYNAMIC => cpu_config.rob_capacity,
+        };
+        assert!(thread_rob_capacity > 0, "Backend: rob_capacity should be at least thread_count");
+
+        let threads = threads.iter().map(|thread| {
+            perf_counters.borrow_mut().threads.push(ThreadPerfCounters { retired_cnt: 0 });
+
+            BackendThread {
+                instr_queue: Rc::clone(&thread.instr_queue),
+                arch_reg_file: Rc::clone(&thread.arch_reg_file),
+                frontend_control: Rc::clone(&thread.frontend_control),
+                ras: Rc::clone(&thread.ras),
+                rat: RAT::new(cpu_config.phys_reg_count, thread_rob_capacity),
+                rob: ROB::new(thread_rob_capacity),
+                exit: false,
+            }
+        }).collect();
+
         Backend {
+            core_id,
+            threads,
             trace: cpu_config.trace.clone(),
-            instr_queue: Rc::clone(instr_queue),
             memory_subsystem: Rc::clone(&memory_subsystem),
-            arch_reg_file: Rc::clone(arch_reg_file),
             rs_table: RSTable::new(cpu_config.rs_count),
             phys_reg_file: Rc::clone(&phys_reg_file),
-            rat: RAT::new(cpu_config.phys_reg_count),
-            rob: ROB::new(cpu_config.rob_capacity),
             eu_table: EUTable::new(cpu_config, &memory_subsystem, &phys_reg_file, &perf_counters),
+            rob_capacity: cpu_config.rob_capacity,
+            rob_partitioning: cpu_config.rob_partitioning.clone(),
             retire_n_wide: cpu_config.retire_n_wide,
             dispatch_n_wide: cpu_config.dispatch_n_wide,
             issue_n_wide: cpu_config.issue_n_wide,
-            cdb_broadcast_buffer: Vec::with_capacity(cpu_config.eu_count as usize),
-            frontend_control: Rc::clone(frontend_control),
-            exit: false,
+            cdb_broadcast_buffer: Vec::new(),
+            cycle_cnt: 0,
             perf_counters: Rc::clone(perf_counters),
+            branch_predictor: Rc::clone(branch_predictor),
+            btb: Rc::clone(btb),
         }
     }
 
+    // True if all hardware threads have retired the EXIT.
+    pub(crate) fn is_exited(&self) -> bool {
+        self.threads.iter().all(|thread| thread.exit)
+    }
+
     pub(crate) fn do_cycle(&mut self) {
+        self.cycle_cnt += 1;
         self.cycle_retire();
         self.cycle_eu_table();
         debug_assert!(self.cdb_broadcast_buffer.is_empty());
         self.cycle_dispatch();
         self.cycle_rs_allocation();
         self.cycle_issue();
+        self.update_undispatched_cnt();
+    }
+
+    // The order in which the threads get to use the bandwidth of a stage in this cycle. The
+    // first thread rotates every cycle so that no thread can starve the others.
+    fn thread_order(&self) -> Vec<usize> {
+        let thread_count = self.threads.len();
+        (0..thread_count)
+            .map(|k| (self.cycle_cnt as usize + k) % thread_count)
+            .collect()
+    }
+
+    // The value of the MPIDR_EL1 register of the thread. Without SMT, affinity level 0 (bits 0-7)
+    // is the core id. With SMT, affinity level 0 is the thread id and affinity level 1 (bits 8-15)
+    // the core id.
+    fn mpidr(&self, thread_id: usize) -> DWordType {
+        if self.threads.len() > 1 {
+            ((self.core_id as DWordType) << 8) | thread_id as DWordType
+        } else {
+            self.core_id as DWordType
+        }
+    }
+
+    fn has_rob_space(&self, thread_id: usize) -> bool {
+        let thread = &self.threads[thread_id];
+        match self.rob_partitioning {
+            ROBPartitioning::STATIC => thread.rob.has_space(),
+            ROBPartitioning::DYNAMIC => {
+                let size: u16 = self.threads.iter().map(|thread| thread.rob.size()).sum();
+                thread.rob.has_space() && size < self.rob_capacity
+            }
+        }
+    }
+
+    // Tells the frontend how many instructions of every thread are waiting to be dispatched.
+    fn update_undispatched_cnt(&mut self) {
+        for thread in &mut self.threads {
+            let rob = &mut thread.rob;
+            let undispatched_cnt = (rob.head..rob.tail)
+                .filter(|seq| {
+                    let rob_slot_index = rob.to_index(*seq);
+                    rob.get_mut(rob_slot_index).state == ROBSlotState::ISSUED
+                })
+                .count();
+            thread.frontend_control.borrow_mut().undispatched_cnt = undispatched_cnt as u16;
+        }
     }
 
-    // issues as many instructions from the instruction queue into the rob as possible.
+    // issues as many instructions from the instruction queues into the robs as possible.
     fn cycle_issue(&mut self) {
         let mut perf_counters = self.perf_counters.borrow_mut();
-        let mut instr_queue = self.instr_queue.borrow_mut();
+        let mut issued_cnt = 0;
 
-        // try to put as many instructions into the rob
-        for _ in 0..self.issue_n_wide {
-            // println!("cycle_issue: instr_queue.isempty: {}, self.rob.has_space: {}", instr_queue.is_empty(), self.rob.has_space());
+        for thread_id in self.thread_order() {
+            let instr_queue = Rc::clone(&self.threads[thread_id].instr_queue);
+            let mut instr_queue = instr_queue.borrow_mut();
 
-            if instr_queue.is_empty() || !self.rob.has_space() {
-                break;
-            }
+            // try to put as many instructions into the rob
+            while issued_cnt < self.issue_n_wide {
+                if instr_queue.is_empty() || !self.has_rob_space(thread_id) {
+                    break;
+                }
 
-            // todo: register renaming should be done here.
+                // todo: register renaming should be done here.
 
-            let instr_queue_head_index = instr_queue.head_index();
-            let instr_queue_slot = instr_queue.get_mut(instr_queue_head_index);
+                let instr_queue_head_index = instr_queue.head_index();
+                let instr_queue_slot = instr_queue.get_mut(instr_queue_head_index);
 
-            let branch_target_predicted = instr_queue_slot.branch_target_predicted;
-            let instr = Rc::clone(&instr_queue_slot.instr);
+                let branch_target_predicted = instr_queue_slot.branch_target_predicted;
+                let ras_checkpoint = instr_queue_slot.ras_checkpoint;
+                let instr = Rc::clone(&instr_queue_slot.instr);
 
-            // If needed, synchronize of the sb being empty
-            if instr.sb_sync() && self.memory_subsystem.borrow().sb.size() > 0 {
-                return;
-            }
+                // If needed, synchronize of the sb being empty
+                if instr.sb_sync() && self.memory_subsystem.borrow().sbs[thread_id].size() > 0 {
+                    break;
+                }
 
-            // If needed, synchronize on the rob being empty
-            if instr.rob_sync() && self.rob.size() > 0 {
-                return;
-            }
+                let rob = &mut self.threads[thread_id].rob;
 
-            let rob_slot_index = self.rob.allocate();
-            let rob_slot = self.rob.get_mut(rob_slot_index);
+                // If needed, synchronize on the rob being empty
+                if instr.rob_sync() && rob.size() > 0 {
+                    break;
+                }
 
-            if self.trace.issue {
-                println!("Issued [{}]", instr);
-            }
+                // An instruction that synchronized on the rob (DSB, an atomic) also keeps the younger
+                // instructions out of the rob until it has retired.
+                if !rob.is_empty() && rob.get_mut(rob.to_index(rob.seq_issued - 1)).instr.as_ref().unwrap().rob_sync() {
+                    break;
+                }
 
-            rob_slot.pc = instr_queue_slot.pc;
-            rob_slot.state = ROBSlotState::ISSUED;
-            rob_slot.instr = Some(instr);
-            rob_slot.branch_target_predicted = branch_target_predicted;
-            self.rob.seq_issued += 1;
-            perf_counters.issue_cnt += 1;
+                let rob_slot_index = rob.allocate();
+                let rob_slot = rob.get_mut(rob_slot_index);
+
+                if self.trace.issue {
+                    println!("Issued [{}]", instr);
+                }
 
-            instr_queue.head_bump();
+                rob_slot.pc = instr_queue_slot.pc;
+                rob_slot.state = ROBSlotState::ISSUED;
+                rob_slot.instr = Some(instr);
+                rob_slot.branch_target_predicted = branch_target_predicted;
+                rob_slot.ras_checkpoint = ras_checkpoint;
+                rob.seq_issued += 1;
+                perf_counters.issue_cnt += 1;
+                issued_cnt += 1;
+
+                instr_queue.head_bump();
+            }
         }
     }
 
     // For any rob entry that doesn't have a reservation station, try to look up a rs.
     fn cycle_rs_allocation(&mut self) {
-        let arch_reg_file = self.arch_reg_file.borrow();
         let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        let mut allocated_cnt = 0;
 
-        for _ in 0..self.issue_n_wide {
-            if self.rob.seq_rs_allocated == self.rob.seq_issued || !self.rs_table.has_idle() {
-                break;
-            }
+        for thread_id in self.thread_order() {
+            let mpidr = self.mpidr(thread_id);
+            let thread = &mut self.threads[thread_id];
+            let arch_reg_file = thread.arch_reg_file.borrow();
+            let rob = &mut thread.rob;
+            let rat = &mut thread.rat;
 
-            let rob_slot_index = self.rob.to_index(self.rob.seq_rs_allocated);
-            let rob_slot = self.rob.get_mut(rob_slot_index);
+            while allocated_cnt < self.issue_n_wide {
+                if rob.seq_rs_allocated == rob.seq_issued || !self.rs_table.has_idle() {
+                    break;
+                }
 
-            debug_assert!(rob_slot.state == ROBSlotState::ISSUED);
-            debug_assert!(rob_slot.eu_index.is_none());
-            debug_assert!(rob_slot.rs_index.is_none());
+                let rob_slot_index = rob.to_index(rob.seq_rs_allocated);
+                let rob_slot = rob.get_mut(rob_slot_index);
 
-            let instr = rob_slot.instr.as_ref().unwrap();
+                debug_assert!(rob_slot.state == ROBSlotState::ISSUED);
+                debug_assert!(rob_slot.eu_index.is_none());
+                debug_assert!(rob_slot.rs_index.is_none());
+
+                let instr = rob_slot.instr.as_ref().unwrap();
 
-            if instr.mem_stores > 0 {
-                if !memory_subsystem.sb.has_space() {
+                let sb = &mut memory_subsystem.sbs[thread_id];
+                if instr.mem_stores > 0 && !sb.has_space_for(instr.mem_stores) {
                     // we can't allocate a slot in the store buffer, we are done
                     break;
                 }
 
-                rob_slot.sb_pos = Some(memory_subsystem.sb.allocate());
-            }
+                let sb_seq = sb.tail_seq();
+                let lq = &mut memory_subsystem.lqs[thread_id];
+                if instr.mem_loads > 0 && !lq.has_space_for(instr.mem_loads) {
+                    // we can't allocate a slot in the load queue, we are done
+                    break;
+                }
 
-            let rs_index = self.rs_table.allocate();
-            let rs = self.rs_table.get_mut(rs_index);
-            debug_assert!(rs.state == RSState::BUSY);
+                // a pair gets two consecutive entries; only the first one is recorded
+                if instr.mem_loads > 0 {
+                    rob_slot.lq_pos = Some(lq.allocate(sb_seq));
+                    for _ in 1..instr.mem_loads {
+                        lq.allocate(sb_seq);
+                    }
+                }
 
-            rob_slot.rs_index = Some(rs_index);
-
-            rs.rob_slot_index = Some(rob_slot_index);
-            rs.opcode = instr.opcode;
-            rs.source_cnt = instr.source_cnt;
-
-            // Register renaming of the source operands
-            for operand_index in 0..instr.source_cnt as usize {
-                let operand_instr = &instr.source[operand_index];
-                let mut operand_rs = &mut rs.source[operand_index];
-                operand_rs.operand = Some(*operand_instr);
-                match operand_instr {
-                    Operand::MemRegisterIndirect(arch_reg) |
-                    Operand::Register(arch_reg) => {
-                        let rat_entry = self.rat.get(*arch_reg);
-                        if rat_entry.valid {
-                            let phys_reg_file = self.phys_reg_file.borrow_mut();
-                            let phys_reg_entry = phys_reg_file.get(rat_entry.phys_reg);
-                            if phys_reg_entry.has_value {
-                                //we got lucky, there is a value in the physical register.
-                                operand_rs.value = Some(phys_reg_entry.value);
-                                rs.source_ready_cnt += 1;
+                if instr.mem_stores > 0 {
+                    let sb = &mut memory_subsystem.sbs[thread_id];
+                    rob_slot.sb_pos = Some(sb.allocate(instr.opcode == Opcode::STLR));
+                    for _ in 1..instr.mem_stores {
+                        sb.allocate(false);
+                    }
+                }
+
+                let rs_index = self.rs_table.allocate();
+                let rs = self.rs_table.get_mut(rs_index);
+                debug_assert!(rs.state == RSState::BUSY);
+
+                rob_slot.rs_index = Some(rs_index);
+
+                rs.thread_id = thread_id as u8;
+                rs.rob_slot_index = Some(rob_slot_index);
+                rs.opcode = instr.opcode;
+                rs.source_cnt = instr.source_cnt;
+
+                // Register renaming of the source operands
+                for operand_index in 0..instr.source_cnt as usize {
+                    let operand_instr = &instr.source[operand_index];
+                    let mut operand_rs = &mut rs.source[operand_index];
+                    operand_rs.operand = Some(*operand_instr);
+                    match operand_instr {
+                        Operand::Register(ZR) => {
+                            operand_rs.value = Some(0);
+                            rs.source_ready_cnt += 1;
+                        }
+                        Operand::MemRegisterIndirect(arch_reg) |
+                        Operand::Register(arch_reg) => {
+                            let rat_entry = rat.get(*arch_reg);
+                            if rat_entry.valid {
+                                let phys_reg_file = self.phys_reg_file.borrow_mut();
+                                let phys_reg_entry = phys_reg_file.get(rat_entry.phys_reg);
+                                if phys_reg_entry.has_value {
+                                    //we got lucky, there is a value in the physical register.
+                                    operand_rs.value = Some(phys_reg_entry.value);
+                                    rs.source_ready_cnt += 1;
+                                } else {
+                                    // cdb broadcast will update
+                                    operand_rs.phys_reg = Some(rat_entry.phys_reg);
+                                }
                             } else {
-                                // cdb broadcast will update
-                                operand_rs.phys_reg = Some(rat_entry.phys_reg);
+                                let value = arch_reg_file.get_value(*arch_reg);
+                                operand_rs.value = Some(value);
+                                rs.source_ready_cnt += 1;
                             }
-                        } else {
-                            let value = arch_reg_file.get_value(*arch_reg);
-                            operand_rs.value = Some(value);
+                        }
+                        Operand::Memory(addr) => {
+                            operand_rs.value = Some(*addr);
                             rs.source_ready_cnt += 1;
                         }
+                        Operand::Code(addr) => {
+                            operand_rs.value = Some(*addr);
+                            rs.source_ready_cnt += 1;
+                        }
+                        Operand::Immediate(value) => {
+                            operand_rs.value = Some(*value);
+                            rs.source_ready_cnt += 1;
+                        }
+                        Operand::SystemRegister(SystemRegisterType::MPIDR) => {
+                            operand_rs.value = Some(mpidr);
+                            rs.source_ready_cnt += 1;
+                        }
+                        Operand::BarrierOption(_) => {
+                            operand_rs.value = Some(0);
+                            rs.source_ready_cnt += 1;
+                        }
+                        Operand::MemRegIndirectWithOffset(_, _) |
+                        Operand::MemRegIndirectWithRegOffset(_, _, _) |
+                        Operand::MemPreIndexed(_, _) |
+                        Operand::Condition(_) |
+                        Operand::Shift(_) |
+                        Operand::WRegister(_) |
+                        Operand::Unused => panic!("Illegal source {:?} {}", operand_instr, instr)
                     }
-                    Operand::Memory(addr) => {
-                        operand_rs.value = Some(*addr);
-                        rs.source_ready_cnt += 1;
-                    }
-                    Operand::Code(addr) => {
-                        operand_rs.value = Some(*addr);
-                        rs.source_ready_cnt += 1;
-                    }
-                    Operand::Immediate(value) => {
-                        operand_rs.value = Some(*value);
-                        rs.source_ready_cnt += 1;
-                    }
-                    Operand::Unused => panic!("Illegal source {:?} {}", operand_instr, instr)
                 }
-            }
 
-            // Register renaming of the sink operands.
-            rs.sink_cnt = instr.sink_cnt;
-            for operand_index in 0..instr.sink_cnt as usize {
-                let operand_instr = &instr.sink[operand_index];
-                let mut operand_rs = &mut rs.sink[operand_index];
-                operand_rs.operand = Some(*operand_instr);
-                match operand_instr {
-                    Operand::Register(arch_reg) => {
-                        let phys_reg = self.phys_reg_file.borrow_mut().allocate();
-                        // update the RAT entry to point to the newest phys_reg
-                        let rat_entry = self.rat.get_mut(*arch_reg);
-                        rat_entry.phys_reg = phys_reg;
-                        rat_entry.valid = true;
-
-                        rob_slot.sink_phys_regs[operand_index] = Some(phys_reg);
-
-                        operand_rs.phys_reg = Some(phys_reg);
-                    }
-                    Operand::Memory(_) => {}
-                    Operand::Unused |
-                    Operand::Immediate(_) |
-                    Operand::Code(_) |
-                    Operand::MemRegisterIndirect(_) => {
-                        panic!("Illegal sink {:?}", operand_instr)
+                // Register renaming of the sink operands.
+                rs.sink_cnt = instr.sink_cnt;
+                for operand_index in 0..instr.sink_cnt as usize {
+                    let operand_instr = &instr.sink[operand_index];
+                    let mut operand_rs = &mut rs.sink[operand_index];
+                    operand_rs.operand = Some(*operand_instr);
+                    match operand_instr {
+                        Operand::Register(ZR) => {
+                            // the zero register isn't renamed; the write goes to a phys register that is never read
+                            let phys_reg = self.phys_reg_file.borrow().zero_reg;
+                            rob_slot.sink_phys_regs[operand_index] = Some(phys_reg);
+                            operand_rs.phys_reg = Some(phys_reg);
+                        }
+                        Operand::Register(arch_reg) => {
+                            let phys_reg = self.phys_reg_file.borrow_mut().allocate();
+                            // update the RAT entry to point to the newest phys_reg
+                            let rat_entry = rat.get_mut(*arch_reg);
+                            rat_entry.phys_reg = phys_reg;
+                            rat_entry.valid = true;
+
+                            rob_slot.sink_phys_regs[operand_index] = Some(phys_reg);
+
+                            operand_rs.phys_reg = Some(phys_reg);
+                        }
+                        Operand::Memory(_) => {}
+                        Operand::Unused |
+                        Operand::Immediate(_) |
+                        Operand::Code(_) |
+                        Operand::MemRegisterIndirect(_) |
+                        Operand::SystemRegister(_) |
+                        Operand::BarrierOption(_) |
+                        Operand::MemRegIndirectWithOffset(_, _) |
+                        Operand::MemRegIndirectWithRegOffset(_, _, _) |
+                        Operand::MemPreIndexed(_, _) |
+                        Operand::Condition(_) |
+                        Operand::Shift(_) |
+                        Operand::WRegister(_) => {
+                            panic!("Illegal sink {:?}", operand_instr)
+                        }
                     }
                 }
-            }
 
-            if rs.source_ready_cnt == rs.source_cnt {
-                self.rs_table.enqueue_ready(rs_index);
-            }
+                // A checkpoint of the RAT is made after the branch is renamed so that the
+                // mappings of the younger instructions can be discarded on a misprediction.
+                if instr.is_branch() {
+                    rat.checkpoint(rob_slot_index);
+                }
 
-            if self.trace.allocate_rs {
-                println!("Allocate RS [{}]", instr);
-            }
+                if rs.source_ready_cnt == rs.source_cnt {
+                    self.rs_table.enqueue_ready(rs_index);
+                }
+
+                if self.trace.allocate_rs {
+                    println!("Allocate RS [{}]", instr);
+                }
 
-            self.rob.seq_rs_allocated += 1;
+                rob.seq_rs_allocated += 1;
+                allocated_cnt += 1;
+            }
         }
     }
 
@@ -248,30 +460,68 @@ impl Backend {
         let mut perf_counters = self.perf_counters.borrow_mut();
 
         for _ in 0..self.dispatch_n_wide {
-            if !self.rs_table.has_ready() || !self.eu_table.has_idle() {
+            if !self.rs_table.has_ready() {
                 break;
             }
 
-            let rs_index = self.rs_table.deque_ready();
+            // find the first ready rs that has an eligible port available and isn't a load held
+            // back by a barrier
+            let eu_table = &self.eu_table;
+            let threads = &self.threads;
+            let memory_subsystem = self.memory_subsystem.borrow();
+            let Some(rs_index) = self.rs_table.deque_ready_matching(|rs| {
+                eu_table.has_idle(rs.opcode)
+                    && !threads[rs.thread_id as usize].is_load_blocked(rs, &memory_subsystem)
+            }) else {
+                break;
+            };
+            drop(memory_subsystem);
 
             let rs = self.rs_table.get_mut(rs_index);
             debug_assert!(rs.state == RSState::BUSY);
 
             let rob_slot_index = rs.rob_slot_index.unwrap();
-            let rob_slot = self.rob.get_mut(rob_slot_index);
+            let rob_slot = self.threads[rs.thread_id as usize].rob.get_mut(rob_slot_index);
+
+            if rob_slot.instr.as_ref().unwrap().is_atomic() {
+                // the port only does the address generation; the atomic is performed by the memory subsystem
+                let addr = rs.source[0].value.unwrap();
+                let agen_cycles = self.eu_table.latency(rs.opcode).cycles;
+                if !self.memory_subsystem.borrow_mut().issue_atomic(rs.thread_id, addr, rob_slot_index, agen_cycles, atomic_op(rs)) {
+                    // there is no line fill buffer available; the atomic needs to try again.
+                    self.rs_table.enqueue_ready(rs_index);
+                    break;
+                }
+            } else if let Some(lq_pos) = rob_slot.lq_pos {
+                // the port only does the address generation; the load is performed by the memory subsystem
+                let instr = rob_slot.instr.as_ref().unwrap();
+                let (addr, _) = rs.agen(instr);
+                let load = LoadIssue {
+                    thread_id: rs.thread_id,
+                    lq_index: lq_pos,
+                    rob_slot_index,
+                    addr,
+                    size: instr.mem_size,
+                    agen_cycles: self.eu_table.latency(rs.opcode).cycles,
+                    pc: rob_slot.pc,
+                };
+                if !self.memory_subsystem.borrow_mut().issue_load(&load) {
+                    // there is no miss status holding register or line fill buffer available;
+                    // the load needs to try again.
+                    self.rs_table.enqueue_ready(rs_index);
+                    break;
+                }
+            }
 
-            let eu_index = self.eu_table.allocate();
-            let eu = self.eu_table.get_mut(eu_index);
-            debug_assert!(eu.state == EUState::EXECUTING);
+            let eu_index = self.eu_table.allocate(rs_index, rs.opcode);
+            debug_assert!(self.eu_table.get_mut(eu_index).state == EUState::EXECUTING);
 
             let instr = rob_slot.instr.as_ref().unwrap();
 
-            eu.rs_index = Some(rs_index);
-            eu.cycles_remaining = instr.cycles;
-
             rob_slot.state = ROBSlotState::DISPATCHED;
             rob_slot.eu_index = Some(eu_index);
 
+            perf_counters.ports[eu_index as usize].dispatch_cnt += 1;
             if self.trace.dispatch {
                 println!("Dispatched [{}]", instr);
             }
@@ -280,6 +530,9 @@ impl Backend {
     }
 
     fn cycle_eu_table(&mut self) {
+        // per thread, the sequence number of the oldest branch that got mispredicted in this cycle.
+        let mut mispredicted_branch_seqs: Vec<Option<u64>> = vec![None; self.threads.len()];
+
         {
             // todo: we should only iterate over the used execution units.
             for eu_index in 0..self.eu_table.capacity {
@@ -289,59 +542,143 @@ impl Backend {
                     continue;
                 }
 
-                debug_assert!(eu.state == EUState::EXECUTING);
-
-                let rs_index = eu.rs_index.unwrap();
+                eu.cycle();
+
+                while let Some(rs_index) = eu.pop_completed() {
+                    let rs = self.rs_table.get_mut(rs_index);
+                    debug_assert!(rs.state == RSState::BUSY);
+
+                    let thread_id = rs.thread_id as usize;
+                    let thread = &mut self.threads[thread_id];
+                    let rob_index = rs.rob_slot_index.unwrap();
+                    let rob_slot = thread.rob.get_mut(rob_index);
+                    debug_assert!(rob_slot.state == ROBSlotState::DISPATCHED,
+                                  "rob_slot is not in dispatched state, but in {:?}, rs_index={}", rob_slot.state, rs_index);
+                    debug_assert!(rob_slot.rs_index.is_some());
+                    debug_assert!(rob_slot.eu_index == Some(eu_index));
+
+                    let instr = rob_slot.instr.as_ref().unwrap();
+                    if instr.mem_loads > 0 || instr.is_atomic() {
+                        // the address generation is done and the port is released; the load or
+                        // atomic completes when the memory subsystem has the data. The base
+                        // register of a pre- or post-indexed load is written back right away;
+                        // it is the sink after the loaded registers.
+                        if instr.addressing_mode.has_writeback() {
+                            let (_, writeback) = rs.agen(instr);
+                            let phys_reg = rs.sink[instr.mem_loads as usize].phys_reg.unwrap();
+                            self.phys_reg_file.borrow_mut().set_value(phys_reg, writeback);
+                            self.cdb_broadcast_buffer.push(CDBBroadcast { phys_reg, value: writeback });
+                        }
+                        rob_slot.eu_index = None;
+                        self.rs_table.deallocate(rs_index);
+                        rob_slot.rs_index = None;
+                        continue;
+                    }
 
-                let rs = self.rs_table.get_mut(rs_index);
-                debug_assert!(rs.state == RSState::BUSY);
+                    eu.execute(rs, rob_slot);
 
-                let rob_index = rs.rob_slot_index.unwrap();
-                let rob_slot = self.rob.get_mut(rob_index);
-                debug_assert!(rob_slot.state == ROBSlotState::DISPATCHED,
-                              "rob_slot is not in dispatched state, but in {:?}, rs_index={}", rob_slot.state, rs_index);
-                debug_assert!(rob_slot.rs_index.is_some());
-                debug_assert!(rob_slot.eu_index.is_some());
+                    for sink_index in 0..rs.sink_cnt {
+                        let sink = &mut rs.sink[sink_index as usize];
+                        match sink.operand.unwrap() {
+                            Operand::Register(_) => {
+                                let phys_reg = sink.phys_reg.unwrap();
+                                let mut phys_reg_file = self.phys_reg_file.borrow_mut();
+                                let phys_reg_entry = phys_reg_file.get_mut(phys_reg);
+                                self.cdb_broadcast_buffer.push(CDBBroadcast { phys_reg, value: phys_reg_entry.value });
+                            }
+                            Operand::Memory(addr) => {}
+                            Operand::Immediate(_) |
+                            Operand::Code(_) |
+                            Operand::MemRegisterIndirect(_) |
+                            Operand::SystemRegister(_) |
+                            Operand::BarrierOption(_) |
+                            Operand::MemRegIndirectWithOffset(_, _) |
+                            Operand::MemRegIndirectWithRegOffset(_, _, _) |
+                            Operand::MemPreIndexed(_, _) |
+                            Operand::Condition(_) |
+                            Operand::Shift(_) |
+                            Operand::WRegister(_) |
+                            Operand::Unused => panic!("Illegal sink {:?}", sink.operand.unwrap()),
+                        }
+                    }
 
-                eu.cycle(rs, rob_slot);
+                    rob_slot.eu_index = None;
 
-                if eu.state == EUState::EXECUTING {
-                    continue;
-                }
+                    self.rs_table.deallocate(rs_index);
+                    rob_slot.rs_index = None;
 
-                debug_assert!(eu.state == EUState::COMPLETED);
+                    rob_slot.state = ROBSlotState::EXECUTED;
 
-                for sink_index in 0..rs.sink_cnt {
-                    let sink = &mut rs.sink[sink_index as usize];
-                    match sink.operand.unwrap() {
-                        Operand::Register(_) => {
-                            let phys_reg = sink.phys_reg.unwrap();
-                            let mut phys_reg_file = self.phys_reg_file.borrow_mut();
-                            let phys_reg_entry = phys_reg_file.get_mut(phys_reg);
-                            self.cdb_broadcast_buffer.push(CDBBroadcast { phys_reg, value: phys_reg_entry.value });
+                    let instr = rob_slot.instr.as_ref().unwrap();
+                    if instr.is_branch() {
+                        if rob_slot.branch_target_actual != rob_slot.branch_target_predicted {
+                            let seq = thread.rob.to_seq(rob_index);
+                            let mispredicted_branch_seq = &mut mispredicted_branch_seqs[thread_id];
+                            if mispredicted_branch_seq.is_none_or(|oldest| seq < oldest) {
+                                *mispredicted_branch_seq = Some(seq);
+                            }
+                        } else {
+                            // the branch is resolved, so the checkpoint isn't needed anymore
+                            thread.rat.release_checkpoint(rob_index);
                         }
-                        Operand::Memory(addr) => {}
-                        Operand::Immediate(_) |
-                        Operand::Code(_) |
-                        Operand::MemRegisterIndirect(_) |
-                        Operand::Unused => panic!("Illegal sink {:?}", sink.operand.unwrap()),
                     }
                 }
+            }
+        }
 
-                let eu_index = eu.index;
-                self.eu_table.deallocate(eu_index);
-                rob_slot.eu_index = None;
-
-                self.rs_table.deallocate(rs_index);
-                rob_slot.rs_index = None;
+        self.complete_loads();
 
-                rob_slot.state = ROBSlotState::EXECUTED;
+        for (thread_id, mispredicted_branch_seq) in mispredicted_branch_seqs.into_iter().enumerate() {
+            if let Some(seq) = mispredicted_branch_seq {
+                self.squash(thread_id, seq);
             }
         }
 
         self.cdb_broadcast();
     }
 
+    // Completes the loads and atomics for which the memory subsystem has the data available.
+    fn complete_loads(&mut self) {
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+
+        while let Some((thread_id, rob_slot_index, addr, atomic)) = memory_subsystem.completed_load() {
+            let rob_slot = self.threads[thread_id as usize].rob.get_mut(rob_slot_index);
+            debug_assert!(rob_slot.state == ROBSlotState::DISPATCHED);
+            debug_assert!(rob_slot.eu_index.is_none());
+
+            let mut perf_counters = self.perf_counters.borrow_mut();
+            perf_counters.execute_cnt += 1;
+            if self.trace.execute {
+                println!("Executing {}", rob_slot.instr.as_ref().unwrap());
+            }
+
+            // a pair loads its second register from the next address using the next load queue entry
+            let instr = rob_slot.instr.as_ref().unwrap();
+            let mut lq_pos = rob_slot.lq_pos;
+            for k in 0..instr.mem_loads.max(1) as usize {
+                let value = match atomic {
+                    Some(op) => memory_subsystem.atomic(thread_id, addr, op),
+                    None => {
+                        let lq_index = lq_pos.unwrap();
+                        let size = instr.mem_size;
+                        let (value, forwarded) = memory_subsystem.load(thread_id, lq_index, addr.wrapping_add((k * size as usize) as DWordType), size);
+                        if forwarded {
+                            perf_counters.store_forward_cnt += 1;
+                        }
+                        lq_pos = Some(memory_subsystem.lqs[thread_id as usize].next_index(lq_index));
+                        instr.extend_loaded(value)
+                    }
+                };
+
+                let phys_reg = rob_slot.sink_phys_regs[k].unwrap();
+                self.phys_reg_file.borrow_mut().set_value(phys_reg, value);
+                self.cdb_broadcast_buffer.push(CDBBroadcast { phys_reg, value });
+            }
+
+            rob_slot.state = ROBSlotState::EXECUTED;
+        }
+    }
+
     fn cdb_broadcast(&mut self) {
         let rs_table_capacity = self.rs_table.capacity;
 
@@ -354,7 +691,7 @@ impl Backend {
                 }
 
                 let rob_slot_index = rs.rob_slot_index.unwrap();
-                let rob_slot = self.rob.get_mut(rob_slot_index);
+                let rob_slot = self.threads[rs.thread_id as usize].rob.get_mut(rob_slot_index);
                 if rob_slot.state != ROBSlotState::ISSUED {
                     continue;
                 }
@@ -364,7 +701,9 @@ impl Backend {
                 for source_index in 0..rs.source_cnt as usize {
                     let operand_rs = &mut rs.source[source_index];
                     if let Some(phys_reg) = operand_rs.phys_reg {
-                        if phys_reg == broadcast.phys_reg {
+                        // a phys_reg can be reused after a squash, so only wait for
+                        // a value that hasn't arrived yet.
+                        if phys_reg == broadcast.phys_reg && operand_rs.value.is_none() {
                             operand_rs.value = Some(broadcast.value);
                             rs.source_ready_cnt += 1;
                             added_src_ready = true;
@@ -385,109 +724,269 @@ impl Backend {
     }
 
     fn cycle_retire(&mut self) {
-        let mut bad_speculation = false;
+        // the threads with a load that caused a memory ordering violation and its rob slot
+        let mut nuked_rob_slots: Vec<(usize, u16)> = Vec::new();
+        // the threads that retired an ISB and the pc and RAS checkpoint to continue with
+        let mut isb_restarts: Vec<(usize, usize, RASCheckpoint)> = Vec::new();
 
         {
-            let mut arch_reg_file = self.arch_reg_file.borrow_mut();
             let mut perf_counters = self.perf_counters.borrow_mut();
             let mut phys_reg_file = &mut self.phys_reg_file.borrow_mut();
-            //let frontend_control = self.frontend_control.borrow_mut();
             let mut memory_subsytem = self.memory_subsystem.borrow_mut();
+            let mut retired_cnt = 0;
 
-            for _ in 0..self.retire_n_wide {
-                let rob_slot_index = self.rob.to_index(self.rob.seq_retired);
-                let rob_slot = self.rob.get_mut(rob_slot_index);
+            for thread_id in self.thread_order() {
+                let thread = &mut self.threads[thread_id];
+                let mut arch_reg_file = thread.arch_reg_file.borrow_mut();
 
-                if rob_slot.state != ROBSlotState::EXECUTED {
-                    break;
-                }
+                while retired_cnt < self.retire_n_wide {
+                    let rob_slot_index = thread.rob.to_index(thread.rob.seq_retired);
+                    let rob_slot = thread.rob.get_mut(rob_slot_index);
 
-                let instr = rob_slot.instr.as_ref().unwrap();
+                    if rob_slot.state != ROBSlotState::EXECUTED {
+                        break;
+                    }
 
-                perf_counters.retired_cnt += 1;
+                    let instr = rob_slot.instr.as_ref().unwrap();
 
-                if instr.opcode == Opcode::EXIT {
-                    self.exit = true;
-                }
+                    if instr.is_store_barrier() && memory_subsytem.sbs[thread_id].has_committed() {
+                        // the older stores need to be written to memory before the younger
+                        // loads can be performed
+                        perf_counters.barrier_stall_cnt += 1;
+                        break;
+                    }
 
-                if self.trace.retire {
-                    println!("Retiring {}", instr);
-                }
+                    if let Some(lq_pos) = rob_slot.lq_pos {
+                        let lq = &mut memory_subsytem.lqs[thread_id];
+                        if lq.is_violated(lq_pos) || (instr.mem_loads > 1 && lq.is_violated(lq.next_index(lq_pos))) {
+                            // an older store to the same address executed after the load, so the
+                            // load has read a stale value. The load and everything younger needs
+                            // to be executed again.
+                            nuked_rob_slots.push((thread_id, rob_slot_index));
+                            break;
+                        }
 
-                for sink_index in 0..instr.sink_cnt as usize {
-                    let sink = instr.sink[sink_index];
-                    match sink {
-                        Operand::Register(arch_reg) => {
-                            let rat_entry = self.rat.get_mut(arch_reg);
-                            debug_assert!(rat_entry.valid);
+                        let mut lq_pos = lq_pos;
+                        for _ in 0..instr.mem_loads {
+                            lq.deallocate_head(lq_pos);
+                            lq_pos = lq.next_index(lq_pos);
+                        }
+                    }
+
+                    perf_counters.retired_cnt += 1;
+                    perf_counters.threads[thread_id].retired_cnt += 1;
 
-                            let rat_phys_reg = rat_entry.phys_reg;
-                            let rob_phys_reg = rob_slot.sink_phys_regs[sink_index].unwrap();
+                    if instr.opcode == Opcode::EXIT {
+                        thread.exit = true;
+                    }
+
+                    if self.trace.retire {
+                        println!("Retiring {}", instr);
+                    }
+
+                    for sink_index in 0..instr.sink_cnt as usize {
+                        let sink = instr.sink[sink_index];
+                        match sink {
+                            // the write to the zero register is discarded
+                            Operand::Register(ZR) => {}
+                            Operand::Register(arch_reg) => {
+                                let rob_phys_reg = rob_slot.sink_phys_regs[sink_index].unwrap();
 
-                            // only when the physical register on the rat is the same as the physical register used for that
-                            // instruction, the rat entry should be invalidated
-                            if rat_phys_reg == rob_phys_reg {
-                                rat_entry.valid = false;
+                                thread.rat.invalidate(arch_reg, rob_phys_reg);
+
+                                // update the architectural register
+                                let value = phys_reg_file.get_value(rob_phys_reg);
+                                arch_reg_file.set_value(sink.get_register(), value);
+
+                                phys_reg_file.deallocate(rob_phys_reg);
                             }
+                            _ => unreachable!(),
+                        }
+                    }
+
+                    if let Some(mut sb_pos) = rob_slot.sb_pos {
+                        let sb = &mut memory_subsytem.sbs[thread_id];
+                        for _ in 0..instr.mem_stores {
+                            sb.commit(sb_pos);
+                            sb_pos = sb.next_index(sb_pos);
+                        }
+                    }
 
-                            // update the architectural register
-                            let value = phys_reg_file.get_value(rob_phys_reg);
-                            arch_reg_file.set_value(sink.get_register(), value);
+                    if instr.is_branch() {
+                        if instr.is_conditional_branch() {
+                            let taken = rob_slot.branch_target_actual != rob_slot.pc + 1;
+                            self.branch_predictor.borrow_mut().update(rob_slot.pc, taken);
+                        } else if matches!(instr.opcode, Opcode::BX | Opcode::RET) {
+                            self.btb.borrow_mut().update(rob_slot.pc, rob_slot.branch_target_actual);
+                        }
 
-                            phys_reg_file.deallocate(rob_phys_reg);
+                        if rob_slot.branch_target_actual != rob_slot.branch_target_predicted {
+                            // the branch was not correctly predicted; the younger instructions
+                            // have already been squashed when the branch was executed.
+                            perf_counters.branch_miss_prediction_cnt += 1;
+                        } else {
+                            // the branch was correctly predicted
+                            perf_counters.branch_good_predictions_cnt += 1;
                         }
-                        _ => unreachable!(),
                     }
-                }
 
-                if rob_slot.sb_pos.is_some() {
-                    memory_subsytem.sb.commit(rob_slot.sb_pos.unwrap())
-                }
+                    let isb = instr.opcode == Opcode::ISB;
+                    if isb {
+                        isb_restarts.push((thread_id, rob_slot.pc + 1, rob_slot.ras_checkpoint));
+                    }
 
-                if instr.is_branch() {
-                    if rob_slot.branch_target_actual != rob_slot.branch_target_predicted {
-                        // the branch was not correctly predicted
-                        perf_counters.branch_miss_prediction_cnt += 1;
-                        bad_speculation = true;
-
-                        // re-steer the frontend
-                        arch_reg_file.set_value(PC, rob_slot.branch_target_actual as DWordType);
-                    } else {
-                        // the branch was correctly predicted
-                        perf_counters.branch_good_predictions_cnt += 1;
+                    thread.rob.seq_retired += 1;
+                    thread.rob.deallocate();
+                    retired_cnt += 1;
+
+                    if isb {
+                        // the younger instructions are fetched again
+                        break;
                     }
                 }
+            }
+        }
 
-                self.rob.seq_retired += 1;
-                self.rob.deallocate();
+        for (thread_id, rob_slot_index) in nuked_rob_slots {
+            self.nuke(thread_id, rob_slot_index);
+        }
 
-                if bad_speculation {
-                    break;
-                }
+        for (thread_id, pc, ras_checkpoint) in isb_restarts {
+            if self.trace.pipeline_flush {
+                println!("ISB flush before pc {}", pc);
             }
+            self.restart(thread_id, pc, &ras_checkpoint);
         }
+    }
 
-        if bad_speculation {
-            self.flush();
+    // Flushes the instructions of the thread starting from the load that caused a memory
+    // ordering violation and re-steers the frontend of the thread to the load. The other
+    // threads are not affected.
+    fn nuke(&mut self, thread_id: usize, rob_slot_index: u16) {
+        let rob_slot = self.threads[thread_id].rob.get_mut(rob_slot_index);
+        let pc = rob_slot.pc;
+        let ras_checkpoint = rob_slot.ras_checkpoint;
+
+        if self.trace.pipeline_flush {
+            println!("Memory order nuke [{}]", rob_slot.instr.as_ref().unwrap());
         }
+
+        self.perf_counters.borrow_mut().memory_order_nuke_cnt += 1;
+
+        // the load is the oldest instruction of the thread, so all its instructions are discarded
+        self.restart(thread_id, pc, &ras_checkpoint);
     }
 
-    fn flush(&mut self) {
-        let mut perf_counters = self.perf_counters.borrow_mut();
+    // Discards all instructions of the thread that are in flight and re-steers the frontend of
+    // the thread to the pc.
+    fn restart(&mut self, thread_id: usize, pc: usize, ras_checkpoint: &RASCheckpoint) {
+        let head = self.threads[thread_id].rob.head;
+        self.discard(thread_id, head);
+
+        let thread = &mut self.threads[thread_id];
+        thread.rob.flush();
+        thread.rat.flush();
+
+        thread.instr_queue.borrow_mut().flush();
+        thread.frontend_control.borrow_mut().exit = false;
+        thread.arch_reg_file.borrow_mut().set_value(PC, pc as DWordType);
+        thread.ras.borrow_mut().restore(ras_checkpoint);
+    }
+
+    // Squashes all instructions of the thread younger than the mispredicted branch with the given
+    // sequence number and re-steers the frontend of the thread to the actual branch target. Unlike
+    // a nuke, the instructions older than the branch remain in flight.
+    fn squash(&mut self, thread_id: usize, branch_seq: u64) {
+        let branch_rob_slot_index = self.threads[thread_id].rob.to_index(branch_seq);
 
         if self.trace.pipeline_flush {
-            println!("Pipeline flush");
+            let branch_rob_slot = self.threads[thread_id].rob.get_mut(branch_rob_slot_index);
+            println!("Squash after [{}]", branch_rob_slot.instr.as_ref().unwrap());
         }
 
+        self.discard(thread_id, branch_seq + 1);
+
+        let thread = &mut self.threads[thread_id];
+        thread.rob.squash(branch_seq);
+
+        thread.rat.restore(branch_rob_slot_index);
+        thread.rat.release_checkpoint(branch_rob_slot_index);
+
+        let branch_rob_slot = thread.rob.get_mut(branch_rob_slot_index);
+
+        // re-steer the frontend
+        thread.instr_queue.borrow_mut().flush();
+        thread.frontend_control.borrow_mut().exit = false;
+        thread.arch_reg_file.borrow_mut().set_value(PC, branch_rob_slot.branch_target_actual as DWordType);
+
+        // repair the RAS which was speculatively updated by the younger branches
+        thread.ras.borrow_mut().restore(&branch_rob_slot.ras_checkpoint);
+    }
+
+    // Releases the resources of the instructions of the thread starting at the given sequence
+    // number; from youngest to oldest so that the stores and loads are removed from the tail of
+    // the store buffer and load queue. The slots in the ROB itself are left to the caller.
+    fn discard(&mut self, thread_id: usize, from_seq: u64) {
+        let mut perf_counters = self.perf_counters.borrow_mut();
+        let mut memory_subsystem = self.memory_subsystem.borrow_mut();
+        let mut phys_reg_file = self.phys_reg_file.borrow_mut();
+        let thread = &mut self.threads[thread_id];
+
         perf_counters.pipeline_flushes += 1;
-        perf_counters.bad_speculation_cnt += self.rob.size() as u64;
-
-        self.instr_queue.borrow_mut().flush();
-        self.phys_reg_file.borrow_mut().flush();
-        self.eu_table.flush();
-        self.rob.flush();
-        self.rat.flush();
-        self.rs_table.flush();
-        self.memory_subsystem.borrow_mut().sb.flush();
+        perf_counters.bad_speculation_cnt += thread.rob.tail - from_seq;
+
+        for seq in (from_seq..thread.rob.tail).rev() {
+            let rob_slot_index = thread.rob.to_index(seq);
+            let rob_slot = thread.rob.get_mut(rob_slot_index);
+
+            if let Some(eu_index) = rob_slot.eu_index {
+                self.eu_table.deallocate(eu_index, rob_slot.rs_index.unwrap());
+            }
+
+            if let Some(rs_index) = rob_slot.rs_index {
+                self.rs_table.remove_ready(rs_index);
+                self.rs_table.deallocate(rs_index);
+            }
+
+            for phys_reg in rob_slot.sink_phys_regs.iter().flatten() {
+                phys_reg_file.deallocate(*phys_reg);
+                self.cdb_broadcast_buffer.retain(|broadcast| broadcast.phys_reg != *phys_reg);
+            }
+
+            // the second entry of a pair is at the tail; so it is deallocated first
+            let instr = rob_slot.instr.as_ref().unwrap();
+            if let Some(sb_pos) = rob_slot.sb_pos {
+                let sb = &mut memory_subsystem.sbs[thread_id];
+                if instr.mem_stores > 1 {
+                    sb.deallocate_tail(sb.next_index(sb_pos));
+                }
+                sb.deallocate_tail(sb_pos);
+            }
+
+            if let Some(lq_pos) = rob_slot.lq_pos {
+                memory_subsystem.cancel_load(thread_id as u8, rob_slot_index);
+                let lq = &mut memory_subsystem.lqs[thread_id];
+                if instr.mem_loads > 1 {
+                    lq.deallocate_tail(lq.next_index(lq_pos));
+                }
+                lq.deallocate_tail(lq_pos);
+            }
+
+            thread.rat.release_checkpoint(rob_slot_index);
+        }
     }
-}
\ No newline at end of file
+}
+
+// The atomic operation for the atomic instruction in the reservation station; see Instr::is_atomic
+// for the layout of the source operands.
+fn atomic_op(rs: &RS) -> AtomicOp {
+    let value = |source_index: usize| rs.source[source_index].value.unwrap();
+    match rs.opcode {
+        Opcode::LDXR => AtomicOp::LDXR,
+        Opcode::STXR => AtomicOp::STXR(value(1)),
+        Opcode::LDADD => AtomicOp::LDADD(value(1)),
+        Opcode::SWP => AtomicOp::SWP(value(1)),
+        Opcode::CAS => AtomicOp::CAS(value(1), value(2)),
+        _ => unreachable!("{:?} is not an atomic", rs.opcode),
+    }
+}
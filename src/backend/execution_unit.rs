@@ -1,18 +1,33 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Index;
 use std::rc::Rc;
 
 use crate::backend::physical_register::PhysRegFile;
 use crate::backend::reorder_buffer::ROBSlot;
 use crate::backend::reservation_station::RS;
-use crate::cpu::{CARRY_FLAG, CPUConfig, NEGATIVE_FLAG, OVERFLOW_FLAG, PerfCounters, ZERO_FLAG};
-use crate::instructions::instructions::{DWordType, Opcode, Operand};
+use crate::cpu::{CARRY_FLAG, CPSR, CPUConfig, Latency, NEGATIVE_FLAG, OVERFLOW_FLAG, PerfCounters, PortPerfCounters, PortType, ZERO_FLAG};
+use crate::instructions::instructions::{ConditionCode, DWordType, Instr, MAX_SOURCE_COUNT, Opcode, Operand, Shift};
 use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
 
-/// A single execution unit.
+/// An instruction that is in flight in an execution unit.
+pub(crate) struct EUSlot {
+    pub(crate) rs_index: u16,
+    pub(crate) cycles_remaining: u8,
+    pub(crate) pipelined: bool,
+}
+
+/// A single execution unit (port).
+///
+/// A pipelined instruction only occupies the unit for a single cycle, so multiple
+/// instructions can be in flight at the same time. A non pipelined instruction
+/// blocks the unit until it has completed.
 pub(crate) struct EU {
     pub(crate) index: u8,
-    pub(crate) rs_index: Option<u16>,
-    pub(crate) cycles_remaining: u8,
+    pub(crate) port_type: PortType,
+    pub(crate) in_flight: Vec<EUSlot>,
+    // the number of cycles before the unit can accept a new instruction
+    pub(crate) busy_cycles: u8,
     pub(crate) state: EUState,
     memory_subsystem: Rc<RefCell<MemorySubsystem>>,
     perf_counters: Rc<RefCell<PerfCounters>>,
@@ -20,178 +35,322 @@ pub(crate) struct EU {
     trace: bool,
 }
 
+pub(crate) fn port_type(opcode: Opcode) -> PortType {
+    match opcode {
+        Opcode::LDR |
+        Opcode::LDRB |
+        Opcode::LDRH |
+        Opcode::LDRSW |
+        Opcode::LDAR |
+        Opcode::LDP |
+        Opcode::LDXR |
+        Opcode::STXR |
+        Opcode::LDADD |
+        Opcode::SWP |
+        Opcode::CAS |
+        Opcode::CLREX => PortType::LOAD,
+        Opcode::STR |
+        Opcode::STRB |
+        Opcode::STRH |
+        Opcode::STP |
+        Opcode::STLR => PortType::STORE,
+        Opcode::MUL |
+        Opcode::SDIV |
+        Opcode::UDIV |
+        Opcode::MADD |
+        Opcode::MSUB |
+        Opcode::SMULL |
+        Opcode::UMULL |
+        Opcode::SMULH |
+        Opcode::UMULH => PortType::MULDIV,
+        Opcode::B |
+        Opcode::BX |
+        Opcode::BL |
+        Opcode::RET |
+        Opcode::CBZ |
+        Opcode::CBNZ |
+        Opcode::BCOND => PortType::BRANCH,
+        Opcode::ADD |
+        Opcode::SUB |
+        Opcode::RSB |
+        Opcode::ADR |
+        Opcode::NOP |
+        Opcode::PRINTR |
+        Opcode::MOV |
+        Opcode::EXIT |
+        Opcode::NEG |
+        Opcode::AND |
+        Opcode::ORR |
+        Opcode::EOR |
+        Opcode::MVN |
+        Opcode::CMP |
+        Opcode::CSEL |
+        Opcode::CSET |
+        Opcode::CSINC |
+        Opcode::CSNEG |
+        Opcode::CCMP |
+        Opcode::ADDS |
+        Opcode::SUBS |
+        Opcode::ANDS |
+        Opcode::NEGS |
+        Opcode::TST |
+        Opcode::CMN |
+        Opcode::LSL |
+        Opcode::LSR |
+        Opcode::ASR |
+        Opcode::ROR |
+        Opcode::CLZ |
+        Opcode::CLS |
+        Opcode::RBIT |
+        Opcode::REV |
+        Opcode::UBFX |
+        Opcode::SBFX |
+        Opcode::BFI |
+        Opcode::EXTR |
+        Opcode::MOVZ |
+        Opcode::MOVN |
+        Opcode::MOVK |
+        Opcode::DSB |
+        Opcode::DMB |
+        Opcode::ISB |
+        Opcode::MRS => PortType::ALU,
+    }
+}
+
+// The source values of an instruction as it uses them. The shifted register operand is shifted
+// and a 32 bit instruction only sees the lower 32 bits of its registers; the RS keeps the values
+// of the renamed registers.
+struct Operands {
+    values: [DWordType; MAX_SOURCE_COUNT as usize],
+    is_32bit: bool,
+}
+
+impl Operands {
+    fn new(rs: &RS, instr: &Instr) -> Operands {
+        let is_32bit = instr.is_32bit();
+        let mut values = [0; MAX_SOURCE_COUNT as usize];
+        for (value, source) in values.iter_mut().zip(&rs.source[..rs.source_cnt as usize]) {
+            *value = source.value.unwrap();
+            if is_32bit && matches!(source.operand, Some(Operand::Register(reg)) if reg != CPSR) {
+                *value &= 0xFFFF_FFFF;
+            }
+        }
+
+        if instr.shift != Shift::LSL(0) {
+            values[1] = if is_32bit { instr.shift.apply_32bit(values[1]) } else { instr.shift.apply(values[1]) };
+        }
+        Operands { values, is_32bit }
+    }
+
+    // Returns the value as a signed integer; the value of a 32 bit instruction is sign extended.
+    fn signed(&self, index: usize) -> i64 {
+        if self.is_32bit { self.values[index] as i32 as i64 } else { self.values[index] as i64 }
+    }
+
+    // Returns the value that is written to the destination register; a 32 bit instruction zero
+    // extends the lower 32 bits of its result.
+    fn result(&self, rd: DWordType) -> DWordType {
+        if self.is_32bit { rd & 0xFFFF_FFFF } else { rd }
+    }
+}
+
+impl Index<usize> for Operands {
+    type Output = DWordType;
+
+    fn index(&self, index: usize) -> &DWordType {
+        &self.values[index]
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub(crate) enum EUState {
     IDLE,
     EXECUTING,
-    COMPLETED,
 }
 
 #[allow(non_snake_case)]
 impl EU {
     fn reset(&mut self) {
-        self.rs_index = None;
-        self.cycles_remaining = 0;
+        self.in_flight.clear();
+        self.busy_cycles = 0;
         self.state = EUState::IDLE;
     }
 
-    pub fn cycle(&mut self,
-                 rs: &mut RS,
-                 rob_slot: &mut ROBSlot) {
-        debug_assert!(self.state == EUState::EXECUTING);
-        debug_assert!(self.cycles_remaining > 0);
-
-        self.cycles_remaining -= 1;
-        if self.cycles_remaining > 0 {
-            // the execution unit isn't finished with its work
-            return;
-        }
-        self.state = EUState::COMPLETED;
-        self.perf_counters.borrow_mut().execute_cnt += 1;
-
-        if self.trace {
-            let instr = rob_slot.instr.as_ref().unwrap();
-            println!("Executing {}", instr);
-        }
-
-        match rs.opcode {
-            Opcode::NOP => {}
-            Opcode::ADD => self.execute_ADD(rs),
-            Opcode::SUB => self.execute_SUB(rs),
-            Opcode::RSB => self.execute_RSB(rs),
-            Opcode::MUL => self.execute_MUL(rs),
-            Opcode::SDIV => self.execute_SDIV(rs),
-            Opcode::NEG => self.execute_NEG(rs),
-            Opcode::AND => self.execute_AND(rs),
-            Opcode::MOV => self.execute_MOV(rs),
-            Opcode::ADR => self.execute_ADR(rs, rob_slot),
-            Opcode::ORR => self.execute_ORR(rs),
-            Opcode::EOR => self.execute_EOR(rs),
-            Opcode::MVN => self.execute_MVN(rs),
-            Opcode::LDR => self.execute_LDR(rs),
-            Opcode::STR => self.execute_STR(rs, rob_slot),
-            Opcode::PRINTR => self.execute_PRINTR(rs, rob_slot),
-            Opcode::CMP => self.execute_CMP(rs, rob_slot),
-            Opcode::BEQ => self.execute_BEQ(rs, rob_slot),
-            Opcode::BNE => self.execute_BNE(rs, rob_slot),
-            Opcode::BLT => self.execute_BLT(rs, rob_slot),
-            Opcode::BLE => self.execute_BLE(rs, rob_slot),
-            Opcode::BGT => self.execute_BGT(rs, rob_slot),
-            Opcode::BGE => self.execute_BGE(rs, rob_slot),
-            Opcode::CBZ => self.execute_CBZ(rs, rob_slot),
-            Opcode::CBNZ => self.execute_CBNZ(rs, rob_slot),
-            Opcode::RET => self.execute_RET(rs, rob_slot),
-            Opcode::B => self.execute_B(rs, rob_slot),
-            Opcode::BX => self.execute_BX(rs, rob_slot),
-            Opcode::BL => self.execute_BL(rs, rob_slot),
-            Opcode::EXIT => {}
-            Opcode::DSB => {}
-        }
+    pub(crate) fn can_accept(&self) -> bool {
+        self.busy_cycles == 0
     }
 
-    fn execute_BEQ(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let target = rs.source[0].value.unwrap();
-        let cpsr = rs.source[1].value.unwrap();
-        let pc = rob_slot.pc as DWordType;
+    pub(crate) fn accept(&mut self, rs_index: u16, latency: Latency) {
+        debug_assert!(self.can_accept());
+        debug_assert!(latency.cycles > 0);
 
-        let zero_flag = (cpsr >> ZERO_FLAG) & 0x1;
-        let pc_update = if zero_flag == 1 { target } else { pc + 1 };
-        rob_slot.branch_target_actual = pc_update as usize;
+        self.in_flight.push(EUSlot { rs_index, cycles_remaining: latency.cycles, pipelined: latency.pipelined });
+        self.busy_cycles = if latency.pipelined { 1 } else { latency.cycles };
+        self.state = EUState::EXECUTING;
     }
 
-    fn execute_BNE(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let target = rs.source[0].value.unwrap();
-        let cpsr = rs.source[1].value.unwrap();
-        let pc = rob_slot.pc as DWordType;
+    /// Removes the instruction from the unit without completing it.
+    pub(crate) fn remove(&mut self, rs_index: u16) {
+        let pos = self.in_flight.iter().position(|slot| slot.rs_index == rs_index).unwrap();
+        let slot = self.in_flight.remove(pos);
+        if !slot.pipelined {
+            self.busy_cycles = 0;
+        }
 
-        let zero_flag = (cpsr >> ZERO_FLAG) & 0x1;
-        let pc_update = if zero_flag == 0 { target } else { pc + 1 };
-        rob_slot.branch_target_actual = pc_update as usize;
+        if self.in_flight.is_empty() {
+            self.reset();
+        }
     }
 
-    fn execute_BLT(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let target = rs.source[0].value.unwrap();
-        let cpsr = rs.source[1].value.unwrap();
-        let pc = rob_slot.pc as DWordType;
+    /// Advances all the instructions in flight by a single cycle.
+    pub(crate) fn cycle(&mut self) {
+        self.perf_counters.borrow_mut().ports[self.index as usize].busy_cnt += 1;
 
-        let negative_flag = (cpsr >> NEGATIVE_FLAG) & 0x1;
-        let overflow_flag = (cpsr >> OVERFLOW_FLAG) & 0x1;
+        if self.busy_cycles > 0 {
+            self.busy_cycles -= 1;
+        }
 
-        let pc_update = if negative_flag != overflow_flag { target } else { pc + 1 };
-        rob_slot.branch_target_actual = pc_update as usize;
+        for slot in &mut self.in_flight {
+            debug_assert!(slot.cycles_remaining > 0);
+            slot.cycles_remaining -= 1;
+        }
     }
 
-    fn execute_BLE(&mut self, rs: &RS, rob_slot: &mut ROBSlot) {
-        let target = rs.source[0].value.unwrap();
-        let cpsr = rs.source[1].value.unwrap();
-        let pc = rob_slot.pc as DWordType;
-
-        // Extract the zero flag (bit 30), negative flag (bit 31), and overflow flag (bit 28) from CPSR
-        let zero_flag = (cpsr >> ZERO_FLAG) & 0x1;
-        let negative_flag = (cpsr >> NEGATIVE_FLAG) & 0x1;
-        let overflow_flag = (cpsr >> OVERFLOW_FLAG) & 0x1;
-
-        let pc_update = if (zero_flag == 1) || (negative_flag != overflow_flag) {
-            target
-        } else {
-            pc + 1
-        };
-        rob_slot.branch_target_actual = pc_update as usize;
+    /// Removes an instruction that has finished its latency and returns its rs_index.
+    pub(crate) fn pop_completed(&mut self) -> Option<u16> {
+        let pos = self.in_flight.iter().position(|slot| slot.cycles_remaining == 0)?;
+        let slot = self.in_flight.remove(pos);
+        if self.in_flight.is_empty() {
+            self.state = EUState::IDLE;
+        }
+        Some(slot.rs_index)
     }
 
-    fn execute_BGT(&mut self, rs: &RS, rob_slot: &mut ROBSlot) {
-        let target = rs.source[0].value.unwrap();
-        let cpsr = rs.source[1].value.unwrap();
-        let pc = rob_slot.pc as DWordType;
+    pub fn execute(&mut self,
+                   rs: &mut RS,
+                   rob_slot: &mut ROBSlot) {
+        self.perf_counters.borrow_mut().execute_cnt += 1;
 
-        let zero_flag = (cpsr >> ZERO_FLAG) & 0x1;
-        let negative_flag = (cpsr >> NEGATIVE_FLAG) & 0x1;
-        let overflow_flag = (cpsr >> OVERFLOW_FLAG) & 0x1;
+        if self.trace {
+            let instr = rob_slot.instr.as_ref().unwrap();
+            println!("Executing {}", instr);
+        }
 
-        let pc_update = if (zero_flag == 0) && (negative_flag == overflow_flag) {
-            target
-        } else {
-            pc + 1
-        };
-        rob_slot.branch_target_actual = pc_update as usize;
+        let operands = Operands::new(rs, rob_slot.instr.as_ref().unwrap());
+
+        match rs.opcode {
+            Opcode::NOP => {}
+            Opcode::ADD => self.execute_ADD(rs, &operands),
+            Opcode::SUB => self.execute_SUB(rs, &operands),
+            Opcode::RSB => self.execute_RSB(rs, &operands),
+            Opcode::MUL => self.execute_MUL(rs, &operands),
+            Opcode::SDIV => self.execute_SDIV(rs, &operands),
+            Opcode::UDIV => self.execute_UDIV(rs, &operands),
+            Opcode::MADD |
+            Opcode::MSUB => self.execute_MADD(rs, &operands),
+            Opcode::SMULL => self.execute_SMULL(rs, &operands),
+            Opcode::UMULL => self.execute_UMULL(rs, &operands),
+            Opcode::SMULH => self.execute_SMULH(rs, &operands),
+            Opcode::UMULH => self.execute_UMULH(rs, &operands),
+            Opcode::NEG => self.execute_NEG(rs, &operands),
+            Opcode::AND => self.execute_AND(rs, &operands),
+            Opcode::MOV => self.execute_MOV(rs, &operands),
+            Opcode::ADR => self.execute_ADR(rs, rob_slot),
+            Opcode::ORR => self.execute_ORR(rs, &operands),
+            Opcode::EOR => self.execute_EOR(rs, &operands),
+            Opcode::MVN => self.execute_MVN(rs, &operands),
+            Opcode::LDR |
+            Opcode::LDRB |
+            Opcode::LDRH |
+            Opcode::LDRSW |
+            Opcode::LDAR |
+            Opcode::LDP |
+            Opcode::LDXR |
+            Opcode::STXR |
+            Opcode::LDADD |
+            Opcode::SWP |
+            Opcode::CAS => unreachable!("A load or atomic is performed by the memory subsystem"),
+            Opcode::CLREX => self.execute_CLREX(rs),
+            Opcode::STR |
+            Opcode::STRB |
+            Opcode::STRH |
+            Opcode::STP |
+            Opcode::STLR => self.execute_STR(rs, rob_slot),
+            Opcode::PRINTR => self.execute_PRINTR(rob_slot, &operands),
+            Opcode::MRS => self.execute_MRS(rs, &operands),
+            Opcode::CMP => self.execute_CMP(rs, rob_slot, &operands),
+            Opcode::CMN => self.execute_CMN(rs, &operands),
+            Opcode::TST => self.execute_TST(rs, &operands),
+            Opcode::ADDS => self.execute_ADDS(rs, &operands),
+            Opcode::SUBS => self.execute_SUBS(rs, &operands),
+            Opcode::ANDS => self.execute_ANDS(rs, &operands),
+            Opcode::NEGS => self.execute_NEGS(rs, &operands),
+            Opcode::LSL |
+            Opcode::LSR |
+            Opcode::ASR |
+            Opcode::ROR => self.execute_shift(rs, &operands),
+            Opcode::CLZ => self.execute_CLZ(rs, &operands),
+            Opcode::CLS => self.execute_CLS(rs, &operands),
+            Opcode::RBIT => self.execute_RBIT(rs, &operands),
+            Opcode::REV => self.execute_REV(rs, &operands),
+            Opcode::UBFX => self.execute_UBFX(rs, &operands),
+            Opcode::SBFX => self.execute_SBFX(rs, &operands),
+            Opcode::BFI => self.execute_BFI(rs, &operands),
+            Opcode::EXTR => self.execute_EXTR(rs, &operands),
+            Opcode::MOVZ |
+            Opcode::MOVN => self.execute_MOVZ(rs, &operands),
+            Opcode::MOVK => self.execute_MOVK(rs, &operands),
+            Opcode::CSEL |
+            Opcode::CSINC |
+            Opcode::CSNEG => self.execute_CSEL(rs, rob_slot, &operands),
+            Opcode::CSET => self.execute_CSET(rs, rob_slot, &operands),
+            Opcode::CCMP => self.execute_CCMP(rs, rob_slot, &operands),
+            Opcode::BCOND => self.execute_BCOND(rob_slot, &operands),
+            Opcode::CBZ => self.execute_CBZ(rob_slot, &operands),
+            Opcode::CBNZ => self.execute_CBNZ(rob_slot, &operands),
+            Opcode::RET => self.execute_RET(rob_slot, &operands),
+            Opcode::B => self.execute_B(rob_slot, &operands),
+            Opcode::BX => self.execute_BX(rob_slot, &operands),
+            Opcode::BL => self.execute_BL(rs, rob_slot, &operands),
+            Opcode::EXIT => {}
+            Opcode::DSB => {}
+            // the ordering of a DMB is enforced by the backend and an ISB flushes the frontend when it retires
+            Opcode::DMB => {}
+            Opcode::ISB => {}
+        }
     }
 
-    fn execute_BGE(&mut self, rs: &RS, rob_slot: &mut ROBSlot) {
-        let target = rs.source[0].value.unwrap();
-        let cpsr = rs.source[1].value.unwrap();
+    // Branches to the target if the condition holds for the flags.
+    fn execute_BCOND(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let instr = rob_slot.instr.as_ref().unwrap();
+        let target = operands[0];
+        let cpsr = operands[1];
         let pc = rob_slot.pc as DWordType;
 
-        let negative_flag = (cpsr >> NEGATIVE_FLAG) & 0x1;
-        let overflow_flag = (cpsr >> OVERFLOW_FLAG) & 0x1;
-
-        let pc_update = if negative_flag == overflow_flag {
-            target
-        } else {
-            pc + 1
-        };
+        let pc_update = if condition_holds(instr.condition_code, cpsr) { target } else { pc + 1 };
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_CBZ(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let reg_value = rs.source[0].value.unwrap();
-        let branch = rs.source[1].value.unwrap();
+    fn execute_CBZ(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let reg_value = operands[0];
+        let branch = operands[1];
         let pc = rob_slot.pc as DWordType;
 
         let pc_update = if reg_value == 0 { branch } else { pc + 1 };
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_CBNZ(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let reg_value = rs.source[0].value.unwrap();
-        let branch = rs.source[1].value.unwrap();
+    fn execute_CBNZ(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let reg_value = operands[0];
+        let branch = operands[1];
         let pc = rob_slot.pc as DWordType;
 
         let pc_update = if reg_value != 0 { branch } else { pc + 1 };
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_BL(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let branch_target = rs.source[0].value.unwrap();
+    fn execute_BL(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let branch_target = operands[0];
 
         let pc_update = branch_target;
 
@@ -202,100 +361,157 @@ impl EU {
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_BX(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
+    fn execute_BX(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
         // update the PC
-        let branch_target = rs.source[0].value.unwrap() as i64;
+        let branch_target = operands[0] as i64;
         let pc_update = branch_target;
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_B(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
+    fn execute_B(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
         // update the PC
-        let branch_target = rs.source[0].value.unwrap();
+        let branch_target = operands[0];
         let pc_update = branch_target;
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_RET(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
+    fn execute_RET(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
         // update the PC
-        let branch_target = rs.source[0].value.unwrap();
+        let branch_target = operands[0];
         let pc_update = branch_target;
         rob_slot.branch_target_actual = pc_update as usize;
     }
 
-    fn execute_CMP(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
-        let cprs_value = rs.source[2].value.unwrap();
+    fn execute_CMP(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
 
-        // Perform the comparison: rn - operand2
-        let result = rn.wrapping_sub(operand2);
+        let (_, new_cprs_value) = subtract_with_flags(rn, operand2, cprs_value, operands.is_32bit);
 
-        // Update the CPSR flags based on the result
-        let zero_flag = result == 0;
-        let negative_flag = (result & (1 << 63)) != 0;
-        let carry_flag = (rn as u128).wrapping_sub(operand2 as u128) > (rn as u128); // Checking for borrow
-        let overflow_flag = (((rn ^ operand2) & (rn ^ result)) >> 63) != 0;
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, new_cprs_value);
+    }
 
-        let mut new_cprs_value = cprs_value;
-        if zero_flag {
-            new_cprs_value |= 1 << ZERO_FLAG;
-        } else {
-            new_cprs_value &= !(1 << ZERO_FLAG);
-        }
+    fn execute_CMN(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
 
-        if negative_flag {
-            new_cprs_value |= 1 << NEGATIVE_FLAG;
-        } else {
-            new_cprs_value &= !(1 << NEGATIVE_FLAG);
-        }
+        let (_, new_cprs_value) = add_with_flags(rn, operand2, cprs_value, operands.is_32bit);
 
-        if carry_flag {
-            new_cprs_value |= 1 << CARRY_FLAG;
-        } else {
-            new_cprs_value &= !(1 << CARRY_FLAG);
-        }
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, new_cprs_value);
+    }
+
+    fn execute_TST(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
+
+        let new_cprs_value = logical_flags(rn & operand2, cprs_value, operands.is_32bit);
+
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, new_cprs_value);
+    }
 
-        if overflow_flag {
-            new_cprs_value |= 1 << OVERFLOW_FLAG;
+    // Compares like CMP if the condition holds; otherwise the flags are set to the nzcv immediate.
+    fn execute_CCMP(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let instr = rob_slot.instr.as_ref().unwrap();
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
+        let nzcv = operands[3];
+
+        let new_cprs_value = if condition_holds(instr.condition_code, cprs_value) {
+            subtract_with_flags(rn, operand2, cprs_value, operands.is_32bit).1
         } else {
-            new_cprs_value &= !(1 << OVERFLOW_FLAG);
-        }
+            // the nzcv bits map onto the flags in the same order
+            (cprs_value & !(0xF << OVERFLOW_FLAG)) | (nzcv << OVERFLOW_FLAG)
+        };
 
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, new_cprs_value);
     }
 
-    fn execute_PRINTR(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
+    // Selects the first register if the condition holds; otherwise the second register, which is
+    // incremented by CSINC and negated by CSNEG.
+    fn execute_CSEL(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot, operands: &Operands) {
         let instr = rob_slot.instr.as_ref().unwrap();
+        let rn = operands[0];
+        let rm = operands[1];
+        let cprs_value = operands[2];
 
-        println!("PRINTR {}={}", Operand::Register(instr.source[0].get_register()), rs.source[0].value.unwrap());
+        let value = if condition_holds(instr.condition_code, cprs_value) {
+            rn
+        } else {
+            match rs.opcode {
+                Opcode::CSINC => rm.wrapping_add(1),
+                Opcode::CSNEG => rm.wrapping_neg(),
+                _ => rm,
+            }
+        };
+
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(value));
+    }
+
+    fn execute_CSET(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let instr = rob_slot.instr.as_ref().unwrap();
+        let cprs_value = operands[0];
+
+        let value = condition_holds(instr.condition_code, cprs_value) as DWordType;
+
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(value));
+    }
+
+    fn execute_PRINTR(&mut self, rob_slot: &mut ROBSlot, operands: &Operands) {
+        let instr = rob_slot.instr.as_ref().unwrap();
+
+        println!("PRINTR {}={}", Operand::Register(instr.source[0].get_register()), operands[0]);
     }
 
     fn execute_STR(&mut self, rs: &mut RS, rob_slot: &mut ROBSlot) {
-        let value = rs.source[0].value.unwrap();
-        let address = rs.source[1].value.unwrap();
+        let instr = rob_slot.instr.as_ref().unwrap();
+        let (address, writeback) = rs.agen(instr);
 
+        // a pair stores the second register right after the first one in the next store buffer entry
         let mut memory_subsystem = self.memory_subsystem.borrow_mut();
-        memory_subsystem.sb.store(rob_slot.sb_pos.unwrap(), address, value);
+        let mut sb_pos = rob_slot.sb_pos.unwrap();
+        for k in 0..instr.mem_stores as usize {
+            let value = rs.source[k].value.unwrap();
+            let addr = address.wrapping_add((k * instr.mem_size as usize) as DWordType);
+            memory_subsystem.store(rs.thread_id, sb_pos, addr, instr.mem_size, value);
+            sb_pos = memory_subsystem.sbs[rs.thread_id as usize].next_index(sb_pos);
+        }
+
+        // the base register of a pre- or post-indexed store
+        if instr.addressing_mode.has_writeback() {
+            let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+            self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, writeback);
+        }
+    }
+
+    fn execute_CLREX(&mut self, rs: &mut RS) {
+        self.memory_subsystem.borrow_mut().clear_exclusive(rs.thread_id);
     }
 
-    fn execute_LDR(&mut self, rs: &mut RS) {
-        let memory_subsystem = self.memory_subsystem.borrow_mut();
-        let address = rs.source[0].value.unwrap() as usize;
-        let value = memory_subsystem.memory[address];
+    fn execute_MVN(&mut self, rs: &mut RS, operands: &Operands) {
+        let value = !operands[0];
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
-        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, value);
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(value));
     }
 
-    fn execute_MVN(&mut self, rs: &mut RS) {
-        let value = !rs.source[0].value.unwrap();
+    fn execute_MOV(&mut self, rs: &mut RS, operands: &Operands) {
+        let value = operands[0];
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
-        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, value);
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(value));
     }
 
-    fn execute_MOV(&mut self, rs: &mut RS) {
-        let value = rs.source[0].value.unwrap();
+    // The value of the system register is read when the operands are renamed.
+    fn execute_MRS(&mut self, rs: &mut RS, operands: &Operands) {
+        let value = operands[0];
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, value);
     }
@@ -304,83 +520,289 @@ impl EU {
         panic!("ADR is not implemented");
     }
 
-    fn execute_EOR(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
+    fn execute_EOR(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
         let rd = rn ^ operand2;
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
-        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
     }
 
-    fn execute_ORR(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
+    fn execute_ORR(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
         let rd = rn | operand2;
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
-        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
     }
 
-    fn execute_AND(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
+    fn execute_AND(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
         let rd = rn & operand2;
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
-        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
     }
 
-    fn execute_NEG(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
+    fn execute_NEG(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
         let rd = rn.wrapping_neg();
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_SDIV(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands.signed(0);
+        let operand2 = operands.signed(1);
+        // like on ARM, a division by zero gives zero and the overflowing MIN / -1 gives MIN
+        let rd = if operand2 == 0 { 0 } else { rn.wrapping_div(operand2) as DWordType };
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_UDIV(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        // like on ARM, a division by zero gives zero
+        let rd = rn.checked_div(operand2).unwrap_or(0);
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_MUL(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let rd = rn.wrapping_mul(operand2);
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_MADD(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let rm = operands[1];
+        let ra = operands[2];
+        let product = rn.wrapping_mul(rm);
+        let rd = if rs.opcode == Opcode::MSUB {
+            ra.wrapping_sub(product)
+        } else {
+            ra.wrapping_add(product)
+        };
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_SMULL(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0] as i32 as i64;
+        let rm = operands[1] as i32 as i64;
+        let rd = (rn * rm) as DWordType;
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
     }
 
-    fn execute_SDIV(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
-        let rd = rn / operand2;
+    fn execute_UMULL(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0] as u32 as DWordType;
+        let rm = operands[1] as u32 as DWordType;
+        let rd = rn * rm;
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
     }
 
-    fn execute_MUL(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
-        let rd = rn.wrapping_mul(operand2);
+    fn execute_SMULH(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0] as i64 as i128;
+        let rm = operands[1] as i64 as i128;
+        // the upper 64 bits of the 128 bit product
+        let rd = ((rn * rm) >> 64) as DWordType;
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
     }
 
-    fn execute_SUB(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
-        let rd = rn.wrapping_sub(operand2);
+    fn execute_UMULH(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0] as u128;
+        let rm = operands[1] as u128;
+        // the upper 64 bits of the 128 bit product
+        let rd = ((rn * rm) >> 64) as DWordType;
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
     }
 
-    fn execute_RSB(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
+    fn execute_SUB(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let rd = rn.wrapping_sub(operand2);
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_RSB(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
         let rd = operand2.wrapping_sub(rn);
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
     }
 
-    fn execute_ADD(&mut self, rs: &mut RS) {
-        let rn = rs.source[0].value.unwrap();
-        let operand2 = rs.source[1].value.unwrap();
+    fn execute_ADD(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
         let rd = rn.wrapping_add(operand2);
         let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_ADDS(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
+        let (rd, new_cprs_value) = add_with_flags(rn, operand2, cprs_value, operands.is_32bit);
+        self.write_with_flags(rs, operands, rd, new_cprs_value);
+    }
+
+    fn execute_SUBS(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
+        let (rd, new_cprs_value) = subtract_with_flags(rn, operand2, cprs_value, operands.is_32bit);
+        self.write_with_flags(rs, operands, rd, new_cprs_value);
+    }
+
+    fn execute_ANDS(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let operand2 = operands[1];
+        let cprs_value = operands[2];
+        let rd = rn & operand2;
+        let new_cprs_value = logical_flags(rd, cprs_value, operands.is_32bit);
+        self.write_with_flags(rs, operands, rd, new_cprs_value);
+    }
+
+    fn execute_NEGS(&mut self, rs: &mut RS, operands: &Operands) {
+        let rm = operands[0];
+        let cprs_value = operands[1];
+        // NEGS is an alias for SUBS with the zero register as first operand
+        let (rd, new_cprs_value) = subtract_with_flags(0, rm, cprs_value, operands.is_32bit);
+        self.write_with_flags(rs, operands, rd, new_cprs_value);
+    }
+
+    fn execute_shift(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        // like on ARM, a register amount is taken modulo the register size
+        let size = if operands.is_32bit { 32 } else { 64 };
+        let amount = (operands[1] % size) as u8;
+        let shift = match rs.opcode {
+            Opcode::LSL => Shift::LSL(amount),
+            Opcode::LSR => Shift::LSR(amount),
+            Opcode::ASR => Shift::ASR(amount),
+            Opcode::ROR => Shift::ROR(amount),
+            _ => unreachable!(),
+        };
+        let rd = if operands.is_32bit { shift.apply_32bit(rn) } else { shift.apply(rn) };
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_CLZ(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let rd = rn.leading_zeros() as DWordType;
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
         self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
     }
+
+    fn execute_CLS(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        // the number of bits below the sign bit that are equal to it; the lowest bit of the
+        // difference mask is set so that 0 and -1 give 63
+        let rd = ((rn ^ (rn << 1)) | 1).leading_zeros() as DWordType;
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_RBIT(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let rd = rn.reverse_bits();
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_REV(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let rd = rn.swap_bytes();
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_UBFX(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let lsb = operands[1];
+        let width = operands[2];
+        let rd = (rn >> lsb) & bitfield_mask(width);
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_SBFX(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let lsb = operands[1];
+        let width = operands[2];
+        // the field is moved to the top, so the arithmetic shift back sign extends it
+        let rd = (((rn << (64 - lsb - width)) as i64) >> (64 - width)) as DWordType;
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_BFI(&mut self, rs: &mut RS, operands: &Operands) {
+        let rd_old = operands[0];
+        let rn = operands[1];
+        let lsb = operands[2];
+        let width = operands[3];
+        let mask = bitfield_mask(width) << lsb;
+        let rd = (rd_old & !mask) | ((rn << lsb) & mask);
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_EXTR(&mut self, rs: &mut RS, operands: &Operands) {
+        let rn = operands[0];
+        let rm = operands[1];
+        let lsb = operands[2];
+        // the lower 64 bits of rn:rm shifted right by lsb
+        let rd = if lsb == 0 { rm } else { (rm >> lsb) | (rn << (64 - lsb)) };
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, rd);
+    }
+
+    fn execute_MOVZ(&mut self, rs: &mut RS, operands: &Operands) {
+        let value = operands[0];
+        let shift = operands[1];
+        let rd = if rs.opcode == Opcode::MOVN {
+            !(value << shift)
+        } else {
+            value << shift
+        };
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    fn execute_MOVK(&mut self, rs: &mut RS, operands: &Operands) {
+        let rd_old = operands[0];
+        let value = operands[1];
+        let shift = operands[2];
+        let rd = (rd_old & !(0xFFFF << shift)) | (value << shift);
+        let dst_phys_reg = rs.sink[0].phys_reg.unwrap();
+        self.phys_reg_file.borrow_mut().set_value(dst_phys_reg, operands.result(rd));
+    }
+
+    // Writes the result to the first sink and the flags to the CPSR in the second sink.
+    fn write_with_flags(&mut self, rs: &RS, operands: &Operands, rd: DWordType, cprs_value: DWordType) {
+        let mut phys_reg_file = self.phys_reg_file.borrow_mut();
+        phys_reg_file.set_value(rs.sink[0].phys_reg.unwrap(), operands.result(rd));
+        phys_reg_file.set_value(rs.sink[1].phys_reg.unwrap(), cprs_value);
+    }
 }
 
 /// The table containing all execution units of a CPU core.
 pub(crate) struct EUTable {
     pub(crate) capacity: u8,
-    idle_stack: Vec<u8>,
     array: Vec<EU>,
+    latencies: HashMap<Opcode, Latency>,
 }
 
 impl EUTable {
@@ -390,67 +812,158 @@ impl EUTable {
         phys_reg_file: &Rc<RefCell<PhysRegFile>>,
         perf_counters: &Rc<RefCell<PerfCounters>>,
     ) -> EUTable {
-        let capacity = cpu_config.eu_count;
-        let mut free_stack = Vec::with_capacity(capacity as usize);
+        let ports = &cpu_config.ports;
+        let port_types = [
+            (PortType::ALU, ports.alu),
+            (PortType::LOAD, ports.load),
+            (PortType::STORE, ports.store),
+            (PortType::BRANCH, ports.branch),
+            (PortType::MULDIV, ports.muldiv),
+        ];
+
+        for (port_type, count) in port_types {
+            assert!(count > 0, "EUTable: the number of {:?} ports should be larger than 0", port_type);
+        }
+
+        for (opcode, latency) in &cpu_config.latencies {
+            assert!(latency.cycles > 0, "EUTable: the latency of {:?} should be at least 1 cycle", opcode);
+        }
+
+        let capacity = port_types.iter().map(|(_, count)| count).sum();
         let mut array = Vec::with_capacity(capacity as usize);
-        for i in 0..capacity {
-            array.push(EU {
-                index: i,
-                cycles_remaining: 0,
-                rs_index: None,
-                state: EUState::IDLE,
-                trace: cpu_config.trace.execute,
-                memory_subsystem: Rc::clone(memory_subsystem),
-                perf_counters: Rc::clone(perf_counters),
-                phys_reg_file: Rc::clone(phys_reg_file),
-            });
-            free_stack.push(i);
+        let mut port_perf_counters = Vec::with_capacity(capacity as usize);
+        for (port_type, count) in port_types {
+            for _ in 0..count {
+                array.push(EU {
+                    index: array.len() as u8,
+                    port_type,
+                    in_flight: Vec::new(),
+                    busy_cycles: 0,
+                    state: EUState::IDLE,
+                    trace: cpu_config.trace.execute,
+                    memory_subsystem: Rc::clone(memory_subsystem),
+                    perf_counters: Rc::clone(perf_counters),
+                    phys_reg_file: Rc::clone(phys_reg_file),
+                });
+                port_perf_counters.push(PortPerfCounters { port_type, dispatch_cnt: 0, busy_cnt: 0 });
+            }
         }
+        perf_counters.borrow_mut().ports = port_perf_counters;
 
         EUTable {
             capacity,
             array,
-            idle_stack: free_stack,
+            latencies: cpu_config.latencies.clone(),
         }
     }
 
-    pub(crate) fn flush(&mut self) {
-        self.idle_stack.clear();
-        for k in 0..self.capacity {
-            self.idle_stack.push(k);
-            self.array.get_mut(k as usize).unwrap().reset();
-        }
+    pub(crate) fn latency(&self, opcode: Opcode) -> Latency {
+        self.latencies.get(&opcode).copied().unwrap_or_default()
     }
 
-    pub(crate) fn has_idle(&self) -> bool {
-        return !self.idle_stack.is_empty();
+    /// Checks if there is a port that can accept an instruction with the given opcode.
+    pub(crate) fn has_idle(&self, opcode: Opcode) -> bool {
+        let port_type = port_type(opcode);
+        self.array.iter().any(|eu| eu.port_type == port_type && eu.can_accept())
     }
 
     pub(crate) fn get_mut(&mut self, eu_index: u8) -> &mut EU {
         self.array.get_mut(eu_index as usize).unwrap()
     }
 
-    pub(crate) fn allocate(&mut self) -> u8 {
-        if let Some(last_element) = self.idle_stack.pop() {
-            let eu = self.array.get_mut(last_element as usize).unwrap();
-            debug_assert!(eu.state == EUState::IDLE);
-            debug_assert!(eu.rs_index.is_none());
-            debug_assert!(eu.cycles_remaining == 0);
-
-            eu.state = EUState::EXECUTING;
-            return last_element;
+    /// Dispatches the instruction with the given opcode to an eligible port that can accept it.
+    pub(crate) fn allocate(&mut self, rs_index: u16, opcode: Opcode) -> u8 {
+        let latency = self.latency(opcode);
+        let port_type = port_type(opcode);
+        if let Some(eu) = self.array.iter_mut().find(|eu| eu.port_type == port_type && eu.can_accept()) {
+            eu.accept(rs_index, latency);
+            return eu.index;
         } else {
-            panic!("No free PhysReg")
+            panic!("No idle EU")
         }
     }
 
-    pub(crate) fn deallocate(&mut self, eu_index: u8) {
+    /// Removes an instruction that is in flight; e.g. because it got squashed.
+    pub(crate) fn deallocate(&mut self, eu_index: u8, rs_index: u16) {
         let eu = self.array.get_mut(eu_index as usize).unwrap();
-        debug_assert!(eu.state == EUState::EXECUTING || eu.state == EUState::COMPLETED);
-        debug_assert!(eu.rs_index.is_some());
-        debug_assert!(!self.idle_stack.contains(&eu_index));
+        debug_assert!(eu.state == EUState::EXECUTING);
+
+        eu.remove(rs_index);
+    }
+}
+
+// Returns rn + operand2 and the CPSR with the flags of the addition. The operands of a 32 bit
+// addition are moved into the upper half, so the carry and the sign end up in bit 63.
+fn add_with_flags(rn: DWordType, operand2: DWordType, cprs_value: DWordType, is_32bit: bool) -> (DWordType, DWordType) {
+    let half = if is_32bit { 32 } else { 0 };
+    let (rn, operand2) = (rn << half, operand2 << half);
+    let (result, carry_flag) = rn.overflowing_add(operand2);
+    let overflow_flag = (((rn ^ result) & (operand2 ^ result)) >> 63) != 0;
+    (result >> half, set_flags(result, carry_flag, overflow_flag, cprs_value))
+}
+
+// Returns rn - operand2 and the CPSR with the flags of the subtraction; a 32 bit subtraction is
+// done in the upper half like the addition.
+fn subtract_with_flags(rn: DWordType, operand2: DWordType, cprs_value: DWordType, is_32bit: bool) -> (DWordType, DWordType) {
+    let half = if is_32bit { 32 } else { 0 };
+    let (rn, operand2) = (rn << half, operand2 << half);
+    let result = rn.wrapping_sub(operand2);
+    // like on ARM, the carry is set if the subtraction doesn't borrow
+    let carry_flag = rn >= operand2;
+    let overflow_flag = (((rn ^ operand2) & (rn ^ result)) >> 63) != 0;
+    (result >> half, set_flags(result, carry_flag, overflow_flag, cprs_value))
+}
+
+// Returns the CPSR with the flags of a logical operation; C and V are cleared.
+fn logical_flags(result: DWordType, cprs_value: DWordType, is_32bit: bool) -> DWordType {
+    let half = if is_32bit { 32 } else { 0 };
+    set_flags(result << half, false, false, cprs_value)
+}
+
+// Returns the CPSR with the NZCV flags updated; the other bits are left alone.
+fn set_flags(result: DWordType, carry_flag: bool, overflow_flag: bool, cprs_value: DWordType) -> DWordType {
+    let zero_flag = result == 0;
+    let negative_flag = (result & (1 << 63)) != 0;
+
+    let mut new_cprs_value = cprs_value;
+    for (flag, bit) in [(zero_flag, ZERO_FLAG), (negative_flag, NEGATIVE_FLAG), (carry_flag, CARRY_FLAG), (overflow_flag, OVERFLOW_FLAG)] {
+        if flag {
+            new_cprs_value |= 1 << bit;
+        } else {
+            new_cprs_value &= !(1 << bit);
+        }
+    }
+    new_cprs_value
+}
+
+// Returns a mask with the lowest width bits set; the width is 1 to 64.
+fn bitfield_mask(width: DWordType) -> DWordType {
+    DWordType::MAX >> (64 - width)
+}
 
-        eu.reset();
-        self.idle_stack.push(eu_index);
+// True if the condition holds for the flags in the CPSR.
+fn condition_holds(condition_code: ConditionCode, cprs_value: DWordType) -> bool {
+    let flag = |bit: u8| (cprs_value >> bit) & 0x1 == 1;
+    let n = flag(NEGATIVE_FLAG);
+    let z = flag(ZERO_FLAG);
+    let c = flag(CARRY_FLAG);
+    let v = flag(OVERFLOW_FLAG);
+
+    match condition_code {
+        ConditionCode::EQ => z,
+        ConditionCode::NE => !z,
+        ConditionCode::CS => c,
+        ConditionCode::CC => !c,
+        ConditionCode::MI => n,
+        ConditionCode::PL => !n,
+        ConditionCode::VS => v,
+        ConditionCode::VC => !v,
+        ConditionCode::HI => c && !z,
+        ConditionCode::LS => !c || z,
+        ConditionCode::GE => n == v,
+        ConditionCode::LT => n != v,
+        ConditionCode::GT => !z && n == v,
+        ConditionCode::LE => z || n != v,
+        ConditionCode::AL => true,
     }
 }
@@ -22,8 +22,10 @@ impl PhysRegEntry {
 
 pub(crate) struct PhysRegFile {
     free_stack: Vec<u16>,
-    count: u16,
     entries: Vec<PhysRegEntry>,
+    // the sink of the zero register; it is never allocated, always has the value 0 and writes
+    // to it are discarded.
+    pub(crate) zero_reg: RegisterType,
 }
 
 impl PhysRegFile {
@@ -38,8 +40,13 @@ impl PhysRegFile {
             });
             free_stack.push(count - 1 - i);
         }
+        entries.push(PhysRegEntry {
+            value: 0,
+            has_value: true,
+            state: PhysRegEntryState::BUSY,
+        });
 
-        PhysRegFile { count, entries, free_stack }
+        PhysRegFile { entries, free_stack, zero_reg: count }
     }
 
     pub(crate) fn get(&self, reg: RegisterType) -> &PhysRegEntry {
@@ -55,6 +62,10 @@ impl PhysRegFile {
     }
 
     pub(crate) fn set_value(&mut self, reg: RegisterType, value: DWordType) {
+        if reg == self.zero_reg {
+            return;
+        }
+
         let entry = self.get_mut(reg);
         debug_assert!(!entry.has_value);
         entry.has_value = true;
@@ -80,20 +91,13 @@ impl PhysRegFile {
         }
     }
 
-    pub(crate) fn flush(&mut self) {
-        self.free_stack.clear();
-
-        for i in 0..self.count {
-            let entry = &mut self.entries[i as usize];
-
-            self.free_stack.push(i);
-            entry.reset();
-        }
-    }
-
     pub(crate) fn deallocate(&mut self, reg: RegisterType) {
         // println!("Phys Register: deallocate {}",reg);
 
+        if reg == self.zero_reg {
+            return;
+        }
+
         debug_assert!(!self.free_stack.contains(&reg), "Phys register {} can't be deallocated while it is also on the free stack", reg);
 
         let entry = self.get_mut(reg);
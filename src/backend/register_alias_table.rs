@@ -1,5 +1,6 @@
 use crate::instructions::instructions::RegisterType;
 
+#[derive(Clone, Copy)]
 pub(crate) struct RATEntry {
     // the physical register in the arch register to phys register mapping
     pub(crate) phys_reg: RegisterType,
@@ -11,17 +12,29 @@ pub(crate) struct RATEntry {
 /// renaming process. The RAT entry for a given architectural register
 /// points to the physical register to use. As long as such a entry
 /// exists, it should be used.
+///
+/// For every branch a checkpoint of the table is made after the branch is renamed.
+/// When the branch turns out to be mispredicted, the table is restored from the
+/// checkpoint so that the mappings of the younger instructions are discarded.
 pub(crate) struct RAT {
     pub(crate) table: Vec<RATEntry>,
+    // the checkpoints indexed by the rob slot index of the branch.
+    checkpoints: Vec<Option<Vec<RATEntry>>>,
 }
 
 impl RAT {
-    pub(crate) fn new(phys_reg_count: u16) -> Self {
+    pub(crate) fn new(phys_reg_count: u16, rob_capacity: u16) -> Self {
         let mut table = Vec::with_capacity(phys_reg_count as usize);
         for _ in 0..phys_reg_count {
             table.push(RATEntry { phys_reg: 0, valid: false });
         }
-        Self { table }
+
+        let mut checkpoints = Vec::with_capacity(rob_capacity as usize);
+        for _ in 0..rob_capacity {
+            checkpoints.push(None);
+        }
+
+        Self { table, checkpoints }
     }
 
     pub(crate) fn flush(&mut self) {
@@ -29,6 +42,10 @@ impl RAT {
             let option = self.table.get_mut(k).unwrap();
             option.valid = false;
         }
+
+        for checkpoint in &mut self.checkpoints {
+            *checkpoint = None;
+        }
     }
 
     pub(crate) fn get(&self, arch_reg: RegisterType) -> &RATEntry {
@@ -38,4 +55,40 @@ impl RAT {
     pub(crate) fn get_mut(&mut self, arch_reg: RegisterType) -> &mut RATEntry {
         return self.table.get_mut(arch_reg as usize).unwrap();
     }
+
+    // Invalidates the mapping of the arch_reg to the phys_reg when the instruction that wrote
+    // the phys_reg retires. From then on, the value is found in the architectural register file.
+    // The checkpoints are updated as well because a checkpoint can be restored after the
+    // instruction retired.
+    pub(crate) fn invalidate(&mut self, arch_reg: RegisterType, phys_reg: RegisterType) {
+        let entry = self.get_mut(arch_reg);
+        debug_assert!(entry.valid);
+
+        // only when the physical register on the rat is the same as the physical register used for that
+        // instruction, the rat entry should be invalidated
+        if entry.phys_reg == phys_reg {
+            entry.valid = false;
+        }
+
+        for checkpoint in self.checkpoints.iter_mut().flatten() {
+            let entry = &mut checkpoint[arch_reg as usize];
+            if entry.valid && entry.phys_reg == phys_reg {
+                entry.valid = false;
+            }
+        }
+    }
+
+    pub(crate) fn checkpoint(&mut self, rob_slot_index: u16) {
+        debug_assert!(self.checkpoints[rob_slot_index as usize].is_none());
+        self.checkpoints[rob_slot_index as usize] = Some(self.table.clone());
+    }
+
+    pub(crate) fn restore(&mut self, rob_slot_index: u16) {
+        let checkpoint = self.checkpoints[rob_slot_index as usize].as_ref().unwrap();
+        self.table.copy_from_slice(checkpoint);
+    }
+
+    pub(crate) fn release_checkpoint(&mut self, rob_slot_index: u16) {
+        self.checkpoints[rob_slot_index as usize] = None;
+    }
 }
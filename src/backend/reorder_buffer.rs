@@ -1,5 +1,6 @@
 use std::rc::Rc;
 
+use crate::frontend::return_address_stack::RASCheckpoint;
 use crate::instructions::instructions::{Instr, MAX_SINK_COUNT, RegisterType};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -24,7 +25,11 @@ pub(crate) struct ROBSlot {
     pub(crate) sink_phys_regs: [Option<RegisterType>; MAX_SINK_COUNT as usize],
     pub(crate) branch_target_predicted: usize,
     pub(crate) branch_target_actual: usize,
+    // the state of the RAS after the branch was predicted; used to repair the RAS on a misprediction.
+    pub(crate) ras_checkpoint: RASCheckpoint,
+    // the first store buffer entry and load queue entry; a pair has a second entry right after it
     pub(crate) sb_pos: Option<u16>,
+    pub(crate) lq_pos: Option<u16>,
     pub(crate) eu_index: Option<u8>,
 }
 
@@ -32,10 +37,12 @@ impl ROBSlot {
     fn reset(&mut self) {
         self.branch_target_predicted = 0;
         self.branch_target_actual = 0;
+        self.ras_checkpoint = RASCheckpoint::default();
         self.state = ROBSlotState::IDLE;
         self.rs_index = None;
         self.instr = None;
         self.sb_pos = None;
+        self.lq_pos = None;
         self.eu_index = None;
         self.pc = 0;
 
@@ -65,10 +72,12 @@ impl ROB {
                 instr: None,
                 state: ROBSlotState::IDLE,
                 rs_index: None,
-                sink_phys_regs: [None, None],
+                sink_phys_regs: [None, None, None],
                 branch_target_predicted: 0,
                 branch_target_actual: 0,
+                ras_checkpoint: RASCheckpoint::default(),
                 sb_pos: None,
+                lq_pos: None,
                 eu_index: None,
                 pc: 0,
             });
@@ -86,6 +95,10 @@ impl ROB {
         }
     }
 
+    pub(crate) fn get(&self, slot_index: u16) -> &ROBSlot {
+        &self.slots[slot_index as usize]
+    }
+
     pub(crate) fn get_mut(&mut self, slot_index: u16) -> &mut ROBSlot {
         // todo: should be between head and tail
         &mut self.slots[slot_index as usize]
@@ -104,6 +117,13 @@ impl ROB {
         (seq % self.capacity as u64) as u16
     }
 
+    // Returns the sequence number of the slot with the given index. The slot should be
+    // between the head and the tail.
+    pub(crate) fn to_seq(&self, slot_index: u16) -> u64 {
+        let head_index = self.to_index(self.head);
+        self.head + ((slot_index + self.capacity - head_index) % self.capacity) as u64
+    }
+
     pub(crate) fn deallocate(&mut self) {
         debug_assert!(!self.is_empty(), "ROB: Can't deallocate if ROB is empty");
 
@@ -124,6 +144,19 @@ impl ROB {
         return self.capacity > self.size();
     }
 
+    // Removes all slots younger than the slot with the given sequence number.
+    pub(crate) fn squash(&mut self, seq: u64) {
+        for k in (seq + 1..self.tail).rev() {
+            let index = self.to_index(k);
+            self.slots[index as usize].reset();
+        }
+
+        self.tail = seq + 1;
+        self.seq_issued = self.tail;
+        self.seq_rs_allocated = self.seq_rs_allocated.min(self.tail);
+        self.seq_dispatched = self.seq_dispatched.min(self.tail);
+    }
+
     pub(crate) fn flush(&mut self) {
         // todo: we don't need to go over the whole rob; just over the busy slots
         for i in 0..self.capacity {
@@ -1,6 +1,6 @@
 use std::collections::{HashSet, VecDeque};
 
-use crate::instructions::instructions::{DWordType, MAX_SINK_COUNT, MAX_SOURCE_COUNT, Opcode, Operand, RegisterType};
+use crate::instructions::instructions::{DWordType, Instr, MAX_SINK_COUNT, MAX_SOURCE_COUNT, Opcode, Operand, RegisterType};
 use crate::instructions::instructions::Opcode::NOP;
 
 pub(crate) struct RSOperand {
@@ -30,6 +30,8 @@ pub(crate) enum RSState {
 
 // A single reservation station
 pub(crate) struct RS {
+    // the hardware thread the instruction belongs to; the rob slot index is relative to its ROB
+    pub(crate) thread_id: u8,
     pub(crate) rob_slot_index: Option<u16>,
     pub(crate) opcode: Opcode,
     pub(crate) state: RSState,
@@ -48,16 +50,28 @@ impl RS {
             opcode: Opcode::NOP,
             state: RSState::IDLE,
             source_cnt: 0,
-            source: [RSOperand::new(), RSOperand::new(), RSOperand::new()],
+            source: [RSOperand::new(), RSOperand::new(), RSOperand::new(), RSOperand::new()],
             source_ready_cnt: 0,
             sink_cnt: 0,
-            sink: [RSOperand::new(), RSOperand::new()],
+            sink: [RSOperand::new(), RSOperand::new(), RSOperand::new()],
+            thread_id: 0,
             rob_slot_index: None,
             index,
         }
     }
 
+    // Returns the address of the load or store and the new value of its base register. The base
+    // register is the first source of a load and follows the stored registers of a store; the
+    // offset is the source that follows it.
+    pub(crate) fn agen(&self, instr: &Instr) -> (DWordType, DWordType) {
+        let base_index = instr.mem_stores as usize;
+        let base = self.source[base_index].value.unwrap();
+        let offset = self.source[base_index + 1].value.unwrap();
+        instr.addressing_mode.agen(base, offset)
+    }
+
     fn reset(&mut self) {
+        self.thread_id = 0;
         self.rob_slot_index = None;
         self.opcode = NOP;
         self.state = RSState::IDLE;
@@ -128,35 +142,29 @@ impl RSTable {
         //return self.ready_queue_head != self.ready_queue_tail;
     }
 
-    pub(crate) fn flush(&mut self) {
-        self.ready_queue.clear();
-        self.idle_stack.clear();
-        self.allocated.clear();
-
-        for k in 0..self.capacity {
-            self.array.get_mut(k as usize).unwrap().reset();
-            self.idle_stack.push(k);
-        }
-    }
-
-    pub(crate) fn deque_ready(&mut self) -> u16 {
-        debug_assert!(self.has_ready(), "RSTable: can't dequeue ready when there are no ready items");
-        //let index = self.to_index(self.ready_queue_head);
-        let rs_ready_index = self.ready_queue.pop_front().unwrap();
+    // Dequeues the first ready rs for which the predicate holds; e.g. there is an execution
+    // port available for it.
+    pub(crate) fn deque_ready_matching<F: Fn(&RS) -> bool>(&mut self, predicate: F) -> Option<u16> {
+        let pos = self.ready_queue.iter()
+            .position(|&rs_index| predicate(&self.array[rs_index as usize]))?;
+        let rs_ready_index = self.ready_queue.remove(pos).unwrap();
 
         debug_assert!(self.allocated.contains(&rs_ready_index),
-                      " deque_ready for rs_ready_index {} failed, it is not in the allocated set", rs_ready_index);
+                      " deque_ready_matching for rs_ready_index {} failed, it is not in the allocated set", rs_ready_index);
 
         #[cfg(debug_assertions)]
         {
             let rs = &self.array[rs_ready_index as usize];
-            //println!("RS dequeue ready {:?}", rs.opcode);
-
             debug_assert!(rs.state == RSState::BUSY, "RS should be busy state, rs_index {}", rs_ready_index);
             debug_assert!(rs.rob_slot_index.is_some());
         }
 
-        return rs_ready_index;
+        Some(rs_ready_index)
+    }
+
+    // Removes the rs from the ready queue if it is on the ready queue.
+    pub(crate) fn remove_ready(&mut self, rs_index: u16) {
+        self.ready_queue.retain(|&index| index != rs_index);
     }
 
     pub(crate) fn has_idle(&self) -> bool {
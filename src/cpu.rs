@@ -1,17 +1,19 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::ops::Add;
 use std::rc::Rc;
-use std::thread;
-use std::time::{Duration, Instant};
 
 use serde::Deserialize;
 
 use crate::backend::backend::Backend;
+use crate::frontend::branch_predictor::create_branch_predictor;
+use crate::frontend::branch_target_buffer::BTB;
 use crate::frontend::frontend::{Frontend, FrontendControl};
-use crate::instructions::instructions::{DWordType, InstrQueue, Program, RegisterType};
+use crate::frontend::return_address_stack::RAS;
+use crate::instructions::instructions::{DWordType, InstrQueue, Opcode, Program, RegisterType};
 use crate::memory_subsystem::memory_subsystem::MemorySubsystem;
+use crate::memory_subsystem::uncore::Uncore;
 
 pub struct PerfCounters {
     pub branch_miss_prediction_cnt: u64,
@@ -23,7 +25,26 @@ pub struct PerfCounters {
     pub retired_cnt: u64,
     pub bad_speculation_cnt: u64,
     pub pipeline_flushes: u64,
+    pub store_forward_cnt: u64,
+    pub memory_order_nuke_cnt: u64,
+    // the number of cycles a DMB couldn't retire because the older stores weren't written to memory
+    pub barrier_stall_cnt: u64,
     pub cycle_cnt: u64,
+    pub ports: Vec<PortPerfCounters>,
+    pub threads: Vec<ThreadPerfCounters>,
+}
+
+pub struct PortPerfCounters {
+    pub port_type: PortType,
+    // the number of instructions dispatched to the port
+    pub dispatch_cnt: u64,
+    // the number of cycles the port had at least one instruction in flight
+    pub busy_cnt: u64,
+}
+
+pub struct ThreadPerfCounters {
+    // the number of instructions of the hardware thread that retired
+    pub retired_cnt: u64,
 }
 
 impl PerfCounters {
@@ -39,6 +60,11 @@ impl PerfCounters {
             branch_miss_prediction_cnt: 0,
             branch_good_predictions_cnt: 0,
             pipeline_flushes: 0,
+            store_forward_cnt: 0,
+            memory_order_nuke_cnt: 0,
+            barrier_stall_cnt: 0,
+            ports: Vec::new(),
+            threads: Vec::new(),
         }
     }
 }
@@ -68,6 +94,229 @@ impl Default for Trace {
     }
 }
 
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BranchPredictorType {
+    // backwards branches are taken, forward branches are not taken
+    STATIC,
+    // a table of 2-bit saturating counters indexed by the pc
+    BIMODAL,
+    // a table of 2-bit saturating counters indexed by the pc XOR the global history
+    GSHARE,
+    // a bimodal base predictor with tagged tables using geometric history lengths
+    TAGE,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct BranchPredictorConfig {
+    // the type of branch predictor
+    #[serde(rename = "type")]
+    pub predictor_type: BranchPredictorType,
+    // the number of 2-bit counters in the pattern history table (bimodal/gshare) or base table (tage)
+    pub pht_size: u32,
+    // the number of bits of global branch history (gshare)
+    pub history_bits: u8,
+    // the number of tagged tables (tage)
+    pub tage_table_count: u8,
+    // the number of entries in each tagged table (tage)
+    pub tage_table_size: u32,
+    // the number of bits of the tag in each tagged table entry (tage)
+    pub tage_tag_bits: u8,
+    // the history length of the tagged table with the shortest history (tage)
+    pub tage_min_history: u8,
+    // the history length of the tagged table with the longest history (tage)
+    pub tage_max_history: u8,
+    // the number of entries in the branch target buffer for indirect branches
+    pub btb_size: u16,
+    // the number of entries in the return address stack
+    pub ras_size: u16,
+}
+
+impl Default for BranchPredictorConfig {
+    fn default() -> Self {
+        BranchPredictorConfig {
+            predictor_type: BranchPredictorType::STATIC,
+            pht_size: 4096,
+            history_bits: 12,
+            tage_table_count: 4,
+            tage_table_size: 1024,
+            tage_tag_bits: 9,
+            tage_min_history: 4,
+            tage_max_history: 64,
+            btb_size: 512,
+            ras_size: 16,
+        }
+    }
+}
+
+// the type of an execution port; it determines which instructions it can execute
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PortType {
+    ALU,
+    LOAD,
+    STORE,
+    BRANCH,
+    MULDIV,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct PortConfig {
+    // the number of ports for integer arithmetic, logic and compare instructions
+    pub alu: u8,
+    // the number of ports for loads
+    pub load: u8,
+    // the number of ports for stores
+    pub store: u8,
+    // the number of ports for branches
+    pub branch: u8,
+    // the number of ports for multiplication and division
+    pub muldiv: u8,
+}
+
+impl Default for PortConfig {
+    fn default() -> Self {
+        PortConfig {
+            alu: 4,
+            load: 2,
+            store: 1,
+            branch: 1,
+            muldiv: 1,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplacementPolicy {
+    // the least recently used line is evicted
+    LRU,
+    // a binary tree approximates the least recently used line
+    PLRU,
+    // a random line is evicted
+    RANDOM,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct CacheConfig {
+    // the size of the cache in bytes
+    pub size: u32,
+    // the size of a cache line in bytes
+    pub line_size: u16,
+    // the number of ways per set
+    pub associativity: u16,
+    // the number of cycles for a cache hit
+    pub hit_latency: u8,
+    // the policy to select the line to evict
+    pub replacement: ReplacementPolicy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            size: 32 * 1024,
+            line_size: 64,
+            associativity: 8,
+            hit_latency: 4,
+            replacement: ReplacementPolicy::LRU,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct DRAMConfig {
+    // the number of banks; consecutive rows are interleaved over the banks
+    pub bank_count: u16,
+    // the size of a row in bytes
+    pub row_size: u32,
+    // the number of cycles to access the row that is in the row buffer of the bank
+    pub row_hit_latency: u16,
+    // the number of cycles to access a row that isn't in the row buffer of the bank
+    pub row_miss_latency: u16,
+}
+
+impl Default for DRAMConfig {
+    fn default() -> Self {
+        DRAMConfig {
+            bank_count: 8,
+            row_size: 2048,
+            row_hit_latency: 30,
+            row_miss_latency: 60,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrefetcherType {
+    // no prefetching
+    NONE,
+    // every access prefetches the next lines
+    #[serde(rename = "next_line")]
+    NEXTLINE,
+    // a table indexed by the pc of the load detects a constant stride between its accesses
+    STRIDE,
+    // misses to consecutive lines are tracked as streams that are prefetched ahead
+    STREAM,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct PrefetcherConfig {
+    // the type of prefetcher
+    #[serde(rename = "type")]
+    pub prefetcher_type: PrefetcherType,
+    // the number of lines prefetched ahead of the access
+    pub degree: u8,
+    // the number of entries in the table of the stride prefetcher
+    pub stride_table_size: u16,
+    // the number of streams the stream prefetcher can track
+    pub stream_count: u8,
+}
+
+impl Default for PrefetcherConfig {
+    fn default() -> Self {
+        PrefetcherConfig {
+            prefetcher_type: PrefetcherType::NONE,
+            degree: 2,
+            stride_table_size: 64,
+            stream_count: 8,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchPolicy {
+    // the hardware threads take turns
+    #[serde(rename = "round_robin")]
+    ROUNDROBIN,
+    // the hardware thread with the fewest instructions waiting to be dispatched
+    ICOUNT,
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ROBPartitioning {
+    // every hardware thread gets an equal share of the reorder buffer
+    STATIC,
+    // the hardware threads compete for the slots of the reorder buffer
+    DYNAMIC,
+}
+
+#[derive(Clone, Copy, Deserialize, Debug)]
+pub struct Latency {
+    // the number of cycles before the result of the instruction is available
+    pub cycles: u8,
+    // if the execution unit can accept a new instruction every cycle while this one is in flight.
+    // If not, the execution unit is blocked for the full latency (e.g. a divider).
+    pub pipelined: bool,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency { cycles: 1, pipelined: true }
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct CPUConfig {
     // the number of physical registers
@@ -80,18 +329,42 @@ pub struct CPUConfig {
     pub frequency_hz: u64,
     // the number of reservation stations
     pub rs_count: u16,
-    // the size of the memory in machine words
+    // the size of the memory in bytes
     pub memory_size: u32,
+    // the number of cores
+    pub core_count: u8,
+    // the number of hardware threads per core (SMT); the threads of a core share its backend
+    pub thread_count: u8,
+    // the policy the frontend uses to select the hardware thread to fetch from
+    pub fetch_policy: FetchPolicy,
+    // how the slots of the reorder buffer are divided over the hardware threads
+    pub rob_partitioning: ROBPartitioning,
+    // the size of the stack of each hardware thread in bytes; the stack of hardware thread n
+    // (counted over all cores) starts at memory_size - n * stack_size and grows down
+    pub stack_size: u32,
+    // the number of cycles to snoop the L1Ds of the other cores; it is the latency of a line
+    // provided by the L1D of another core and of invalidating the other copies of a line
+    pub snoop_latency: u16,
     // the capacity of the store buffer
     pub sb_capacity: u16,
-    // the number of line fill buffers; currently there are no line fill buffer
-    // it is just a limit of the number of stores that can commit to memory
-    // per clock cycle (there is also no cache)
+    // the capacity of the load queue
+    pub lq_capacity: u16,
+    // the number of line fill buffers; it limits the number of lines being fetched into the L1D
     pub lfb_count: u8,
+    // the number of miss status holding registers; it limits the number of loads waiting for an L1D miss
+    pub mshr_count: u8,
+    // the level 1 data cache
+    pub l1d: CacheConfig,
+    // the caches behind the L1D; the first one is the L2, the second the L3 etc
+    pub outer_caches: Vec<CacheConfig>,
+    // main memory
+    pub dram: DRAMConfig,
+    // the hardware prefetcher for the L1D
+    pub prefetcher: PrefetcherConfig,
     // the capacity of the reorder buffer
     pub rob_capacity: u16,
-    // the number of execution units
-    pub eu_count: u8,
+    // the number of execution ports per type
+    pub ports: PortConfig,
     // if processing of a single instruction should be traced (printed)
     pub trace: Trace,
     // the number of instructions that can retire per clock cycle
@@ -102,6 +375,13 @@ pub struct CPUConfig {
     pub issue_n_wide: u8,
     // The delay between writing the CPU stats. A value of 0 means that stat writing is disabled.
     pub stats_seconds: u32,
+    // the branch predictor used by the frontend to predict conditional branches
+    pub branch_predictor: BranchPredictorConfig,
+    // the latency per opcode; an opcode without an entry takes 1 cycle and is pipelined.
+    // For loads it is the latency of the address generation; the latency of the cache is added.
+    pub latencies: HashMap<Opcode, Latency>,
+    // if the loader rejects immediates that can't be encoded by the real instruction
+    pub strict_immediates: bool,
 }
 
 impl Default for CPUConfig {
@@ -112,16 +392,56 @@ impl Default for CPUConfig {
             instr_queue_capacity: 64,
             frequency_hz: 4,
             rs_count: 64,
-            memory_size: 128,
+            memory_size: 1024,
+            core_count: 1,
+            thread_count: 1,
+            fetch_policy: FetchPolicy::ROUNDROBIN,
+            rob_partitioning: ROBPartitioning::STATIC,
+            stack_size: 256,
+            snoop_latency: 40,
             sb_capacity: 16,
+            lq_capacity: 16,
             lfb_count: 4,
+            mshr_count: 8,
+            l1d: CacheConfig::default(),
+            outer_caches: vec![
+                CacheConfig {
+                    size: 256 * 1024,
+                    line_size: 64,
+                    associativity: 8,
+                    hit_latency: 12,
+                    replacement: ReplacementPolicy::LRU,
+                },
+                CacheConfig {
+                    size: 2 * 1024 * 1024,
+                    line_size: 64,
+                    associativity: 16,
+                    hit_latency: 30,
+                    replacement: ReplacementPolicy::LRU,
+                },
+            ],
+            dram: DRAMConfig::default(),
+            prefetcher: PrefetcherConfig::default(),
             rob_capacity: 32,
-            eu_count: 10,
+            ports: PortConfig::default(),
             trace: Trace::default(),
             retire_n_wide: 4,
             dispatch_n_wide: 4,
             issue_n_wide: 4,
             stats_seconds: 0,
+            branch_predictor: BranchPredictorConfig::default(),
+            latencies: HashMap::from([
+                (Opcode::MUL, Latency { cycles: 3, pipelined: true }),
+                (Opcode::SDIV, Latency { cycles: 12, pipelined: false }),
+                (Opcode::UDIV, Latency { cycles: 12, pipelined: false }),
+                (Opcode::MADD, Latency { cycles: 3, pipelined: true }),
+                (Opcode::MSUB, Latency { cycles: 3, pipelined: true }),
+                (Opcode::SMULL, Latency { cycles: 3, pipelined: true }),
+                (Opcode::UMULL, Latency { cycles: 3, pipelined: true }),
+                (Opcode::SMULH, Latency { cycles: 4, pipelined: true }),
+                (Opcode::UMULH, Latency { cycles: 4, pipelined: true }),
+            ]),
+            strict_immediates: false,
         }
     }
 }
@@ -132,98 +452,126 @@ pub fn load_cpu_config(file_path: &str) -> Result<CPUConfig, Box<dyn Error>> {
     Ok(config)
 }
 
+/// The state of a single hardware thread: its architectural registers, including the PC, and
+/// the structures between the frontend and the backend. The hardware threads of a core share
+/// the rest of the core.
+#[derive(Clone)]
+pub(crate) struct HardwareThread {
+    pub(crate) instr_queue: Rc<RefCell<InstrQueue>>,
+    pub(crate) arch_reg_file: Rc<RefCell<ArgRegFile>>,
+    pub(crate) frontend_control: Rc<RefCell<FrontendControl>>,
+    pub(crate) ras: Rc<RefCell<RAS>>,
+}
+
+impl HardwareThread {
+    fn new(cpu_config: &CPUConfig, core_id: u8, thread_id: u8) -> HardwareThread {
+        let instr_queue = Rc::new(RefCell::new(InstrQueue::new(cpu_config.instr_queue_capacity)));
+
+        let arch_reg_file = Rc::new(RefCell::new(
+            ArgRegFile::new(GENERAL_ARG_REG_CNT + SPECIAL_ARG_REG_CNT)));
+
+        // on ARM the stack grows down (from larger address to smaller address)
+        let stack_index = core_id as DWordType * cpu_config.thread_count as DWordType + thread_id as DWordType;
+        let stack_top = cpu_config.memory_size as DWordType - stack_index * cpu_config.stack_size as DWordType;
+        arch_reg_file.borrow_mut().set_value(SP, stack_top);
+
+        let frontend_control = Rc::new(RefCell::new(
+            FrontendControl { halted: false, exit: false, undispatched_cnt: 0 }));
+
+        let ras = Rc::new(RefCell::new(RAS::new(cpu_config.branch_predictor.ras_size)));
+
+        HardwareThread { instr_queue, arch_reg_file, frontend_control, ras }
+    }
+}
+
+/// A single core with its own pipeline, L1D and store buffers. The cores of a System share
+/// main memory through the Uncore.
+///
+/// With SMT, a core runs multiple hardware threads. Every thread has its own architectural
+/// registers, instruction queue, RAS, RAT, reorder buffer, store buffer and load queue; the
+/// reservation stations, execution units, physical registers, branch predictor and L1D are
+/// shared.
 pub struct CPU {
+    pub(crate) core_id: u8,
     pub(crate) backend: Backend,
     pub(crate) frontend: Frontend,
     pub(crate) memory_subsystem: Rc<RefCell<MemorySubsystem>>,
-    pub(crate) arch_reg_file: Rc<RefCell<ArgRegFile>>,
-    pub(crate) cycle_period: Duration,
+    pub(crate) threads: Vec<HardwareThread>,
     pub(crate) trace: Trace,
     pub(crate) perf_counters: Rc<RefCell<PerfCounters>>,
-    pub(crate) stats_seconds: u32,
 }
 
 impl CPU {
-    pub fn new(cpu_config: &CPUConfig) -> CPU {
-        let instr_queue = Rc::new(RefCell::new(InstrQueue::new(cpu_config.instr_queue_capacity)));
+    pub fn new(cpu_config: &CPUConfig, core_id: u8, uncore: &Rc<RefCell<Uncore>>) -> CPU {
+        assert!(cpu_config.thread_count > 0, "CPU: thread_count should be larger than 0");
 
         let perf_counters = Rc::new(RefCell::new(PerfCounters::new()));
 
         let memory_subsystem = Rc::new(RefCell::new(
-            MemorySubsystem::new(cpu_config)));
+            MemorySubsystem::new(cpu_config, core_id, uncore)));
 
-        let arch_reg_file = Rc::new(RefCell::new(
-            ArgRegFile::new(GENERAL_ARG_REG_CNT + SPECIAL_ARG_REG_CNT)));
+        let threads: Vec<HardwareThread> = (0..cpu_config.thread_count)
+            .map(|thread_id| HardwareThread::new(cpu_config, core_id, thread_id))
+            .collect();
 
-        // on ARM the stack grows down (from larger address to smaller address)
-        arch_reg_file.borrow_mut().set_value(SP, cpu_config.memory_size as DWordType);
+        let branch_predictor = create_branch_predictor(&cpu_config.branch_predictor);
 
-        let frontend_control = Rc::new(RefCell::new(
-            FrontendControl { halted: false }));
+        let btb = Rc::new(RefCell::new(BTB::new(cpu_config.branch_predictor.btb_size)));
 
         let backend = Backend::new(
             cpu_config,
-            &instr_queue,
+            core_id,
+            &threads,
             &memory_subsystem,
-            &arch_reg_file,
-            &frontend_control,
             &perf_counters,
+            &branch_predictor,
+            &btb,
         );
 
         let frontend = Frontend::new(
             cpu_config,
-            &instr_queue,
-            &frontend_control,
+            &threads,
             &perf_counters,
-            &arch_reg_file,
+            &branch_predictor,
+            &btb,
         );
 
         CPU {
+            core_id,
             backend,
             frontend,
             memory_subsystem,
-            arch_reg_file,
-            stats_seconds: cpu_config.stats_seconds,
-            cycle_period: Duration::from_micros(1_000_000 / cpu_config.frequency_hz),
+            threads,
             trace: cpu_config.trace.clone(),
             perf_counters: perf_counters,
         }
     }
 
-    pub fn run(&mut self, program: &Rc<Program>) {
+    pub(crate) fn init(&mut self, program: &Rc<Program>) {
         self.frontend.init(program);
 
-        self.memory_subsystem.borrow_mut().init(program);
-
-        let log_stats_interval = Duration::new(self.stats_seconds as u64, 0); // n seconds
-        println!("log_stats_interval: {:?}", log_stats_interval);
-        let mut last_log_stats_time = Instant::now().add(log_stats_interval);
-
-        while !self.backend.exit {
-            self.perf_counters.borrow_mut().cycle_cnt += 1;
-            self.memory_subsystem.borrow_mut().do_cycle();
-            self.backend.do_cycle();
-            self.frontend.do_cycle();
-            thread::sleep(self.cycle_period);
+        self.memory_subsystem.borrow_mut().init();
+    }
 
-            if self.stats_seconds> 0 && last_log_stats_time.elapsed() >= log_stats_interval {
-                self.log_stats();
-                last_log_stats_time = Instant::now();
-            }
+    // Once the program has exited on all hardware threads, only the store buffers are drained.
+    pub(crate) fn do_cycle(&mut self) {
+        self.memory_subsystem.borrow_mut().do_cycle();
+        if self.backend.is_exited() {
+            return;
         }
 
-        loop {
-            if self.memory_subsystem.borrow_mut().sb.is_empty() {
-                break;
-            }
-
-            self.memory_subsystem.borrow_mut().do_cycle();
-        }
+        self.perf_counters.borrow_mut().cycle_cnt += 1;
+        self.backend.do_cycle();
+        self.frontend.do_cycle();
+    }
 
-        println!("Program complete!");
+    // True if the program has exited on all hardware threads and all their stores have been
+    // written to memory.
+    pub(crate) fn is_done(&self) -> bool {
+        self.backend.is_exited() && self.memory_subsystem.borrow().sbs.iter().all(|sb| sb.is_empty())
     }
 
-    fn log_stats(&mut self) {
+    pub(crate) fn log_stats(&self) {
         let perf_counters = self.perf_counters.borrow_mut();
         let branch_total = perf_counters.branch_miss_prediction_cnt + perf_counters.branch_good_predictions_cnt;
 
@@ -237,8 +585,16 @@ impl CPU {
 
         let mut message = String::new();
 
+        message.push_str(&format!("[Core {}]", self.core_id));
         message.push_str(&format!("[Cycles:{}]", perf_counters.cycle_cnt));
         message.push_str(&format!("[IPC={:.2}]", ipc));
+        if perf_counters.threads.len() > 1 {
+            message.push_str("[Thread IPC");
+            for (thread_id, thread) in perf_counters.threads.iter().enumerate() {
+                message.push_str(&format!(" {}={:.2}", thread_id, thread.retired_cnt as f32 / perf_counters.cycle_cnt as f32));
+            }
+            message.push(']');
+        }
         message.push_str(&format!("[Decoded={}]", perf_counters.decode_cnt));
         message.push_str(&format!("[Issued={}]", perf_counters.issue_cnt));
         message.push_str(&format!("[Dispatched={}]", perf_counters.dispatch_cnt));
@@ -246,6 +602,30 @@ impl CPU {
         message.push_str(&format!("[Retired={}]", perf_counters.retired_cnt));
         message.push_str(&format!("[Branch Tot={}, Pred={:.2}%]", branch_total, branch_prediction));
         message.push_str(&format!("[Pipeline Flush={}]", perf_counters.pipeline_flushes));
+        message.push_str(&format!("[Store Forward={}]", perf_counters.store_forward_cnt));
+        message.push_str(&format!("[Memory Order Nuke={}]", perf_counters.memory_order_nuke_cnt));
+        message.push_str(&format!("[Barrier Stall={}]", perf_counters.barrier_stall_cnt));
+
+        let memory_subsystem = self.memory_subsystem.borrow();
+        let l1d = memory_subsystem.l1d.borrow();
+        message.push_str(&format!("[{} Hit={}, Miss={}, Evict={}]", l1d.name, l1d.hit_cnt, l1d.miss_cnt, l1d.eviction_cnt));
+        let mlp = if memory_subsystem.mshr_busy_cycle_cnt > 0 {
+            memory_subsystem.mshr_occupied_sum as f32 / memory_subsystem.mshr_busy_cycle_cnt as f32
+        } else {
+            0.0
+        };
+        message.push_str(&format!("[MLP={:.2}]", mlp));
+        message.push_str(&format!("[Prefetch Issued={}, Useful={}, Late={}]",
+                                  memory_subsystem.prefetch_issued_cnt,
+                                  memory_subsystem.prefetch_useful_cnt,
+                                  memory_subsystem.prefetch_late_cnt));
+
+        message.push_str("[Port Util");
+        for (port_index, port) in perf_counters.ports.iter().enumerate() {
+            let utilisation = 100.0 * port.busy_cnt as f32 / perf_counters.cycle_cnt as f32;
+            message.push_str(&format!(" {}:{:?}={:.2}%", port_index, port.port_type, utilisation));
+        }
+        message.push(']');
 
         println!("{}", message);
     }
@@ -258,6 +638,9 @@ pub const SP: u16 = 13;
 pub const LR: u16 = 14;
 pub const PC: u16 = 15;
 pub const CPSR: u16 = GENERAL_ARG_REG_CNT;
+// The zero register XZR/WZR; it reads as zero and writes are discarded. It isn't part of the
+// architectural register file and isn't renamed.
+pub const ZR: u16 = CPSR + 1;
 
 pub const ZERO_FLAG: u8 = 30;
 pub const NEGATIVE_FLAG: u8 = 31;
@@ -5,7 +5,13 @@ use crate::instructions::instructions::{DWordType, Program, RegisterType};
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Ref;
+
+    use crate::cpu::{FetchPolicy, PerfCounters, PortType, PrefetcherType, ROBPartitioning};
+    use crate::instructions::instructions::Opcode;
     use crate::loader::loader::{load_from_string, LoadError};
+    use crate::memory_subsystem::memory_subsystem::WORD_SIZE;
+    use crate::system::System;
 
     use super::*;
 
@@ -81,6 +87,81 @@ mod tests {
         harness.assert_reg_value(2, 1000);
     }
 
+    #[test]
+    fn test_SDIV_UDIV() {
+        let src = r#"
+.text
+    MOV r0, #-100;
+    MOV r1, #7;
+    MOV r2, #0;
+    SDIV r3, r0, r1;
+    UDIV r4, r0, r1;
+    SDIV r5, r0, r2;
+    UDIV r6, r1, r2;
+    MOV r7, #1;
+    LSL r7, r7, #63;
+    MOV r8, #-1;
+    SDIV r9, r7, r8;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(3, (-14i64) as DWordType);
+        harness.assert_reg_value(4, ((-100i64) as DWordType) / 7);
+        // a division by zero gives zero
+        harness.assert_reg_value(5, 0);
+        harness.assert_reg_value(6, 0);
+        harness.assert_reg_value(9, 1 << 63);
+    }
+
+    #[test]
+    fn test_MADD_MSUB() {
+        let src = r#"
+.text
+    MOV r0, #6;
+    MOV r1, #7;
+    MOV r2, #100;
+    MADD r3, r0, r1, r2;
+    MSUB r4, r0, r1, r2;
+    MOV r5, #-3;
+    MADD r6, r5, r1, r2;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(3, 142);
+        harness.assert_reg_value(4, 58);
+        harness.assert_reg_value(6, 79);
+    }
+
+    #[test]
+    fn test_long_multiply() {
+        let src = r#"
+.text
+    MOV r0, #-2;
+    MOV r1, #3;
+    SMULL r2, w0, w1;
+    UMULL r3, w0, w1;
+    MOV r4, #1;
+    LSL r4, r4, #62;
+    MOV r5, #8;
+    UMULH r6, r4, r5;
+    SMULH r7, r4, r5;
+    SMULH r8, r0, r5;
+    UMULH r9, r0, r5;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, (-6i64) as DWordType);
+        // the lower 32 bits of -2 are 0xFFFF_FFFE
+        harness.assert_reg_value(3, 0xFFFF_FFFE * 3);
+        harness.assert_reg_value(6, 2);
+        harness.assert_reg_value(7, 2);
+        harness.assert_reg_value(8, (-1i64) as DWordType);
+        harness.assert_reg_value(9, 7);
+    }
+
 
     #[test]
     fn test_loop_CMP_BNE() {
@@ -140,6 +221,55 @@ loop:
         harness.assert_reg_value(1, 10);
     }
 
+    #[test]
+    fn test_B_cond() {
+        let conditions = ["EQ", "NE", "CS", "CC", "MI", "PL", "VS", "VC", "HI", "LS", "GE", "LT", "GT", "LE", "AL"];
+        // every condition sets its own bit in r10 if its branch is taken
+        let taken_mask = |cmp: &str| {
+            let mut src = format!(".text\n    MOV r0, #5;\n    MOV r1, #7;\n    MOV r2, #9223372036854775807;\n    {}\n", cmp);
+            for (k, condition) in conditions.iter().enumerate() {
+                src += &format!("    B.{} _taken_{};\n    B _next_{};\n_taken_{}:\n    ORR r10, r10, #{};\n_next_{}:\n",
+                                condition, k, k, k, 1 << k, k);
+            }
+            src += "    NOP;\n";
+
+            let mut harness = TestHarness::default();
+            harness.run(&src);
+            let mask = harness.cpu().threads[0].arch_reg_file.borrow().get_value(10);
+            mask
+        };
+        let expected_mask = |taken: &[&str]| -> DWordType {
+            taken.iter().map(|condition| 1 << conditions.iter().position(|c| c == condition).unwrap()).sum()
+        };
+
+        assert_eq!(taken_mask("CMP r0, r1;"), expected_mask(&["NE", "CC", "MI", "VC", "LS", "LT", "LE", "AL"]));
+        assert_eq!(taken_mask("CMP r1, r0;"), expected_mask(&["NE", "CS", "PL", "VC", "HI", "GE", "GT", "AL"]));
+        assert_eq!(taken_mask("CMP r0, r0;"), expected_mask(&["EQ", "CS", "PL", "VC", "LS", "GE", "LE", "AL"]));
+        // the subtraction overflows
+        assert_eq!(taken_mask("CMP r2, #-1;"), expected_mask(&["NE", "CC", "MI", "VS", "LS", "GE", "GT", "AL"]));
+    }
+
+    #[test]
+    fn test_B_cond_aliases() {
+        let src = r#"
+.text
+    MOV r0, #3;
+    CMP r0, #5;
+    BLO _lower;
+    MOV r1, #1;
+_lower:
+    b.hs _end;
+    MOV r2, #1;
+_end:
+    NOP;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 0);
+        harness.assert_reg_value(2, 1);
+    }
+
     #[test]
     fn test_loop_CMP_BLE() {
         let src = r#"
@@ -412,92 +542,1678 @@ _start:
         harness.assert_reg_value(2, 16);
     }
 
-    struct TestHarness {
-        program: Option<Rc<Program>>,
-        cpu: Option<CPU>,
-        cpu_config: CPUConfig,
+    // Alternating branch outcomes cause mispredictions; the squashed instructions
+    // on the wrong path should not modify registers or memory.
+    #[test]
+    fn test_misprediction_squash() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, #20;
+    MOV r1, #0;
+    MOV r2, #0;
+    MOV r4, =var_a;
+loop:
+    AND r3, r0, #1;
+    CBZ r3, even;
+    ADD r1, r1, #1;
+    STR r1, [r4];
+    B next;
+even:
+    ADD r2, r2, #1;
+next:
+    SUB r0, r0, #1;
+    CBNZ r0, loop;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 10);
+        harness.assert_reg_value(2, 10);
+        harness.assert_variable_value("var_a", 10);
     }
 
-    impl TestHarness {
-        fn default() -> TestHarness {
-            let cpu_config = Self::new_test_cpu_config();
-            TestHarness {
-                program: None,
-                cpu: Some(CPU::new(&cpu_config.clone())),
-                cpu_config: cpu_config,
-            }
-        }
+    #[test]
+    #[should_panic(expected = "the number of MULDIV ports should be larger than 0")]
+    fn test_no_muldiv_port() {
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.ports.muldiv = 0;
+        TestHarness::new(cpu_config);
+    }
 
-        fn new_test_cpu_config() -> CPUConfig {
-            let mut cpu_config = CPUConfig::default();
-            cpu_config.frequency_hz = 1000;
-            cpu_config
-        }
+    #[test]
+    #[should_panic(expected = "the latency of MUL should be at least 1 cycle")]
+    fn test_zero_cycle_latency() {
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.latencies.get_mut(&Opcode::MUL).unwrap().cycles = 0;
+        TestHarness::new(cpu_config);
+    }
 
-        fn run(&mut self, src: &str) {
-            self.program = Some(self.load_program(src));
-            let program = Rc::clone(self.program.as_ref().unwrap());
-            self.cpu.as_mut().unwrap().run(&program);
-        }
+    #[test]
+    fn test_non_pipelined_latency() {
+        let src = r#"
+.text
+    MOV r0, #100;
+    MOV r1, #10;
+    SDIV r2, r0, r1;
+    SDIV r3, r0, r1;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.ports.muldiv = 1;
+        let sdiv_cycles = cpu_config.latencies.get(&Opcode::SDIV).unwrap().cycles as u64;
 
-        fn load_program(&mut self, src: &str) -> Rc<Program> {
-            let load_result = load_from_string(self.cpu_config.clone(), src.to_string());
-            let program = match load_result {
-                Ok(p) => Rc::new(p),
-                Err(err) => {
-                    match err {
-                        LoadError::ParseError(msg) => {
-                            println!("{}", msg);
-                            assert!(false);
-                            unreachable!();
-                        }
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        harness.assert_reg_value(2, 10);
+        harness.assert_reg_value(3, 10);
+        // the second SDIV can't start before the first one has completed
+        assert!(harness.perf_counters().cycle_cnt >= 2 * sdiv_cycles);
+    }
 
-                        LoadError::AnalysisError(msg_vec) => {
-                            for msg in msg_vec {
-                                println!("{}", msg);
-                            }
-                            assert!(false);
-                            unreachable!();
-                        }
-                        LoadError::NotFoundError(msg) => {
-                            println!("{}", msg);
-                            unreachable!();
-                        }
-                        LoadError::IOError(msg) => {
-                            println!("{}", msg);
-                            unreachable!();
-                        }
-                    }
-                }
-            };
-            program
-        }
+    #[test]
+    fn test_port_perf_counters() {
+        let src = r#"
+.data
+    var_a: .dword 6
+    var_b: .dword 7
+.text
+    MOV r0, =var_a;
+    MOV r1, =var_b;
+    LDR r2, [r0];
+    LDR r3, [r1];
+    MUL r4, r2, r3;
+    ADD r5, r2, r3;
+    MUL r6, r4, r2;
+    SDIV r7, r6, r3;
+    SUB r8, r7, r2;
+"#;
+        let cpu_config = TestHarness::new_test_cpu_config();
+        let sdiv_cycles = cpu_config.latencies.get(&Opcode::SDIV).unwrap().cycles as u64;
 
-        fn assert_reg_value(&self, reg: RegisterType, value: DWordType) {
-            if let Some(ref cpu) = self.cpu {
-                let reg_file = cpu.arch_reg_file.borrow();
-                assert_eq!(reg_file.get_value(reg), value);
-            } else {
-                panic!("CPU is not initialized");
-            }
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        harness.assert_reg_value(7, 36);
+
+        let perf_counters = harness.perf_counters();
+        let port_cnt = |port_type: PortType| perf_counters.ports.iter()
+            .filter(|port| port.port_type == port_type)
+            .fold((0, 0), |(dispatch_cnt, busy_cnt), port| (dispatch_cnt + port.dispatch_cnt, busy_cnt + port.busy_cnt));
+
+        let (muldiv_dispatch_cnt, muldiv_busy_cnt) = port_cnt(PortType::MULDIV);
+        assert_eq!(muldiv_dispatch_cnt, 3);
+        // the MULs and the SDIV depend on each other, so they don't overlap
+        assert!(muldiv_busy_cnt >= sdiv_cycles + 2);
+
+        let (load_dispatch_cnt, load_busy_cnt) = port_cnt(PortType::LOAD);
+        assert_eq!(load_dispatch_cnt, 2);
+        assert!(load_busy_cnt >= 1);
+
+        // the EXIT at the end of the program also goes to an ALU port
+        let (alu_dispatch_cnt, alu_busy_cnt) = port_cnt(PortType::ALU);
+        assert_eq!(alu_dispatch_cnt, 5);
+        assert!(alu_busy_cnt >= 1);
+
+        for port_type in [PortType::STORE, PortType::BRANCH] {
+            assert_eq!(port_cnt(port_type), (0, 0), "{:?}", port_type);
         }
+    }
 
-        fn assert_variable_value(&self, name: &str, value: DWordType) {
-            if let Some(ref cpu) = self.cpu {
-                let program = self.program.as_ref().expect("Program not initialized");
-                let data_item = program.data_items.get(name).expect("Data item not found");
-                let offset = data_item.offset;
-                let memory_subsystem = cpu.memory_subsystem.borrow();
-                match memory_subsystem.memory.get(offset as usize) {
-                    Some(&actual_value) => {
-                        assert_eq!(actual_value, value, "Variable '{}' does not have the expected value", name);
-                    }
-                    None => {
-                        panic!("Memory offset {} is invalid", offset);
-                    }
-                }
+    #[test]
+    fn test_store_to_load_forwarding() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, #10;
+    LDR r5, [r0];
+    ADD r6, r0, r5;
+    SDIV r3, r1, r1;
+    SDIV r3, r3, r3;
+    SDIV r3, r3, r3;
+    SDIV r3, r3, r3;
+    STR r1, [r0];
+    LDR r2, [r6];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        // keep the latency of a L1D miss well below the latency of the SDIVs
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_miss_latency = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        // the first load brings var_a into the L1D. The store can't retire before the SDIVs,
+        // so it is still in the store buffer when the second load executes.
+        harness.assert_reg_value(2, 10);
+        harness.assert_variable_value("var_a", 10);
+        assert!(harness.perf_counters().store_forward_cnt > 0);
+    }
+
+    #[test]
+    fn test_memory_ordering_violation() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, #10;
+    MOV r2, #1;
+    LDR r5, [r0];
+    ADD r6, r0, r5;
+    SDIV r3, r0, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    STR r1, [r3];
+    LDR r4, [r6];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        // keep the latency of a L1D miss well below the latency of the SDIVs
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_miss_latency = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        // the first load brings var_a into the L1D, so the second load executes
+        // before the address of the older store is known.
+        harness.assert_reg_value(4, 10);
+        harness.assert_variable_value("var_a", 10);
+        assert!(harness.perf_counters().memory_order_nuke_cnt > 0);
+    }
+
+    #[test]
+    fn test_memory_ordering_violation_in_subroutine() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    BL _violation;
+    MOV r7, #1;
+    B _end;
+_violation:
+    MOV r1, #10;
+    MOV r2, #1;
+    LDR r5, [r0];
+    ADD r6, r0, r5;
+    SDIV r3, r0, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    STR r1, [r3];
+    LDR r4, [r6];
+    RET;
+_end:
+    NOP;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_miss_latency = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        harness.assert_reg_value(4, 10);
+        harness.assert_reg_value(7, 1);
+        assert!(harness.perf_counters().memory_order_nuke_cnt > 0);
+        // the nuke restores the RAS, so the return is still predicted correctly
+        assert_eq!(harness.perf_counters().branch_miss_prediction_cnt, 0);
+    }
+
+    #[test]
+    fn test_byte_addressable_memory() {
+        let src = r#"
+.data
+    var_a: .dword 1
+    var_b: .dword 2
+.text
+    MOV r0, =var_a;
+    MOV r1, =var_b;
+    LDR r2, [r0];
+    LDR r3, [r1];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        // the variables are a machine word apart
+        harness.assert_reg_value(1, 8);
+        harness.assert_reg_value(2, 1);
+        harness.assert_reg_value(3, 2);
+    }
+
+    #[test]
+    fn test_byte_halfword_word_access() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    ADD r1, r0, #1;
+    ADD r2, r0, #4;
+    MOV r3, #255;
+    STRB r3, [r0];
+    MOV r3, #4660;
+    STRH r3, [r1];
+    MOV r3, #4294967295;
+    STR w3, [r2];
+    LDRB r4, [r0];
+    LDRH r5, [r1];
+    LDR w6, [r0];
+    LDRSW r7, [r2];
+    LDR w8, [r2];
+    LDR r9, [r0];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(4, 0xFF);
+        harness.assert_reg_value(5, 0x1234);
+        // the loads that only partially overlap with a store combine the bytes of multiple stores
+        harness.assert_reg_value(6, 0x001234FF);
+        harness.assert_reg_value(7, DWordType::MAX);
+        harness.assert_reg_value(8, 0xFFFFFFFF);
+        harness.assert_reg_value(9, 0xFFFFFFFF001234FF);
+        harness.assert_variable_value("var_a", 0xFFFFFFFF001234FF);
+        assert!(harness.perf_counters().store_forward_cnt > 0);
+    }
+
+    #[test]
+    fn test_partial_overlap_memory_ordering_violation() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, #10;
+    MOV r2, #1;
+    LDR r5, [r0];
+    ADD r6, r0, r5;
+    SDIV r3, r0, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    ADD r3, r3, #1;
+    STRB r1, [r3];
+    LDR r4, [r6];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_miss_latency = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        // the byte store overlaps with the second byte of the word the younger load has already read
+        harness.assert_reg_value(4, 10 << 8);
+        harness.assert_variable_value("var_a", 10 << 8);
+        assert!(harness.perf_counters().memory_order_nuke_cnt > 0);
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let src = r#"
+.data
+    var_a: .dword 10
+    var_b: .dword 20
+    var_c: .dword 30
+    var_d: .dword 40
+.text
+    MOV r0, =var_a;
+    LDR r1, [r0, #8];
+    MOV r2, #2;
+    LDR r3, [r0, r2, LSL #3];
+    MOV r4, #24;
+    LDR r5, [r0, r4];
+    MOV r6, r0;
+    LDR r7, [r6, #16]!;
+    MOV r8, r0;
+    LDR r9, [r8], #8;
+    MOV r10, =var_d;
+    STR r9, [r10, #-8]!;
+    MOV r11, r0;
+    STR r5, [r11], #24;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        let var_a = harness.program.as_ref().unwrap().data_items["var_a"].offset;
+        harness.assert_reg_value(1, 20);
+        harness.assert_reg_value(3, 30);
+        harness.assert_reg_value(5, 40);
+        harness.assert_reg_value(7, 30);
+        harness.assert_reg_value(6, var_a + 16);
+        harness.assert_reg_value(9, 10);
+        harness.assert_reg_value(8, var_a + 8);
+        harness.assert_reg_value(10, var_a + 16);
+        harness.assert_reg_value(11, var_a + 24);
+        harness.assert_variable_value("var_c", 10);
+        harness.assert_variable_value("var_a", 40);
+    }
+
+    #[test]
+    fn test_post_indexed_loop() {
+        let src = r#"
+.data
+    var_a: .dword 1
+    var_b: .dword 2
+    var_c: .dword 3
+    var_d: .dword 4
+.text
+    MOV r0, =var_a;
+    MOV r1, #4;
+    MOV r2, #0;
+loop:
+    LDR r3, [r0], #8;
+    ADD r2, r2, r3;
+    STR r2, [r0, #-8];
+    SUB r1, r1, #1;
+    CBNZ r1, loop;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        let var_a = harness.program.as_ref().unwrap().data_items["var_a"].offset;
+        harness.assert_reg_value(0, var_a + 32);
+        harness.assert_reg_value(2, 10);
+        harness.assert_variable_value("var_a", 1);
+        harness.assert_variable_value("var_b", 3);
+        harness.assert_variable_value("var_c", 6);
+        harness.assert_variable_value("var_d", 10);
+    }
+
+    #[test]
+    fn test_CSEL_CSINC_CSNEG_CSET() {
+        let src = r#"
+.text
+    MOV r0, #5;
+    MOV r1, #7;
+    CMP r0, r1;
+    CSEL r2, r0, r1, LT;
+    CSEL r3, r0, r1, GE;
+    CSINC r4, r0, r1, EQ;
+    CSNEG r5, r0, r1, NE;
+    CSNEG r6, r0, r1, HI;
+    CSET r7, LO;
+    CSET r8, hs;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, 5);
+        harness.assert_reg_value(3, 7);
+        harness.assert_reg_value(4, 8);
+        harness.assert_reg_value(5, 5);
+        harness.assert_reg_value(6, (-7i64) as DWordType);
+        harness.assert_reg_value(7, 1);
+        harness.assert_reg_value(8, 0);
+    }
+
+    #[test]
+    fn test_condition_names_as_labels() {
+        let src = r#"
+.data
+    hi: .dword 5
+.text
+    MOV r0, =hi;
+    LDR r0, [r0];
+    CMP r0, #3;
+    B lo;
+    MOV r1, #1;
+lo:
+    CSET r2, HI;
+    CSEL r3, r0, r0, lo;
+    CBNZ r0, hs;
+    MOV r4, #1;
+hs:
+    ADD r5, r0, #1;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 0);
+        harness.assert_reg_value(2, 1);
+        harness.assert_reg_value(3, 5);
+        harness.assert_reg_value(4, 0);
+        harness.assert_reg_value(5, 6);
+        harness.assert_variable_value("hi", 5);
+    }
+
+    #[test]
+    fn test_CCMP() {
+        let src = r#"
+.text
+    MOV r0, #3;
+    MOV r1, #3;
+    MOV r2, #10;
+    CMP r0, r1;
+    CCMP r2, #10, #0, EQ;
+    CSET r3, EQ;
+    CMP r0, #4;
+    CCMP r2, #10, #0, EQ;
+    CSET r4, EQ;
+    CCMP r2, r2, #0, NE;
+    CSET r5, EQ;
+    CMP r0, #4;
+    CCMP r2, #10, #8, EQ;
+    CSET r6, MI;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(4, 0);
+        harness.assert_reg_value(5, 1);
+        harness.assert_reg_value(6, 1);
+    }
+
+    #[test]
+    fn test_SUBS_counting_loop() {
+        let src = r#"
+.text
+    MOV r0, #10;
+    MOV r1, #0;
+_loop:
+    ADD r1, r1, #3;
+    SUBS r0, r0, #1;
+    B.NE _loop;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(0, 0);
+        harness.assert_reg_value(1, 30);
+    }
+
+    #[test]
+    fn test_ADDS_SUBS_flags() {
+        let src = r#"
+.text
+    MOV r0, #-1;
+    ADDS r1, r0, #1;
+    CSET r2, EQ;
+    CSET r3, CS;
+    CSET r4, VS;
+    MOV r0, #9223372036854775807;
+    ADDS r5, r0, #1;
+    CSET r6, VS;
+    CSET r7, MI;
+    CSET r8, CS;
+    MOV r0, #5;
+    SUBS r9, r0, #7;
+    CSET r10, CC;
+    CSET r11, LT;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 0);
+        harness.assert_reg_value(2, 1);
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(4, 0);
+        harness.assert_reg_value(5, 1 << 63);
+        harness.assert_reg_value(6, 1);
+        harness.assert_reg_value(7, 1);
+        harness.assert_reg_value(8, 0);
+        harness.assert_reg_value(9, (-2i64) as DWordType);
+        harness.assert_reg_value(10, 1);
+        harness.assert_reg_value(11, 1);
+    }
+
+    #[test]
+    fn test_ANDS_NEGS() {
+        let src = r#"
+.text
+    MOV r0, #-1;
+    ADDS r1, r0, #1;
+    ANDS r2, r0, #6;
+    CSET r3, CS;
+    CSET r4, EQ;
+    MOV r0, #12;
+    ANDS r5, r0, #3;
+    CSET r6, EQ;
+    NEGS r7, r0;
+    CSET r8, MI;
+    CSET r9, CS;
+    MOV r0, #0;
+    NEGS r10, r0;
+    CSET r11, CS;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, 6);
+        // ANDS clears the carry set by the ADDS
+        harness.assert_reg_value(3, 0);
+        harness.assert_reg_value(4, 0);
+        harness.assert_reg_value(5, 0);
+        harness.assert_reg_value(6, 1);
+        harness.assert_reg_value(7, (-12i64) as DWordType);
+        harness.assert_reg_value(8, 1);
+        harness.assert_reg_value(9, 0);
+        harness.assert_reg_value(10, 0);
+        harness.assert_reg_value(11, 1);
+    }
+
+    #[test]
+    fn test_TST_CMN() {
+        let src = r#"
+.text
+    MOV r0, #10;
+    MOV r1, #-10;
+    TST r0, #5;
+    CSET r2, EQ;
+    TST r0, #2;
+    CSET r3, NE;
+    CMN r0, r1;
+    CSET r4, EQ;
+    CSET r5, CS;
+    CMN r0, #1;
+    CSET r6, GT;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(0, 10);
+        harness.assert_reg_value(2, 1);
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(4, 1);
+        harness.assert_reg_value(5, 1);
+        harness.assert_reg_value(6, 1);
+    }
+
+    #[test]
+    fn test_shift_instructions() {
+        let src = r#"
+.text
+    MOV r0, #-16;
+    MOV r1, #2;
+    LSL r2, r0, #1;
+    LSR r3, r0, #60;
+    ASR r4, r0, r1;
+    ROR r5, r1, #1;
+    MOV r6, #66;
+    lsl r7, r1, r6;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, (-32i64) as DWordType);
+        harness.assert_reg_value(3, 15);
+        harness.assert_reg_value(4, (-4i64) as DWordType);
+        harness.assert_reg_value(5, 1);
+        // the register amount is taken modulo 64
+        harness.assert_reg_value(7, 8);
+    }
+
+    #[test]
+    fn test_X_registers() {
+        let src = r#"
+.text
+    MOV x0, #5;
+    ADD X1, x0, r0;
+    MOV x30, x1;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 10);
+        harness.assert_reg_value(30, 10);
+    }
+
+    #[test]
+    fn test_W_registers() {
+        let src = r#"
+.text
+    MOV x0, #-1;
+    ADD w1, w0, #1;
+    MOV w2, #-1;
+    MUL w3, w2, w2;
+    MOV w5, #2;
+    UDIV w4, w0, w5;
+    ADD w6, w2, w2, LSL #1;
+    MOV x7, #1;
+    LSL x7, x7, #32;
+    CBZ w7, _low_zero;
+    MOV x8, #1;
+_low_zero:
+    NEG w9, w5;
+    CSET w10, AL;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 0);
+        harness.assert_reg_value(2, 0xFFFF_FFFF);
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(4, 0x7FFF_FFFF);
+        harness.assert_reg_value(6, 0xFFFF_FFFD);
+        harness.assert_reg_value(8, 0);
+        harness.assert_reg_value(9, 0xFFFF_FFFE);
+        harness.assert_reg_value(10, 1);
+    }
+
+    #[test]
+    fn test_W_register_flags() {
+        let src = r#"
+.text
+    MOV w0, #-1;
+    ADDS w1, w0, #1;
+    CSET w2, CS;
+    CMP w0, #0;
+    CSET w3, MI;
+    MOV w4, #2147483647;
+    ADDS w5, w4, #1;
+    CSET w6, VS;
+    TST w0, #2147483648;
+    CSET w7, MI;
+    MOV w8, #-8;
+    ASR w9, w8, #1;
+    MOV w10, #2;
+    SDIV w11, w8, w10;
+    MOV w12, #1;
+    ROR w13, w12, #1;
+    MOVN w14, #0;
+    MOVZ w15, #1, LSL #16;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 0);
+        harness.assert_reg_value(2, 1);
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(5, 0x8000_0000);
+        harness.assert_reg_value(6, 1);
+        harness.assert_reg_value(7, 1);
+        harness.assert_reg_value(9, 0xFFFF_FFFC);
+        harness.assert_reg_value(11, 0xFFFF_FFFC);
+        harness.assert_reg_value(13, 0x8000_0000);
+        harness.assert_reg_value(14, 0xFFFF_FFFF);
+        harness.assert_reg_value(15, 0x1_0000);
+    }
+
+    #[test]
+    fn test_zero_register() {
+        let src = r#"
+.data
+    var_a: .dword 10
+.text
+    MOV x0, #7;
+    ADD x1, x0, xzr;
+    MOV xzr, #5;
+    MOV x2, xzr;
+    MOV x3, #3;
+_loop:
+    SUBS xzr, x3, #1;
+    SUB x3, x3, #1;
+    B.NE _loop;
+    CSET x4, EQ;
+    MOV x5, =var_a;
+    STR wzr, [x5];
+    LDR xzr, [x5];
+    ADD w6, wzr, #1;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 7);
+        harness.assert_reg_value(2, 0);
+        harness.assert_reg_value(3, 0);
+        harness.assert_reg_value(4, 1);
+        harness.assert_reg_value(6, 1);
+        harness.assert_variable_value("var_a", 0);
+    }
+
+    #[test]
+    fn test_register_names_as_labels() {
+        let src = r#"
+.data
+    xzr: .dword 7
+.text
+    MOV x0, =xzr;
+    LDR x1, [x0];
+    B x1;
+    MOV x2, #1;
+x1:
+    CBNZ w1, w2;
+    MOV x3, #1;
+w2:
+    ADD x4, x1, xzr;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 7);
+        harness.assert_reg_value(2, 0);
+        harness.assert_reg_value(3, 0);
+        harness.assert_reg_value(4, 7);
+        harness.assert_variable_value("xzr", 7);
+    }
+
+    #[test]
+    fn test_MOVZ_MOVK_MOVN() {
+        let src = r#"
+.text
+    MOVZ r0, #20077;
+    MOVK r0, #16838, LSL #16;
+    MOVZ r1, #1, LSL #48;
+    MOVN r2, #0;
+    MOVK r2, #0, LSL #32;
+    MOVN r3, #1, LSL #16;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(0, 1103515245);
+        harness.assert_reg_value(1, 1 << 48);
+        harness.assert_reg_value(2, !(0xFFFF << 32));
+        harness.assert_reg_value(3, !0x10000);
+    }
+
+    #[test]
+    fn test_strict_immediates() {
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.strict_immediates = true;
+        let error_cnt = |cpu_config: &CPUConfig, src: &str| {
+            match load_from_string(cpu_config.clone(), src.to_string()) {
+                Ok(_) => 0,
+                Err(LoadError::AnalysisError(msg_vec)) => msg_vec.len(),
+                Err(_) => panic!("The program should parse"),
+            }
+        };
+
+        let encodable = r#"
+.text
+    MOV r0, #-1;
+    MOV r1, #65536;
+    MOV r1, #2147483647;
+    ADD r2, r0, #4095;
+    SUB r2, r2, #-4;
+    ADD r3, r0, #4096;
+    AND r4, r0, #255;
+    ORR r4, r4, #6148914691236517205;
+    LDR r5, [r0, #32760];
+    LDR r5, [r0, #-256]!;
+    LDP r6, r7, [r0, #504];
+    CCMP r0, #31, #0, EQ;
+    MOVZ r8, #65535, LSL #48;
+"#;
+        assert_eq!(error_cnt(&cpu_config, encodable), 0);
+
+        let not_encodable = r#"
+.text
+    MOV r1, #1103515245;
+    ADD r2, r0, #4097;
+    AND r4, r0, #0;
+    AND r4, r0, #5;
+    MUL r2, r0, #3;
+    LDR r5, [r0, #32768];
+    LDR r5, [r0, #257]!;
+    LDP r6, r7, [r0, #512];
+    CCMP r0, #32, #0, EQ;
+"#;
+        assert_eq!(error_cnt(&cpu_config, not_encodable), 9);
+        // without strict immediates any value is accepted
+        assert_eq!(error_cnt(&TestHarness::new_test_cpu_config(), not_encodable), 0);
+    }
+
+    #[test]
+    fn test_CLZ_CLS_RBIT_REV() {
+        let src = r#"
+.text
+    MOV r0, #1;
+    CLZ r1, r0;
+    MOV r2, #0;
+    CLZ r3, r2;
+    MOV r4, #-1;
+    CLS r5, r4;
+    MOV r6, #255;
+    CLS r7, r6;
+    CLS r8, r2;
+    RBIT r9, r0;
+    REV r10, r6;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 63);
+        harness.assert_reg_value(3, 64);
+        harness.assert_reg_value(5, 63);
+        harness.assert_reg_value(7, 55);
+        harness.assert_reg_value(8, 63);
+        harness.assert_reg_value(9, 1 << 63);
+        harness.assert_reg_value(10, 255 << 56);
+    }
+
+    #[test]
+    fn test_bitfield_instructions() {
+        // 3063 is 0xBF7
+        let src = r#"
+.text
+    MOV r0, #3063;
+    UBFX r1, r0, #4, #8;
+    SBFX r2, r0, #4, #8;
+    SBFX r3, r0, #0, #3;
+    UBFX r4, r0, #0, #64;
+    MOV r5, #-1;
+    MOV r6, #0;
+    BFI r5, r6, #8, #8;
+    SBFX r7, r5, #60, #4;
+    MOV r8, #1;
+    MOV r9, #2;
+    EXTR r10, r8, r9, #1;
+    EXTR r11, r8, r9, #0;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 0xBF);
+        harness.assert_reg_value(2, (-65i64) as DWordType);
+        harness.assert_reg_value(3, (-1i64) as DWordType);
+        harness.assert_reg_value(4, 3063);
+        harness.assert_reg_value(5, !0xFF00);
+        harness.assert_reg_value(7, (-1i64) as DWordType);
+        harness.assert_reg_value(10, (1 << 63) | 1);
+        harness.assert_reg_value(11, 2);
+    }
+
+    #[test]
+    fn test_shifted_register_operand() {
+        let src = r#"
+.data
+    var_a: .dword 10
+    var_b: .dword 20
+    var_c: .dword 30
+.text
+    MOV r0, =var_a;
+    MOV r1, #2;
+    ADD r2, r0, r1, LSL #3;
+    LDR r3, [r2];
+    MOV r4, #100;
+    SUB r5, r4, r1, LSL #4;
+    MOV r6, #-64;
+    ADD r7, r4, r6, ASR #2;
+    EOR r8, r1, r1, ROR #1;
+    ORR r9, r1, r4, LSR #2;
+    SUBS r10, r4, r1, LSL #5;
+    CSET r11, GT;
+    CMP r4, r1, LSL #6;
+    CSET r12, LT;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(3, 30);
+        harness.assert_reg_value(5, 68);
+        harness.assert_reg_value(7, 84);
+        harness.assert_reg_value(8, 2 | 1);
+        harness.assert_reg_value(9, 27);
+        harness.assert_reg_value(10, 36);
+        harness.assert_reg_value(11, 1);
+        harness.assert_reg_value(12, 1);
+        // the shifted register itself isn't modified
+        harness.assert_reg_value(1, 2);
+    }
+
+    #[test]
+    fn test_shift_names_as_labels() {
+        let src = r#"
+.data
+    var_a: .dword 2
+    var_b: .dword 3
+.text
+    MOV r0, =var_a;
+    MOV r1, #1;
+    LDR r2, [r0, r1, lsl #3];
+    LSL r3, r2, #2;
+    ADD r4, r2, r3, asr #1;
+    B lsl;
+    MOV r5, #1;
+lsl:
+    CBNZ r4, asr;
+    MOV r6, #1;
+asr:
+    MOV r7, #1;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, 3);
+        harness.assert_reg_value(3, 12);
+        harness.assert_reg_value(4, 9);
+        harness.assert_reg_value(5, 0);
+        harness.assert_reg_value(6, 0);
+        harness.assert_reg_value(7, 1);
+    }
+
+    #[test]
+    fn test_branchless_fewer_mispredictions() {
+        // counts the pseudo random numbers that have bit 16 set; once with a branch and once with CSINC
+        let src = |count_instrs: &str| format!(r#"
+.text
+    MOV r0, #12345;
+    MOV r1, #1103515245;
+    MOV r2, #2147483647;
+    MOV r3, #200;
+    MOV r4, #0;
+_loop:
+    MUL r0, r0, r1;
+    ADD r0, r0, #12345;
+    AND r0, r0, r2;
+    AND r5, r0, #65536;
+{}
+    SUB r3, r3, #1;
+    CBNZ r3, _loop;
+"#, count_instrs);
+
+        let mut branchy = TestHarness::default();
+        branchy.run(&src("    CBZ r5, _skip;\n    ADD r4, r4, #1;\n_skip:"));
+
+        let mut branchless = TestHarness::default();
+        branchless.run(&src("    CMP r5, #0;\n    CSINC r4, r4, r4, EQ;"));
+
+        let count = branchy.cpu().threads[0].arch_reg_file.borrow().get_value(4);
+        assert!(count > 0 && count < 200);
+        branchless.assert_reg_value(4, count);
+        assert!(branchless.perf_counters().branch_miss_prediction_cnt < branchy.perf_counters().branch_miss_prediction_cnt);
+    }
+
+    #[test]
+    fn test_stack_push_pop() {
+        let src = r#"
+.text
+    MOV r0, #1;
+    MOV r1, #2;
+    MOV r2, #3;
+    MOV r3, sp;
+    STP r0, r1, [sp, #-16]!;
+    STR r2, [sp, #-8]!;
+    MOV r0, #0;
+    MOV r1, #0;
+    MOV r2, #0;
+    LDR r4, [sp], #8;
+    LDP r5, r6, [sp], #16;
+    SUB r7, r3, sp;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(4, 3);
+        harness.assert_reg_value(5, 1);
+        harness.assert_reg_value(6, 2);
+        harness.assert_reg_value(7, 0);
+    }
+
+    #[test]
+    fn test_LDP_STP_prologue_epilogue() {
+        let src = r#"
+.global _start
+.text
+_inc:
+    ADD r0, r0, #1;
+    RET;
+_inc_twice:
+    STP fp, lr, [sp, #-16]!;
+    MOV fp, sp;
+    BL _inc;
+    BL _inc;
+    LDP fp, lr, [sp], #16;
+    RET;
+_start:
+    MOV r0, #5;
+    MOV r1, sp;
+    BL _inc_twice;
+    BL _inc_twice;
+    SUB r2, r1, sp;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(0, 9);
+        harness.assert_reg_value(2, 0);
+    }
+
+    #[test]
+    fn test_LDP_STP_offsets() {
+        let src = r#"
+.data
+    var_a: .dword 0
+    var_b: .dword 0
+    var_c: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, #4294967297;
+    MOV r2, #7;
+    STP r1, r2, [r0, #8];
+    LDP w3, w4, [r0, #8];
+    LDP r5, r6, [r0];
+    STP w2, w2, [r0];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(4, 1);
+        harness.assert_reg_value(5, 0);
+        harness.assert_reg_value(6, 4294967297);
+        harness.assert_variable_value("var_a", 0x0000000700000007);
+        harness.assert_variable_value("var_b", 4294967297);
+        harness.assert_variable_value("var_c", 7);
+    }
+
+    #[test]
+    fn test_DMB_prevents_memory_ordering_violation() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, #10;
+    MOV r2, #1;
+    LDR r5, [r0];
+    ADD r6, r0, r5;
+    SDIV r3, r0, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    STR r1, [r3];
+    DMB ISH;
+    LDR r4, [r6];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_miss_latency = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        // the load waits for the DMB, so it can't be performed before the older store
+        harness.assert_reg_value(4, 10);
+        assert_eq!(harness.perf_counters().memory_order_nuke_cnt, 0);
+        assert!(harness.perf_counters().barrier_stall_cnt > 0);
+    }
+
+    #[test]
+    fn test_STLR_LDAR() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, #10;
+    MOV r2, #1;
+    LDR r5, [r0];
+    ADD r6, r0, r5;
+    SDIV r3, r0, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    SDIV r3, r3, r2;
+    STLR r1, [r3];
+    LDAR r4, [r6];
+    LDR r7, [r6];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_miss_latency = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+        // the load-acquire waits for the store-release and the younger load for the load-acquire
+        harness.assert_reg_value(4, 10);
+        harness.assert_reg_value(7, 10);
+        harness.assert_variable_value("var_a", 10);
+        assert_eq!(harness.perf_counters().memory_order_nuke_cnt, 0);
+    }
+
+    #[test]
+    fn test_DMB_options() {
+        for (option, store_barrier) in [("SY", true), ("ISH", true), ("ISHLD", false), ("ISHST", false)] {
+            let src = format!(r#"
+.data
+    var_a: .dword 0
+    var_b: .dword 0
+.text
+    MOV r0, =var_a;
+    MOV r1, =var_b;
+    MOV r2, #10;
+loop:
+    STR r2, [r0];
+    DMB {};
+    LDR r3, [r1];
+    SUB r2, r2, #1;
+    CBNZ r2, loop;
+"#, option);
+            let mut harness = TestHarness::default();
+            harness.run(&src);
+
+            harness.assert_variable_value("var_a", 1);
+            // only a DMB that orders the stores with the younger loads waits for the store buffer to drain
+            assert_eq!(harness.perf_counters().barrier_stall_cnt > 0, store_barrier, "DMB {}", option);
+        }
+    }
+
+    #[test]
+    fn test_barrier_option_names_as_labels() {
+        let src = r#"
+.data
+    ishld: .dword 5
+.text
+    MOV r0, =ishld;
+    LDR r1, [r0];
+    DMB ish;
+    B sy;
+    MOV r2, #1;
+sy:
+    DSB sy;
+    ADD r3, r1, #1;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, 0);
+        harness.assert_reg_value(3, 6);
+        harness.assert_variable_value("ishld", 5);
+    }
+
+    #[test]
+    fn test_ISB() {
+        let src = r#"
+.text
+    MOV r0, #1;
+    DSB SY;
+    ISB;
+    ADD r0, r0, #1;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(0, 2);
+        // the instructions after the ISB are fetched again
+        assert!(harness.perf_counters().pipeline_flushes > 0);
+    }
+
+    #[test]
+    fn test_ISB_in_subroutine() {
+        let src = r#"
+.text
+    MOV r0, #3;
+_loop:
+    BL _sub;
+    SUB r0, r0, #1;
+    CBNZ r0, _loop;
+    B _end;
+_sub:
+    ADD r1, r1, #1;
+    ISB;
+    RET;
+_end:
+    NOP;
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 3);
+        assert!(harness.perf_counters().pipeline_flushes > 0);
+        // the flush restores the RAS, so the returns are still predicted correctly; only the
+        // loop branch is mispredicted when it exits
+        assert_eq!(harness.perf_counters().branch_miss_prediction_cnt, 1);
+    }
+
+    #[test]
+    fn test_l1d_hit_after_miss() {
+        let src = r#"
+.data
+    var_a: .dword 0
+.text
+    MOV r0, =var_a;
+    LDR r1, [r0];
+    ADD r2, r0, r1;
+    LDR r3, [r2];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        let memory_subsystem = harness.cpu().memory_subsystem.borrow();
+        assert_eq!(memory_subsystem.l1d.borrow().miss_cnt, 1);
+        assert_eq!(memory_subsystem.l1d.borrow().hit_cnt, 1);
+    }
+
+    #[test]
+    fn test_line_fill_buffer_limit() {
+        let src = r#"
+.text
+    MOV r0, #0;
+    MOV r1, #64;
+    LDR r2, [r0];
+    LDR r3, [r1];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        // the second load needs to wait for the line fill buffer of the first load
+        cpu_config.lfb_count = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        let memory_subsystem = harness.cpu().memory_subsystem.borrow();
+        assert_eq!(memory_subsystem.l1d.borrow().miss_cnt, 2);
+        let uncore = memory_subsystem.uncore.borrow();
+        for cache in &uncore.outer_caches {
+            assert_eq!(cache.miss_cnt, 2);
+        }
+        // both lines are in the same DRAM row
+        assert_eq!(uncore.dram.row_miss_cnt, 1);
+        assert_eq!(uncore.dram.row_hit_cnt, 1);
+    }
+
+    #[test]
+    fn test_non_blocking_loads() {
+        let src = r#"
+.text
+    MOV r0, #0;
+    MOV r1, #64;
+    LDR r2, [r0];
+    LDR r3, [r1];
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        // the second load can use the port while the first one is waiting for its miss
+        cpu_config.ports.load = 1;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        let memory_subsystem = harness.cpu().memory_subsystem.borrow();
+        assert_eq!(memory_subsystem.l1d.borrow().miss_cnt, 2);
+        // both misses have been outstanding at the same time
+        assert!(memory_subsystem.mshr_occupied_sum > memory_subsystem.mshr_busy_cycle_cnt);
+    }
+
+    #[test]
+    fn test_stride_prefetcher() {
+        // loads a word from every line; the divide makes sure the loads are spread out in time
+        let src = r#"
+.text
+    MOV r0, #16;
+    MOV r1, #0;
+    MOV r3, #1;
+loop:
+    SDIV r3, r3, r3;
+    ADD r4, r1, r3;
+    LDR r2, [r4];
+    ADD r1, r1, #64;
+    SUB r0, r0, #1;
+    CMP r0, #0;
+    BNE loop;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.memory_size = 8192;
+        cpu_config.outer_caches.clear();
+        cpu_config.dram.row_hit_latency = 10;
+        cpu_config.dram.row_miss_latency = 10;
+        cpu_config.prefetcher.prefetcher_type = PrefetcherType::STRIDE;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        harness.assert_reg_value(1, 1024);
+        let memory_subsystem = harness.cpu().memory_subsystem.borrow();
+        assert!(memory_subsystem.prefetch_issued_cnt > 0);
+        assert!(memory_subsystem.prefetch_useful_cnt > 0);
+        // only the loads before the stride is detected miss the L1D
+        assert!(memory_subsystem.l1d.borrow().miss_cnt < 4);
+    }
+
+    #[test]
+    fn test_core_id() {
+        let src = r#"
+.text
+    MRS r0, MPIDR_EL1;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.core_count = 2;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        harness.assert_core_reg_value(0, 0, 0);
+        harness.assert_core_reg_value(1, 0, 1);
+    }
+
+    #[test]
+    fn test_coherence() {
+        let src = r#"
+.data
+    flag: .dword 0
+.text
+    MOV r1, =flag;
+    MRS r0, MPIDR_EL1;
+    CBZ r0, core0;
+core1:
+    LDR r2, [r1];
+    CBZ r2, core1;
+    B end;
+core0:
+    MOV r2, #42;
+    STR r2, [r1];
+end:
+    NOP;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.core_count = 2;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        // core 1 sees the store of core 0
+        harness.assert_core_reg_value(1, 2, 42);
+        harness.assert_variable_value("flag", 42);
+
+        let uncore = harness.system.as_ref().unwrap().uncore.borrow();
+        // the store of core 0 invalidated the line in the L1D of core 1 and core 1 got the
+        // modified line from the L1D of core 0.
+        assert!(uncore.invalidation_cnt > 0);
+        assert!(uncore.cache_to_cache_cnt > 0);
+    }
+
+    #[test]
+    fn test_smt_thread_id() {
+        let src = r#"
+.text
+    MRS r0, MPIDR_EL1;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.core_count = 2;
+        cpu_config.thread_count = 2;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        // affinity level 0 is the thread id and affinity level 1 the core id
+        harness.assert_thread_reg_value(0, 0, 0, 0x000);
+        harness.assert_thread_reg_value(0, 1, 0, 0x001);
+        harness.assert_thread_reg_value(1, 0, 0, 0x100);
+        harness.assert_thread_reg_value(1, 1, 0, 0x101);
+    }
+
+    #[test]
+    fn test_smt() {
+        let src = r#"
+.text
+    MRS r0, MPIDR_EL1;
+    MOV r1, #1;
+    ADD r1, r1, r0;
+    MOV r2, #10;
+    MOV r3, #0;
+loop:
+    ADD r3, r3, r1;
+    SUB r2, r2, #1;
+    CBNZ r2, loop;
+"#;
+        for fetch_policy in [FetchPolicy::ROUNDROBIN, FetchPolicy::ICOUNT] {
+            for rob_partitioning in [ROBPartitioning::STATIC, ROBPartitioning::DYNAMIC] {
+                let mut cpu_config = TestHarness::new_test_cpu_config();
+                cpu_config.thread_count = 2;
+                cpu_config.fetch_policy = fetch_policy.clone();
+                cpu_config.rob_partitioning = rob_partitioning;
+
+                let mut harness = TestHarness::new(cpu_config);
+                harness.run(src);
+
+                harness.assert_thread_reg_value(0, 0, 3, 10);
+                harness.assert_thread_reg_value(0, 1, 3, 20);
+
+                let perf_counters = harness.perf_counters();
+                assert!(perf_counters.threads.iter().all(|thread| thread.retired_cnt > 0));
+                let thread_retired_cnt: u64 = perf_counters.threads.iter().map(|thread| thread.retired_cnt).sum();
+                assert_eq!(thread_retired_cnt, perf_counters.retired_cnt);
+            }
+        }
+    }
+
+    #[test]
+    fn test_smt_shared_memory() {
+        let src = r#"
+.data
+    flag: .dword 0
+.text
+    MOV r1, =flag;
+    MRS r0, MPIDR_EL1;
+    CBZ r0, thread0;
+thread1:
+    LDR r2, [r1];
+    CBZ r2, thread1;
+    B end;
+thread0:
+    MOV r2, #42;
+    STR r2, [r1];
+end:
+    NOP;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.thread_count = 2;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        // thread 1 sees the store of thread 0
+        harness.assert_thread_reg_value(0, 1, 2, 42);
+        harness.assert_variable_value("flag", 42);
+    }
+
+    #[test]
+    fn test_LDXR_STXR_counter() {
+        let src = r#"
+.data
+    counter: .dword 0
+.text
+    MOV r0, =counter;
+    MOV r1, #10;
+loop:
+    LDXR r2, [r0];
+    ADD r2, r2, #1;
+    STXR r3, r2, [r0];
+    CBNZ r3, loop;
+    SUB r1, r1, #1;
+    CBNZ r1, loop;
+"#;
+        let mut cpu_config = TestHarness::new_test_cpu_config();
+        cpu_config.core_count = 2;
+
+        let mut harness = TestHarness::new(cpu_config);
+        harness.run(src);
+
+        // no increment got lost
+        harness.assert_variable_value("counter", 20);
+    }
+
+    #[test]
+    fn test_STXR_fails_after_CLREX() {
+        let src = r#"
+.data
+    var_a: .dword 5
+.text
+    MOV r0, =var_a;
+    LDXR r1, [r0];
+    CLREX;
+    MOV r2, #7;
+    STXR r3, r2, [r0];
+    STXR r4, r2, [r0];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(1, 5);
+        // the monitor was cleared by CLREX; and a STXR without a preceding LDXR fails as well
+        harness.assert_reg_value(3, 1);
+        harness.assert_reg_value(4, 1);
+        harness.assert_variable_value("var_a", 5);
+    }
+
+    #[test]
+    fn test_LDADD_counter() {
+        let src = r#"
+.data
+    counter: .dword 0
+.text
+    MOV r0, =counter;
+    MOV r1, #10;
+    MOV r2, #1;
+loop:
+    LDADD r2, r3, [r0];
+    SUB r1, r1, #1;
+    CBNZ r1, loop;
+"#;
+        for thread_count in [1, 2] {
+            let mut cpu_config = TestHarness::new_test_cpu_config();
+            cpu_config.core_count = 2;
+            cpu_config.thread_count = thread_count;
+
+            let mut harness = TestHarness::new(cpu_config);
+            harness.run(src);
+
+            harness.assert_variable_value("counter", 20 * thread_count as DWordType);
+        }
+    }
+
+    #[test]
+    fn test_SWP() {
+        let src = r#"
+.data
+    var_a: .dword 5
+.text
+    MOV r0, =var_a;
+    MOV r1, #7;
+    SWP r1, r2, [r0];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        harness.assert_reg_value(2, 5);
+        harness.assert_variable_value("var_a", 7);
+    }
+
+    #[test]
+    fn test_CAS() {
+        let src = r#"
+.data
+    var_a: .dword 5
+    var_b: .dword 5
+.text
+    MOV r0, =var_a;
+    MOV r1, #5;
+    MOV r2, #7;
+    CAS r1, r2, [r0];
+    MOV r3, =var_b;
+    MOV r4, #6;
+    CAS r4, r2, [r3];
+"#;
+        let mut harness = TestHarness::default();
+        harness.run(src);
+
+        // the compare succeeded
+        harness.assert_reg_value(1, 5);
+        harness.assert_variable_value("var_a", 7);
+        // the compare failed
+        harness.assert_reg_value(4, 5);
+        harness.assert_variable_value("var_b", 5);
+    }
+
+    struct TestHarness {
+        program: Option<Rc<Program>>,
+        system: Option<System>,
+        cpu_config: CPUConfig,
+    }
+
+    impl TestHarness {
+        fn default() -> TestHarness {
+            Self::new(Self::new_test_cpu_config())
+        }
+
+        fn new(cpu_config: CPUConfig) -> TestHarness {
+            TestHarness {
+                program: None,
+                system: Some(System::new(&cpu_config)),
+                cpu_config: cpu_config,
+            }
+        }
+
+        fn new_test_cpu_config() -> CPUConfig {
+            let mut cpu_config = CPUConfig::default();
+            cpu_config.frequency_hz = 1000;
+            cpu_config
+        }
+
+        fn run(&mut self, src: &str) {
+            self.program = Some(self.load_program(src));
+            let program = Rc::clone(self.program.as_ref().unwrap());
+            self.system.as_mut().unwrap().run(&program);
+        }
+
+        fn load_program(&mut self, src: &str) -> Rc<Program> {
+            let load_result = load_from_string(self.cpu_config.clone(), src.to_string());
+            let program = match load_result {
+                Ok(p) => Rc::new(p),
+                Err(err) => {
+                    match err {
+                        LoadError::ParseError(msg) => {
+                            println!("{}", msg);
+                            assert!(false);
+                            unreachable!();
+                        }
+
+                        LoadError::AnalysisError(msg_vec) => {
+                            for msg in msg_vec {
+                                println!("{}", msg);
+                            }
+                            assert!(false);
+                            unreachable!();
+                        }
+                        LoadError::NotFoundError(msg) => {
+                            println!("{}", msg);
+                            unreachable!();
+                        }
+                        LoadError::IOError(msg) => {
+                            println!("{}", msg);
+                            unreachable!();
+                        }
+                    }
+                }
+            };
+            program
+        }
+
+        // the first core
+        fn cpu(&self) -> &CPU {
+            &self.system.as_ref().expect("System is not initialized").cores[0]
+        }
+
+        fn perf_counters(&self) -> Ref<'_, PerfCounters> {
+            self.cpu().perf_counters.borrow()
+        }
+
+        fn assert_reg_value(&self, reg: RegisterType, value: DWordType) {
+            self.assert_core_reg_value(0, reg, value);
+        }
+
+        fn assert_core_reg_value(&self, core_id: usize, reg: RegisterType, value: DWordType) {
+            self.assert_thread_reg_value(core_id, 0, reg, value);
+        }
+
+        fn assert_thread_reg_value(&self, core_id: usize, thread_id: usize, reg: RegisterType, value: DWordType) {
+            if let Some(ref system) = self.system {
+                let reg_file = system.cores[core_id].threads[thread_id].arch_reg_file.borrow();
+                assert_eq!(reg_file.get_value(reg), value);
+            } else {
+                panic!("System is not initialized");
+            }
+        }
+
+        fn assert_variable_value(&self, name: &str, value: DWordType) {
+            if let Some(ref system) = self.system {
+                let program = self.program.as_ref().expect("Program not initialized");
+                let data_item = program.data_items.get(name).expect("Data item not found");
+                let offset = data_item.offset;
+                let uncore = system.uncore.borrow();
+                let actual_value = uncore.read(offset, WORD_SIZE as u8);
+                assert_eq!(actual_value, value, "Variable '{}' does not have the expected value", name);
             } else {
-                panic!("CPU is not initialized");
+                panic!("System is not initialized");
             }
         }
     }
@@ -0,0 +1,389 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::{BranchPredictorConfig, BranchPredictorType};
+
+/// A predictor for the direction of conditional branches.
+///
+/// The frontend asks for a prediction when it fetches a conditional branch and the backend
+/// trains the predictor when the branch retires and the actual outcome is known.
+pub(crate) trait BranchPredictor {
+    /// Predicts if the conditional branch at pc with the given target is taken.
+    fn predict(&self, pc: usize, target: usize) -> bool;
+
+    /// Trains the predictor with the actual outcome of the conditional branch at pc.
+    fn update(&mut self, pc: usize, taken: bool);
+}
+
+pub(crate) fn create_branch_predictor(config: &BranchPredictorConfig) -> Rc<RefCell<dyn BranchPredictor>> {
+    match config.predictor_type {
+        BranchPredictorType::STATIC => Rc::new(RefCell::new(StaticBranchPredictor {})),
+        BranchPredictorType::BIMODAL => Rc::new(RefCell::new(BimodalBranchPredictor::new(config.pht_size))),
+        BranchPredictorType::GSHARE => Rc::new(RefCell::new(GShareBranchPredictor::new(config.pht_size, config.history_bits))),
+        BranchPredictorType::TAGE => Rc::new(RefCell::new(TageBranchPredictor::new(config))),
+    }
+}
+
+/// A static branch predictor that will speculate that backwards branches are taken.
+pub(crate) struct StaticBranchPredictor {}
+
+impl BranchPredictor for StaticBranchPredictor {
+    fn predict(&self, pc: usize, target: usize) -> bool {
+        target < pc
+    }
+
+    fn update(&mut self, _pc: usize, _taken: bool) {}
+}
+
+// The 2-bit saturating counter values; a value of 2 or more predicts taken.
+const COUNTER_MAX: u8 = 3;
+const COUNTER_WEAKLY_TAKEN: u8 = 2;
+
+fn counter_update(counter: &mut u8, taken: bool) {
+    if taken {
+        if *counter < COUNTER_MAX {
+            *counter += 1;
+        }
+    } else if *counter > 0 {
+        *counter -= 1;
+    }
+}
+
+/// A table of 2-bit saturating counters indexed by the pc of the branch.
+pub(crate) struct BimodalBranchPredictor {
+    counters: Vec<u8>,
+}
+
+impl BimodalBranchPredictor {
+    pub(crate) fn new(size: u32) -> Self {
+        assert!(size > 0, "BimodalBranchPredictor: size should be larger than 0");
+
+        BimodalBranchPredictor { counters: vec![COUNTER_WEAKLY_TAKEN; size as usize] }
+    }
+
+    fn index(&self, pc: usize) -> usize {
+        pc % self.counters.len()
+    }
+}
+
+impl BranchPredictor for BimodalBranchPredictor {
+    fn predict(&self, pc: usize, _target: usize) -> bool {
+        self.counters[self.index(pc)] >= COUNTER_WEAKLY_TAKEN
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let index = self.index(pc);
+        counter_update(&mut self.counters[index], taken);
+    }
+}
+
+/// A table of 2-bit saturating counters indexed by the pc of the branch XOR the global history
+/// of the most recent branch outcomes.
+///
+/// The global history is updated when branches retire, so the history used for a prediction
+/// doesn't contain the outcome of the branches that are still in flight.
+pub(crate) struct GShareBranchPredictor {
+    counters: Vec<u8>,
+    history: u64,
+    history_mask: u64,
+}
+
+impl GShareBranchPredictor {
+    pub(crate) fn new(size: u32, history_bits: u8) -> Self {
+        assert!(size > 0, "GShareBranchPredictor: size should be larger than 0");
+
+        let history_mask = if history_bits >= 64 { u64::MAX } else { (1u64 << history_bits) - 1 };
+
+        GShareBranchPredictor {
+            counters: vec![COUNTER_WEAKLY_TAKEN; size as usize],
+            history: 0,
+            history_mask,
+        }
+    }
+
+    fn index(&self, pc: usize) -> usize {
+        ((pc as u64 ^ self.history) % self.counters.len() as u64) as usize
+    }
+}
+
+impl BranchPredictor for GShareBranchPredictor {
+    fn predict(&self, pc: usize, _target: usize) -> bool {
+        self.counters[self.index(pc)] >= COUNTER_WEAKLY_TAKEN
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let index = self.index(pc);
+        counter_update(&mut self.counters[index], taken);
+        self.history = ((self.history << 1) | taken as u64) & self.history_mask;
+    }
+}
+
+// The range of the 3-bit signed prediction counter of a tagged entry.
+const TAGE_CTR_MAX: i8 = 3;
+const TAGE_CTR_MIN: i8 = -4;
+// The maximum value of the 2-bit useful counter of a tagged entry.
+const TAGE_USEFUL_MAX: u8 = 3;
+// The number of updates after which the useful counters are aged.
+const TAGE_USEFUL_RESET_PERIOD: u64 = 256 * 1024;
+
+#[derive(Clone, Copy)]
+struct TageEntry {
+    tag: u16,
+    ctr: i8,
+    useful: u8,
+}
+
+struct TageTable {
+    entries: Vec<TageEntry>,
+    history_length: u32,
+}
+
+/// A TAGE (TAgged GEometric history length) predictor.
+///
+/// It consists of a bimodal base predictor and a number of tagged tables that are indexed
+/// with increasingly longer global histories. The prediction is provided by the matching
+/// table with the longest history; if no table matches, the base predictor is used.
+///
+/// Just like the gshare predictor, the global history is updated when branches retire.
+pub(crate) struct TageBranchPredictor {
+    base: BimodalBranchPredictor,
+    tables: Vec<TageTable>,
+    tag_bits: u8,
+    // bit 0 is the most recent outcome.
+    history: u128,
+    update_cnt: u64,
+}
+
+// The result of a lookup in the tagged tables.
+struct TageLookup {
+    // the table and entry index of the longest matching table.
+    provider: Option<(usize, usize)>,
+    // the prediction of the longest matching table.
+    provider_pred: bool,
+    // the prediction if the provider would not have matched.
+    alt_pred: bool,
+}
+
+impl TageBranchPredictor {
+    pub(crate) fn new(config: &BranchPredictorConfig) -> Self {
+        assert!(config.tage_table_size > 0, "TageBranchPredictor: tage_table_size should be larger than 0");
+        // the tag is folded into bits - 1 bits as well and has to fit the u16 of an entry
+        assert!((2..=16).contains(&config.tage_tag_bits), "TageBranchPredictor: tage_tag_bits should be 2 to 16");
+
+        let table_count = config.tage_table_count as u32;
+        let min = config.tage_min_history.max(1) as f64;
+        let max = (config.tage_max_history as f64).clamp(min, 128.0);
+
+        let mut tables = Vec::with_capacity(table_count as usize);
+        for i in 0..table_count {
+            // the history lengths form a geometric series between min and max
+            let history_length = if table_count == 1 {
+                min
+            } else {
+                min * (max / min).powf(i as f64 / (table_count - 1) as f64)
+            };
+
+            tables.push(TageTable {
+                entries: vec![TageEntry { tag: 0, ctr: 0, useful: 0 }; config.tage_table_size as usize],
+                history_length: history_length.round() as u32,
+            });
+        }
+
+        TageBranchPredictor {
+            base: BimodalBranchPredictor::new(config.pht_size),
+            tables,
+            tag_bits: config.tage_tag_bits,
+            history: 0,
+            update_cnt: 0,
+        }
+    }
+
+    // Folds the most recent history_length bits of the global history into 'bits' bits.
+    fn fold_history(&self, history_length: u32, bits: u32) -> u64 {
+        let mut history = if history_length >= 128 {
+            self.history
+        } else {
+            self.history & ((1u128 << history_length) - 1)
+        };
+
+        let mut folded = 0u64;
+        while history != 0 {
+            folded ^= (history & ((1u128 << bits) - 1)) as u64;
+            history >>= bits;
+        }
+        folded
+    }
+
+    fn index(&self, table_index: usize, pc: usize) -> usize {
+        let table = &self.tables[table_index];
+        let size = table.entries.len() as u64;
+        let bits = 64 - size.leading_zeros();
+        let folded = self.fold_history(table.history_length, bits.max(1));
+        ((pc as u64 ^ (pc as u64 >> bits) ^ folded) % size) as usize
+    }
+
+    fn tag(&self, table_index: usize, pc: usize) -> u16 {
+        let table = &self.tables[table_index];
+        let bits = self.tag_bits as u32;
+        // a differently folded history is used than for the index to reduce aliasing
+        let folded = self.fold_history(table.history_length, bits) ^ (self.fold_history(table.history_length, bits - 1) << 1);
+        ((pc as u64 ^ folded) & ((1u64 << bits) - 1)) as u16
+    }
+
+    fn lookup(&self, pc: usize) -> TageLookup {
+        let base_pred = self.base.predict(pc, 0);
+        let mut provider = None;
+        let mut provider_pred = base_pred;
+        let mut alt_pred = base_pred;
+
+        for table_index in 0..self.tables.len() {
+            let entry_index = self.index(table_index, pc);
+            let entry = &self.tables[table_index].entries[entry_index];
+            if entry.tag == self.tag(table_index, pc) {
+                alt_pred = provider_pred;
+                provider_pred = entry.ctr >= 0;
+                provider = Some((table_index, entry_index));
+            }
+        }
+
+        TageLookup { provider, provider_pred, alt_pred }
+    }
+}
+
+impl BranchPredictor for TageBranchPredictor {
+    fn predict(&self, pc: usize, _target: usize) -> bool {
+        self.lookup(pc).provider_pred
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let lookup = self.lookup(pc);
+
+        match lookup.provider {
+            None => self.base.update(pc, taken),
+            Some((table_index, entry_index)) => {
+                let entry = &mut self.tables[table_index].entries[entry_index];
+                if taken {
+                    entry.ctr = (entry.ctr + 1).min(TAGE_CTR_MAX);
+                } else {
+                    entry.ctr = (entry.ctr - 1).max(TAGE_CTR_MIN);
+                }
+
+                // the entry is useful if it made a difference compared to the alternative prediction
+                if lookup.provider_pred != lookup.alt_pred {
+                    if lookup.provider_pred == taken {
+                        entry.useful = (entry.useful + 1).min(TAGE_USEFUL_MAX);
+                    } else {
+                        entry.useful = entry.useful.saturating_sub(1);
+                    }
+                }
+            }
+        }
+
+        // on a misprediction, allocate an entry in a table with a longer history
+        if lookup.provider_pred != taken {
+            let first_table = match lookup.provider {
+                None => 0,
+                Some((table_index, _)) => table_index + 1,
+            };
+
+            let mut allocated = false;
+            for table_index in first_table..self.tables.len() {
+                let entry_index = self.index(table_index, pc);
+                let tag = self.tag(table_index, pc);
+                let entry = &mut self.tables[table_index].entries[entry_index];
+                if entry.useful == 0 {
+                    entry.tag = tag;
+                    entry.ctr = if taken { 0 } else { -1 };
+                    allocated = true;
+                    break;
+                }
+            }
+
+            if !allocated {
+                for table_index in first_table..self.tables.len() {
+                    let entry_index = self.index(table_index, pc);
+                    let entry = &mut self.tables[table_index].entries[entry_index];
+                    entry.useful = entry.useful.saturating_sub(1);
+                }
+            }
+        }
+
+        self.update_cnt += 1;
+        if self.update_cnt.is_multiple_of(TAGE_USEFUL_RESET_PERIOD) {
+            for table in &mut self.tables {
+                for entry in &mut table.entries {
+                    entry.useful >>= 1;
+                }
+            }
+        }
+
+        self.history = (self.history << 1) | taken as u128;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A loop branch that is taken 'trip_count - 1' times and then not taken.
+    fn train_loop(predictor: &mut dyn BranchPredictor, trip_count: usize, iterations: usize) -> usize {
+        let mut miss_cnt = 0;
+        for _ in 0..iterations {
+            for k in 0..trip_count {
+                let taken = k != trip_count - 1;
+                if predictor.predict(100, 50) != taken {
+                    miss_cnt += 1;
+                }
+                predictor.update(100, taken);
+            }
+        }
+        miss_cnt
+    }
+
+    #[test]
+    fn test_static() {
+        let predictor = StaticBranchPredictor {};
+        assert!(predictor.predict(10, 5));
+        assert!(!predictor.predict(10, 15));
+    }
+
+    #[test]
+    fn test_bimodal() {
+        let mut predictor = BimodalBranchPredictor::new(16);
+        for _ in 0..4 {
+            predictor.update(3, false);
+        }
+        assert!(!predictor.predict(3, 0));
+        predictor.update(3, true);
+        assert!(!predictor.predict(3, 0));
+        predictor.update(3, true);
+        assert!(predictor.predict(3, 0));
+    }
+
+    #[test]
+    fn test_gshare_learns_loop_exit() {
+        let mut predictor = GShareBranchPredictor::new(4096, 8);
+        train_loop(&mut predictor, 4, 100);
+        assert_eq!(train_loop(&mut predictor, 4, 10), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "size should be larger than 0")]
+    fn test_gshare_empty_table() {
+        GShareBranchPredictor::new(0, 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "tage_tag_bits should be 2 to 16")]
+    fn test_tage_invalid_tag_bits() {
+        let config = BranchPredictorConfig { tage_tag_bits: 1, ..BranchPredictorConfig::default() };
+        TageBranchPredictor::new(&config);
+    }
+
+    #[test]
+    fn test_tage_learns_loop_exit() {
+        let mut predictor = TageBranchPredictor::new(&BranchPredictorConfig::default());
+        train_loop(&mut predictor, 10, 100);
+        assert_eq!(train_loop(&mut predictor, 10, 10), 0);
+    }
+}
@@ -0,0 +1,40 @@
+#[derive(Clone, Copy)]
+struct BTBEntry {
+    valid: bool,
+    pc: usize,
+    target: usize,
+}
+
+/// The Branch Target Buffer. It remembers the target of indirect branches (branches where the
+/// target is in a register) so the frontend can predict the target when the branch is fetched
+/// again. It is a direct mapped table that is indexed by the pc of the branch.
+pub(crate) struct BTB {
+    entries: Vec<BTBEntry>,
+}
+
+impl BTB {
+    pub(crate) fn new(size: u16) -> BTB {
+        BTB { entries: vec![BTBEntry { valid: false, pc: 0, target: 0 }; size.max(1) as usize] }
+    }
+
+    fn index(&self, pc: usize) -> usize {
+        pc % self.entries.len()
+    }
+
+    pub(crate) fn lookup(&self, pc: usize) -> Option<usize> {
+        let entry = &self.entries[self.index(pc)];
+        if entry.valid && entry.pc == pc {
+            Some(entry.target)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn update(&mut self, pc: usize, target: usize) {
+        let index = self.index(pc);
+        let entry = &mut self.entries[index];
+        entry.valid = true;
+        entry.pc = pc;
+        entry.target = target;
+    }
+}
@@ -1,139 +1,184 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::cpu::{ArgRegFile, CPUConfig, PC, PerfCounters, Trace};
-use crate::instructions::instructions::{DWordType, EXIT, Instr, InstrQueue, Opcode, Program};
+use crate::cpu::{CPUConfig, FetchPolicy, HardwareThread, PC, PerfCounters, Trace};
+use crate::frontend::branch_predictor::BranchPredictor;
+use crate::frontend::branch_target_buffer::BTB;
+use crate::frontend::return_address_stack::RAS;
+use crate::instructions::instructions::{DWordType, EXIT, Instr, Opcode, Program};
 
 pub(crate) struct FrontendControl {
     pub(crate) halted: bool,
+    // true if the frontend has fetched the EXIT and stopped fetching. When the pipeline
+    // is flushed, the backend resets it because the EXIT could have been fetched speculatively.
+    pub(crate) exit: bool,
+    // the number of instructions in the backend that haven't been dispatched yet; it is
+    // updated by the backend every cycle and used by the ICOUNT fetch policy.
+    pub(crate) undispatched_cnt: u16,
 }
 
 pub(crate) struct Frontend {
-    instr_queue: Rc<RefCell<InstrQueue>>,
     n_wide: u8,
-    frontend_control: Rc<RefCell<FrontendControl>>,
+    threads: Vec<HardwareThread>,
+    fetch_policy: FetchPolicy,
+    // the thread that goes first when selecting the thread to fetch from
+    next_thread: usize,
     program_option: Option<Rc<Program>>,
     trace: Trace,
-    exit: bool,
     perf_counters: Rc<RefCell<PerfCounters>>,
-    arch_reg_file: Rc<RefCell<ArgRegFile>>,
+    branch_predictor: Rc<RefCell<dyn BranchPredictor>>,
+    btb: Rc<RefCell<BTB>>,
 }
 
 impl Frontend {
     pub(crate) fn new(
         cpu_config: &CPUConfig,
-        instr_queue: &Rc<RefCell<InstrQueue>>,
-        frontend_control: &Rc<RefCell<FrontendControl>>,
+        threads: &[HardwareThread],
         perf_counters: &Rc<RefCell<PerfCounters>>,
-        arch_reg_file: &Rc<RefCell<ArgRegFile>>,
+        branch_predictor: &Rc<RefCell<dyn BranchPredictor>>,
+        btb: &Rc<RefCell<BTB>>,
     ) -> Frontend {
         Frontend {
-            instr_queue: Rc::clone(instr_queue),
             n_wide: cpu_config.frontend_n_wide,
+            threads: threads.to_vec(),
+            fetch_policy: cpu_config.fetch_policy.clone(),
+            next_thread: 0,
             program_option: None,
             trace: cpu_config.trace.clone(),
-            frontend_control: Rc::clone(frontend_control),
-            exit: false,
             perf_counters: Rc::clone(perf_counters),
-            arch_reg_file: Rc::clone(arch_reg_file),
+            branch_predictor: Rc::clone(branch_predictor),
+            btb: Rc::clone(btb),
         }
     }
 
     pub(crate) fn init(&mut self, program: &Rc<Program>) {
         self.program_option = Some(Rc::clone(program));
-        self.arch_reg_file.borrow_mut().set_value(PC, program.entry_point as DWordType);
+        for thread in &self.threads {
+            thread.arch_reg_file.borrow_mut().set_value(PC, program.entry_point as DWordType);
+        }
+    }
+
+    // Selects the hardware thread to fetch from in this cycle; None if no thread can fetch.
+    // Ties are broken in round robin order.
+    fn select_thread(&mut self) -> Option<usize> {
+        let thread_count = self.threads.len();
+        let mut candidates = (0..thread_count)
+            .map(|k| (self.next_thread + k) % thread_count)
+            .filter(|thread_id| {
+                let thread = &self.threads[*thread_id];
+                let frontend_control = thread.frontend_control.borrow();
+                !frontend_control.halted && !frontend_control.exit && !thread.instr_queue.borrow().is_full()
+            });
+
+        let thread_id = match self.fetch_policy {
+            FetchPolicy::ROUNDROBIN => candidates.next(),
+            FetchPolicy::ICOUNT => candidates.min_by_key(|thread_id| {
+                let thread = &self.threads[*thread_id];
+                thread.instr_queue.borrow().size() + thread.frontend_control.borrow().undispatched_cnt
+            }),
+        }?;
+
+        self.next_thread = (thread_id + 1) % thread_count;
+        Some(thread_id)
     }
 
     pub(crate) fn do_cycle(&mut self) {
-        match &self.program_option {
-            None => return,
-            Some(program) => {
-                let mut instr_queue = self.instr_queue.borrow_mut();
-                let frontend_control = self.frontend_control.borrow_mut();
-                let mut perf_counters = self.perf_counters.borrow_mut();
-                let mut arch_reg_file = self.arch_reg_file.borrow_mut();
-
-                if frontend_control.halted {
-                    return;
-                }
-
-                for _ in 0..self.n_wide {
-                    if self.exit {
-                        return;
-                    }
-
-                    if instr_queue.is_full() {
-                        break;
-                    }
-
-                    // todo: we still need mechanism to 'stall' the pipeline. E.g
-
-                    // MOV IP, 10
-                    // B foobar
-
-                    let pc = arch_reg_file.get_value(PC) as usize;
-                    let instr = if program.code.len() == pc {
-                        // at the end of the program
-                        Rc::new(EXIT)
-                    } else {
-                        program.get_instr(pc)
-                    };
-
-                    if self.trace.decode {
-                        println!("Frontend: pc: {}  '{}'", pc, instr);
-                    }
-
-                    if instr.opcode == Opcode::EXIT {
-                        self.exit = true;
-                    }
-
-                    let tail_index = instr_queue.tail_index();
-                    let slot = instr_queue.get_mut(tail_index);
-
-                    let pc_value_next = if instr.is_branch() {
-                        slot.branch_target_predicted = Self::predict(pc, &instr);
-                        //println!("Frontend branch predicted={}", slot.branch_target_predicted);
-                        slot.branch_target_predicted
-                    } else {
-                        pc + 1
-                    };
-                    arch_reg_file.set_value(PC, pc_value_next as DWordType);
-
-                    slot.instr = instr;
-                    slot.pc = pc;
-                    instr_queue.tail_bump();
-                    perf_counters.decode_cnt += 1;
-                }
+        let Some(program) = self.program_option.clone() else {
+            return;
+        };
+
+        let Some(thread_id) = self.select_thread() else {
+            return;
+        };
+
+        let thread = &self.threads[thread_id];
+        let mut instr_queue = thread.instr_queue.borrow_mut();
+        let mut frontend_control = thread.frontend_control.borrow_mut();
+        let mut perf_counters = self.perf_counters.borrow_mut();
+        let mut arch_reg_file = thread.arch_reg_file.borrow_mut();
+
+        for _ in 0..self.n_wide {
+            if frontend_control.exit {
+                return;
             }
+
+            if instr_queue.is_full() {
+                break;
+            }
+
+            // todo: we still need mechanism to 'stall' the pipeline. E.g
+
+            // MOV IP, 10
+            // B foobar
+
+            let pc = arch_reg_file.get_value(PC) as usize;
+            let instr = if program.code.len() == pc {
+                // at the end of the program
+                Rc::new(EXIT)
+            } else {
+                program.get_instr(pc)
+            };
+
+            if self.trace.decode {
+                println!("Frontend: pc: {}  '{}'", pc, instr);
+            }
+
+            if instr.opcode == Opcode::EXIT {
+                frontend_control.exit = true;
+            }
+
+            let tail_index = instr_queue.tail_index();
+            let slot = instr_queue.get_mut(tail_index);
+
+            let pc_value_next = if instr.is_branch() {
+                slot.branch_target_predicted = self.predict(&thread.ras, pc, &instr);
+                //println!("Frontend branch predicted={}", slot.branch_target_predicted);
+                slot.branch_target_predicted
+            } else {
+                pc + 1
+            };
+            // not only a mispredicted branch restores the RAS, but also a nuke or ISB that restarts
+            // the thread after this instruction
+            slot.ras_checkpoint = thread.ras.borrow().checkpoint();
+            arch_reg_file.set_value(PC, pc_value_next as DWordType);
+
+            slot.instr = instr;
+            slot.pc = pc;
+            instr_queue.tail_bump();
+            perf_counters.decode_cnt += 1;
         }
     }
 
-    // A static branch predictor that will speculate that backwards branches are taken.
-    // In the future better branch predictors can be added.
-    fn predict(ip: usize, instr: &Instr) -> usize {
+    // Predicts the next pc after the branch. Unconditional branches with a target encoded in the
+    // instruction are always predicted correctly; the direction of conditional branches is
+    // determined by the branch predictor. The target of subroutine returns is predicted using
+    // the RAS and the target of other indirect branches using the BTB.
+    fn predict(&self, ras: &RefCell<RAS>, pc: usize, instr: &Instr) -> usize {
         let branch_target = match instr.opcode {
-            Opcode::B |
+            Opcode::B => {
+                return instr.source[0].get_code_address() as usize;
+            }
             Opcode::BL => {
+                ras.borrow_mut().push(pc + 1);
                 return instr.source[0].get_code_address() as usize;
             }
-            Opcode::RET => 0,
-            Opcode::BX => 0,
+            Opcode::RET |
+            Opcode::BX => {
+                let ras_target = if instr.is_return() { ras.borrow_mut().pop() } else { None };
+                return ras_target
+                    .or_else(|| self.btb.borrow().lookup(pc))
+                    .unwrap_or(pc + 1);
+            }
             Opcode::CBNZ |
             Opcode::CBZ => instr.source[1].get_code_address() as usize,
-            Opcode::BNE |
-            Opcode::BLE |
-            Opcode::BLT |
-            Opcode::BGE |
-            Opcode::BGT |
-            Opcode::BEQ => instr.source[0].get_code_address() as usize,
+            Opcode::BCOND => instr.source[0].get_code_address() as usize,
             _ => unreachable!(),
         };
 
-        if branch_target < ip {
-            // backwards branches are always taken
+        if self.branch_predictor.borrow().predict(pc, branch_target) {
             branch_target
         } else {
-            ip + 1
+            pc + 1
         }
     }
 }
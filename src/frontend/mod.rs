@@ -1 +1,4 @@
-pub mod frontend;
\ No newline at end of file
+pub mod frontend;
+pub mod branch_predictor;
+pub mod branch_target_buffer;
+pub mod return_address_stack;
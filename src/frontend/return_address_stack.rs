@@ -0,0 +1,93 @@
+/// The state of the RAS that is needed to undo the speculative pushes and pops done by the
+/// frontend after a branch was fetched.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RASCheckpoint {
+    tos: u16,
+    size: u16,
+    top: usize,
+}
+
+/// The Return Address Stack. BL pushes the return address and RET/BX lr pops it so the target
+/// of a subroutine return can be predicted.
+///
+/// The RAS is updated speculatively by the frontend. Every branch carries a checkpoint so that
+/// the RAS can be repaired when the branch turns out to be mispredicted. The stack is circular;
+/// so when it overflows, the oldest return addresses are overwritten.
+pub(crate) struct RAS {
+    entries: Vec<usize>,
+    // the index of the top of stack.
+    tos: u16,
+    // the number of valid entries.
+    size: u16,
+}
+
+impl RAS {
+    pub(crate) fn new(capacity: u16) -> RAS {
+        RAS { entries: vec![0; capacity.max(1) as usize], tos: 0, size: 0 }
+    }
+
+    fn capacity(&self) -> u16 {
+        self.entries.len() as u16
+    }
+
+    pub(crate) fn push(&mut self, return_address: usize) {
+        self.tos = (self.tos + 1) % self.capacity();
+        self.entries[self.tos as usize] = return_address;
+        if self.size < self.capacity() {
+            self.size += 1;
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<usize> {
+        if self.size == 0 {
+            return None;
+        }
+
+        let return_address = self.entries[self.tos as usize];
+        self.tos = (self.tos + self.capacity() - 1) % self.capacity();
+        self.size -= 1;
+        Some(return_address)
+    }
+
+    pub(crate) fn checkpoint(&self) -> RASCheckpoint {
+        RASCheckpoint { tos: self.tos, size: self.size, top: self.entries[self.tos as usize] }
+    }
+
+    pub(crate) fn restore(&mut self, checkpoint: &RASCheckpoint) {
+        self.tos = checkpoint.tos;
+        self.size = checkpoint.size;
+        // the top entry could have been overwritten by a speculative push
+        self.entries[self.tos as usize] = checkpoint.top;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_pop() {
+        let mut ras = RAS::new(4);
+        assert_eq!(ras.pop(), None);
+        ras.push(10);
+        ras.push(20);
+        assert_eq!(ras.pop(), Some(20));
+        assert_eq!(ras.pop(), Some(10));
+        assert_eq!(ras.pop(), None);
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut ras = RAS::new(4);
+        ras.push(10);
+        let checkpoint = ras.checkpoint();
+
+        // speculative pop followed by a push that overwrites the entry
+        ras.pop();
+        ras.push(30);
+
+        ras.restore(&checkpoint);
+        assert_eq!(ras.pop(), Some(10));
+        assert_eq!(ras.pop(), None);
+    }
+}
@@ -2,13 +2,15 @@ use std::collections::HashMap;
 use std::fmt;
 use std::rc::Rc;
 
+use serde::Deserialize;
 use Operand::Memory;
 
-use crate::cpu::{CPSR, SP};
+use crate::cpu::{CPSR, SP, ZR};
+use crate::frontend::return_address_stack::RASCheckpoint;
 use crate::cpu::FP;
 use crate::cpu::LR;
 use crate::cpu::PC;
-use crate::instructions::instructions::Operand::{Code, Immediate, MemRegisterIndirect, Register, Unused};
+use crate::instructions::instructions::Operand::{BarrierOption, Code, Condition, Immediate, MemPreIndexed, MemRegIndirectWithOffset, MemRegIndirectWithRegOffset, MemRegisterIndirect, Register, SystemRegister, Unused, WRegister};
 
 #[derive(Debug, Clone, Copy)]
 pub struct SourceLocation {
@@ -22,7 +24,7 @@ impl fmt::Display for SourceLocation {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
 pub enum Opcode {
     ADD,
     SUB,
@@ -49,13 +51,60 @@ pub enum Opcode {
     EOR,
     MVN,
     CMP,
-    BEQ,
-    BNE,
-    BLE,
-    BLT,
-    BGE,
-    BGT,
+    // B.<cond>; BEQ, BNE, BLT, BLE, BGT and BGE are aliases
+    BCOND,
     DSB,
+    MRS,
+    LDXR,
+    STXR,
+    CLREX,
+    LDADD,
+    SWP,
+    CAS,
+    DMB,
+    ISB,
+    LDAR,
+    STLR,
+    LDRB,
+    LDRH,
+    LDRSW,
+    STRB,
+    STRH,
+    LDP,
+    STP,
+    CSEL,
+    CSET,
+    CSINC,
+    CSNEG,
+    CCMP,
+    ADDS,
+    SUBS,
+    ANDS,
+    NEGS,
+    TST,
+    CMN,
+    LSL,
+    LSR,
+    ASR,
+    ROR,
+    UDIV,
+    MADD,
+    MSUB,
+    SMULL,
+    UMULL,
+    SMULH,
+    UMULH,
+    CLZ,
+    CLS,
+    RBIT,
+    REV,
+    UBFX,
+    SBFX,
+    BFI,
+    EXTR,
+    MOVZ,
+    MOVN,
+    MOVK,
 }
 
 pub(crate) fn mnemonic(opcode: Opcode) -> &'static str {
@@ -84,13 +133,59 @@ pub(crate) fn mnemonic(opcode: Opcode) -> &'static str {
         Opcode::MVN => "MVN",
         Opcode::EXIT => "EXIT",
         Opcode::CMP => "CMP",
-        Opcode::BEQ => "BEQ",
-        Opcode::BNE => "BNE",
-        Opcode::BLE => "BLE",
-        Opcode::BLT => "BLT",
-        Opcode::BGE => "BGE",
-        Opcode::BGT => "BGT",
+        Opcode::BCOND => "B.cond",
         Opcode::DSB => "DSB",
+        Opcode::MRS => "MRS",
+        Opcode::LDXR => "LDXR",
+        Opcode::STXR => "STXR",
+        Opcode::CLREX => "CLREX",
+        Opcode::LDADD => "LDADD",
+        Opcode::SWP => "SWP",
+        Opcode::CAS => "CAS",
+        Opcode::DMB => "DMB",
+        Opcode::ISB => "ISB",
+        Opcode::LDAR => "LDAR",
+        Opcode::STLR => "STLR",
+        Opcode::LDRB => "LDRB",
+        Opcode::LDRH => "LDRH",
+        Opcode::LDRSW => "LDRSW",
+        Opcode::STRB => "STRB",
+        Opcode::STRH => "STRH",
+        Opcode::LDP => "LDP",
+        Opcode::STP => "STP",
+        Opcode::CSEL => "CSEL",
+        Opcode::CSET => "CSET",
+        Opcode::CSINC => "CSINC",
+        Opcode::CSNEG => "CSNEG",
+        Opcode::CCMP => "CCMP",
+        Opcode::ADDS => "ADDS",
+        Opcode::SUBS => "SUBS",
+        Opcode::ANDS => "ANDS",
+        Opcode::NEGS => "NEGS",
+        Opcode::TST => "TST",
+        Opcode::CMN => "CMN",
+        Opcode::LSL => "LSL",
+        Opcode::LSR => "LSR",
+        Opcode::ASR => "ASR",
+        Opcode::ROR => "ROR",
+        Opcode::UDIV => "UDIV",
+        Opcode::MADD => "MADD",
+        Opcode::MSUB => "MSUB",
+        Opcode::SMULL => "SMULL",
+        Opcode::UMULL => "UMULL",
+        Opcode::SMULH => "SMULH",
+        Opcode::UMULH => "UMULH",
+        Opcode::CLZ => "CLZ",
+        Opcode::CLS => "CLS",
+        Opcode::RBIT => "RBIT",
+        Opcode::REV => "REV",
+        Opcode::UBFX => "UBFX",
+        Opcode::SBFX => "SBFX",
+        Opcode::BFI => "BFI",
+        Opcode::EXTR => "EXTR",
+        Opcode::MOVZ => "MOVZ",
+        Opcode::MOVN => "MOVN",
+        Opcode::MOVK => "MOVK",
     }
 }
 
@@ -123,17 +218,136 @@ pub(crate) fn get_opcode(mnemonic: &str) -> Option<Opcode> {
         "BL" => Some(Opcode::BL),
         "EXIT" => Some(Opcode::EXIT),
         "CMP" => Some(Opcode::CMP),
-        "BEQ" => Some(Opcode::BEQ),
-        "BNE" => Some(Opcode::BNE),
-        "BLE" => Some(Opcode::BLE),
-        "BLT" => Some(Opcode::BLT),
-        "BGE" => Some(Opcode::BGE),
-        "BGT" => Some(Opcode::BGT),
         "DSB" => Some(Opcode::DSB),
+        "MRS" => Some(Opcode::MRS),
+        "LDXR" => Some(Opcode::LDXR),
+        "STXR" => Some(Opcode::STXR),
+        "CLREX" => Some(Opcode::CLREX),
+        "LDADD" => Some(Opcode::LDADD),
+        "SWP" => Some(Opcode::SWP),
+        "CAS" => Some(Opcode::CAS),
+        "DMB" => Some(Opcode::DMB),
+        "ISB" => Some(Opcode::ISB),
+        "LDAR" => Some(Opcode::LDAR),
+        "STLR" => Some(Opcode::STLR),
+        "LDRB" => Some(Opcode::LDRB),
+        "LDRH" => Some(Opcode::LDRH),
+        "LDRSW" => Some(Opcode::LDRSW),
+        "STRB" => Some(Opcode::STRB),
+        "STRH" => Some(Opcode::STRH),
+        "LDP" => Some(Opcode::LDP),
+        "STP" => Some(Opcode::STP),
+        "CSEL" => Some(Opcode::CSEL),
+        "CSET" => Some(Opcode::CSET),
+        "CSINC" => Some(Opcode::CSINC),
+        "CSNEG" => Some(Opcode::CSNEG),
+        "CCMP" => Some(Opcode::CCMP),
+        "ADDS" => Some(Opcode::ADDS),
+        "SUBS" => Some(Opcode::SUBS),
+        "ANDS" => Some(Opcode::ANDS),
+        "NEGS" => Some(Opcode::NEGS),
+        "TST" => Some(Opcode::TST),
+        "CMN" => Some(Opcode::CMN),
+        "LSL" => Some(Opcode::LSL),
+        "LSR" => Some(Opcode::LSR),
+        "ASR" => Some(Opcode::ASR),
+        "ROR" => Some(Opcode::ROR),
+        "UDIV" => Some(Opcode::UDIV),
+        "MADD" => Some(Opcode::MADD),
+        "MSUB" => Some(Opcode::MSUB),
+        "SMULL" => Some(Opcode::SMULL),
+        "UMULL" => Some(Opcode::UMULL),
+        "SMULH" => Some(Opcode::SMULH),
+        "UMULH" => Some(Opcode::UMULH),
+        "CLZ" => Some(Opcode::CLZ),
+        "CLS" => Some(Opcode::CLS),
+        "RBIT" => Some(Opcode::RBIT),
+        "REV" => Some(Opcode::REV),
+        "UBFX" => Some(Opcode::UBFX),
+        "SBFX" => Some(Opcode::SBFX),
+        "BFI" => Some(Opcode::BFI),
+        "EXTR" => Some(Opcode::EXTR),
+        "MOVZ" => Some(Opcode::MOVZ),
+        "MOVN" => Some(Opcode::MOVN),
+        "MOVK" => Some(Opcode::MOVK),
+        _ => get_branch_condition(mnemonic_uppercased).map(|_| Opcode::BCOND),
+    }
+}
+
+// The system registers that can be read using MRS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SystemRegisterType {
+    // MPIDR_EL1; the multiprocessor affinity register. It contains the id of the core.
+    MPIDR,
+}
+
+pub(crate) fn get_system_register(name: &str) -> Option<SystemRegisterType> {
+    match name.to_uppercase().as_str() {
+        "MPIDR_EL1" => Some(SystemRegisterType::MPIDR),
+        _ => None,
+    }
+}
+
+// The options of the DMB and DSB barriers; they select the memory accesses that are ordered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BarrierOptionType {
+    // the full system; orders all loads and stores
+    SY,
+    // the inner shareable domain; orders all loads and stores
+    ISH,
+    // orders the loads with the younger loads and stores
+    ISHLD,
+    // orders the stores with the younger stores
+    ISHST,
+}
+
+pub(crate) fn get_barrier_option(name: &str) -> Option<BarrierOptionType> {
+    match name.to_uppercase().as_str() {
+        "SY" => Some(BarrierOptionType::SY),
+        "ISH" => Some(BarrierOptionType::ISH),
+        "ISHLD" => Some(BarrierOptionType::ISHLD),
+        "ISHST" => Some(BarrierOptionType::ISHST),
         _ => None,
     }
 }
 
+pub(crate) fn get_condition_code(name: &str) -> Option<ConditionCode> {
+    match name.to_uppercase().as_str() {
+        "EQ" => Some(ConditionCode::EQ),
+        "NE" => Some(ConditionCode::NE),
+        "CS" | "HS" => Some(ConditionCode::CS),
+        "CC" | "LO" => Some(ConditionCode::CC),
+        "MI" => Some(ConditionCode::MI),
+        "PL" => Some(ConditionCode::PL),
+        "VS" => Some(ConditionCode::VS),
+        "VC" => Some(ConditionCode::VC),
+        "HI" => Some(ConditionCode::HI),
+        "LS" => Some(ConditionCode::LS),
+        "GE" => Some(ConditionCode::GE),
+        "LT" => Some(ConditionCode::LT),
+        "GT" => Some(ConditionCode::GT),
+        "LE" => Some(ConditionCode::LE),
+        "AL" => Some(ConditionCode::AL),
+        _ => None,
+    }
+}
+
+pub(crate) fn get_shift(name: &str, amount: u8) -> Option<Shift> {
+    match name.to_uppercase().as_str() {
+        "LSL" => Some(Shift::LSL(amount)),
+        "LSR" => Some(Shift::LSR(amount)),
+        "ASR" => Some(Shift::ASR(amount)),
+        "ROR" => Some(Shift::ROR(amount)),
+        _ => None,
+    }
+}
+
+// Returns the condition of a conditional branch mnemonic; B.<cond> or the alias B<cond> like BEQ.
+pub(crate) fn get_branch_condition(mnemonic: &str) -> Option<ConditionCode> {
+    let string = mnemonic.to_uppercase();
+    let condition = string.strip_prefix("B.").or_else(|| string.strip_prefix('B'))?;
+    get_condition_code(condition)
+}
 
 pub(crate) fn create_instr(
     opcode: Opcode,
@@ -141,28 +355,86 @@ pub(crate) fn create_instr(
     loc: SourceLocation,
 ) -> Result<Instr, String> {
     let mut instr = Instr {
-        cycles: 1,
         opcode,
         source_cnt: 0,
-        source: [Unused, Unused, Unused],
+        source: [Unused, Unused, Unused, Unused],
         sink_cnt: 0,
-        sink: [Unused, Unused],
+        sink: [Unused, Unused, Unused],
         loc: Some(loc),
         mem_stores: 0,
+        mem_loads: 0,
+        mem_size: 0,
+        addressing_mode: AddressingMode::OFFSET(0),
         flags: 0,
         condition_code: ConditionCode::AL,
+        shift: Shift::LSL(0),
+    };
+
+    // the W registers select the 32 bit variant; they are replaced by the registers they are a view of
+    let x_operands: Vec<Operand>;
+    let operands = if has_32bit_variant(opcode) && operands.iter().any(|op| matches!(op, WRegister(_))) {
+        if operands.iter().any(|op| matches!(op, Register(_))) {
+            return Err(format!("{:?} can't mix W and X registers", opcode));
+        }
+        instr.set_32bit();
+        x_operands = operands.iter().map(|op| match op {
+            WRegister(reg) => Register(*reg),
+            _ => *op,
+        }).collect();
+        &x_operands
+    } else {
+        operands
     };
 
     match opcode {
-        Opcode::SUB |
         Opcode::MUL |
         Opcode::SDIV |
+        Opcode::UDIV |
+        Opcode::SMULH |
+        Opcode::UMULH |
+        Opcode::RSB => {
+            validate_operand_count(3, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0), Immediate(0)])?;
+        }
+        Opcode::MADD |
+        Opcode::MSUB => {
+            validate_operand_count(4, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // the product of the first two sources is added to or subtracted from the third
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0)])?;
+            instr.source[2] = validate_operand(3, operands, opcode, &[Register(0)])?;
+        }
+        Opcode::SMULL |
+        Opcode::UMULL => {
+            validate_operand_count(3, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // the 64 bit product of the lower 32 bits of the sources
+            instr.source_cnt = 2;
+            for k in 0..2 {
+                let WRegister(reg) = validate_operand(k + 1, operands, opcode, &[WRegister(0)])? else { unreachable!() };
+                instr.source[k] = Register(reg);
+            }
+        }
+        Opcode::SUB |
         Opcode::AND |
         Opcode::ORR |
         Opcode::EOR |
-        Opcode::RSB |
         Opcode::ADD => {
-            validate_operand_count(3, operands, opcode, loc)?;
+            instr.shift = validate_shifted_register(3, operands, opcode, loc)?;
 
             instr.sink_cnt = 1;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -171,24 +443,89 @@ pub(crate) fn create_instr(
             instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
             instr.source[1] = validate_operand(2, operands, opcode, &[Register(0), Immediate(0)])?;
         }
-        Opcode::ADR => { panic!() }
-        Opcode::LDR => {
-            validate_operand_count(2, operands, opcode, loc)?;
+        Opcode::ADDS |
+        Opcode::SUBS |
+        Opcode::ANDS => {
+            instr.shift = validate_shifted_register(3, operands, opcode, loc)?;
 
-            instr.sink_cnt = 1;
+            // the result and the flags are both written
+            instr.sink_cnt = 2;
             instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.sink[1] = Register(CPSR);
 
-            instr.source_cnt = 1;
-            instr.source[0] = validate_operand(1, operands, opcode, &[MemRegisterIndirect(0)])?
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0), Immediate(0)])?;
+            instr.source[2] = Register(CPSR);
         }
-        Opcode::STR => {
-            validate_operand_count(2, operands, opcode, loc)?;
-
-            instr.mem_stores = 1;
+        Opcode::ADR => { panic!() }
+        Opcode::LDAR |
+        Opcode::LDR |
+        Opcode::LDRB |
+        Opcode::LDRH |
+        Opcode::LDRSW |
+        Opcode::LDP => {
+            // a pair loads two registers from consecutive addresses
+            let transfer_cnt = if opcode == Opcode::LDP { 2 } else { 1 };
+            validate_transfer_operand_count(transfer_cnt, operands, opcode, loc)?;
+
+            instr.mem_loads = transfer_cnt as u8;
+
+            for k in 0..transfer_cnt {
+                let (register, size) = validate_transfer_register(k, operands, opcode)?;
+                if k > 0 && register.get_register() == instr.sink[0].get_register() {
+                    return Err(format!("{:?} can't load the register {} twice", opcode, register));
+                }
+                validate_transfer_size(k, size, instr.mem_size, opcode)?;
+                instr.sink[k] = register;
+                instr.mem_size = size;
+            }
+            instr.sink_cnt = transfer_cnt as u8;
 
+            let (base, offset, addressing_mode) = validate_address(transfer_cnt, operands, opcode, instr.mem_size, loc)?;
             instr.source_cnt = 2;
-            instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
-            instr.source[1] = validate_operand(1, operands, opcode, &[MemRegisterIndirect(0)])?;
+            instr.source[0] = base;
+            instr.source[1] = offset;
+            instr.addressing_mode = addressing_mode;
+
+            if addressing_mode.has_writeback() {
+                let base_register = base.get_mem_register();
+                if let Some(register) = instr.sink[..transfer_cnt].iter().find(|sink| sink.get_register() == base_register) {
+                    return Err(format!("{:?} can't load the base register {} that is written back",
+                                       opcode, register));
+                }
+                instr.sink[transfer_cnt] = Register(base_register);
+                instr.sink_cnt += 1;
+            }
+        }
+        Opcode::STLR |
+        Opcode::STR |
+        Opcode::STRB |
+        Opcode::STRH |
+        Opcode::STP => {
+            // a pair stores two registers to consecutive addresses
+            let transfer_cnt = if opcode == Opcode::STP { 2 } else { 1 };
+            validate_transfer_operand_count(transfer_cnt, operands, opcode, loc)?;
+
+            instr.mem_stores = transfer_cnt as u8;
+
+            for k in 0..transfer_cnt {
+                let (register, size) = validate_transfer_register(k, operands, opcode)?;
+                validate_transfer_size(k, size, instr.mem_size, opcode)?;
+                instr.source[k] = register;
+                instr.mem_size = size;
+            }
+
+            let (base, offset, addressing_mode) = validate_address(transfer_cnt, operands, opcode, instr.mem_size, loc)?;
+            instr.source_cnt = transfer_cnt as u8 + 2;
+            instr.source[transfer_cnt] = base;
+            instr.source[transfer_cnt + 1] = offset;
+            instr.addressing_mode = addressing_mode;
+
+            if addressing_mode.has_writeback() {
+                instr.sink_cnt = 1;
+                instr.sink[0] = Register(base.get_mem_register());
+            }
         }
         Opcode::NOP => {
             validate_operand_count(0, operands, opcode, loc)?;
@@ -210,6 +547,41 @@ pub(crate) fn create_instr(
             instr.source_cnt = 1;
             instr.source[0] = validate_operand(1, operands, opcode, &[Immediate(0), Register(0)])?
         }
+        Opcode::MOVZ |
+        Opcode::MOVN |
+        Opcode::MOVK => {
+            if operands.len() != 2 && operands.len() != 3 {
+                return Err(format!("Operand count mismatch. {:?} expects 2 or 3 arguments, but {} are provided at {}:{}",
+                                   opcode, operands.len(), loc.line, loc.column));
+            }
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            let value = validate_immediate_range(1, operands, opcode, 0, 0xFFFF)?;
+            let mut shift = 0;
+            if operands.len() == 3 {
+                // a 32 bit register only has 2 halfwords
+                let max_amount = if instr.is_32bit() { 16 } else { 48 };
+                shift = match validate_operand(2, operands, opcode, &[Operand::Shift(Shift::LSL(0))])? {
+                    Operand::Shift(Shift::LSL(amount)) if amount % 16 == 0 && amount <= max_amount => amount,
+                    operand if instr.is_32bit() => return Err(format!("{:?} with W registers expects LSL #0 or #16, but {} was provided", opcode, operand)),
+                    operand => return Err(format!("{:?} expects LSL #0, #16, #32 or #48, but {} was provided", opcode, operand)),
+                };
+            }
+
+            if opcode == Opcode::MOVK {
+                // MOVK only replaces 16 bits, so the destination is also a source
+                instr.source_cnt = 3;
+                instr.source[0] = instr.sink[0];
+                instr.source[1] = value;
+                instr.source[2] = Immediate(shift as DWordType);
+            } else {
+                instr.source_cnt = 2;
+                instr.source[0] = value;
+                instr.source[1] = Immediate(shift as DWordType);
+            }
+        }
         Opcode::B => {
             validate_operand_count(1, operands, opcode, loc)?;
 
@@ -270,10 +642,131 @@ pub(crate) fn create_instr(
             instr.set_branch();
         }
         Opcode::DSB => {
+            // the option is optional; whatever the option, the DSB waits for everything to complete
+            if operands.len() == 1 {
+                validate_operand(0, operands, opcode, &[BarrierOption(BarrierOptionType::SY)])?;
+            } else {
+                validate_operand_count(0, operands, opcode, loc)?;
+            }
+            instr.set_rob_sync();
+            instr.set_sb_sync();
+        }
+        Opcode::DMB => {
+            validate_operand_count(1, operands, opcode, loc)?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(0, operands, opcode, &[BarrierOption(BarrierOptionType::SY)])?;
+        }
+        Opcode::ISB => {
+            validate_operand_count(0, operands, opcode, loc)?;
+        }
+        Opcode::MRS => {
+            validate_operand_count(2, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[SystemRegister(SystemRegisterType::MPIDR)])?;
+        }
+        Opcode::LDXR => {
+            validate_operand_count(2, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[MemRegisterIndirect(0)])?;
+            instr.set_rob_sync();
+            instr.set_sb_sync();
+        }
+        Opcode::STXR => {
+            validate_operand_count(3, operands, opcode, loc)?;
+
+            // the status: 0 if the store succeeded, 1 if it failed
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(2, operands, opcode, &[MemRegisterIndirect(0)])?;
+            instr.source[1] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.set_rob_sync();
+            instr.set_sb_sync();
+        }
+        Opcode::CLREX => {
             validate_operand_count(0, operands, opcode, loc)?;
             instr.set_rob_sync();
             instr.set_sb_sync();
         }
+        Opcode::LDADD |
+        Opcode::SWP => {
+            validate_operand_count(3, operands, opcode, loc)?;
+
+            // the old value in memory
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(2, operands, opcode, &[MemRegisterIndirect(0)])?;
+            instr.source[1] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.set_rob_sync();
+            instr.set_sb_sync();
+        }
+        Opcode::CAS => {
+            validate_operand_count(3, operands, opcode, loc)?;
+
+            // the first register is the expected value and receives the old value in memory
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(2, operands, opcode, &[MemRegisterIndirect(0)])?;
+            instr.source[1] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.source[2] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.set_rob_sync();
+            instr.set_sb_sync();
+        }
+        Opcode::CSEL |
+        Opcode::CSINC |
+        Opcode::CSNEG => {
+            validate_operand_count(4, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0)])?;
+            instr.source[2] = Register(CPSR);
+            instr.condition_code = validate_condition(3, operands, opcode)?;
+        }
+        Opcode::CSET => {
+            validate_operand_count(2, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = Register(CPSR);
+            instr.condition_code = validate_condition(1, operands, opcode)?;
+        }
+        Opcode::CCMP => {
+            validate_operand_count(4, operands, opcode, loc)?;
+
+            // the flags are set by the compare if the condition holds and to nzcv otherwise
+            instr.source_cnt = 4;
+            instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(1, operands, opcode, &[Immediate(0), Register(0)])?;
+            instr.source[2] = Register(CPSR);
+            instr.source[3] = validate_operand(2, operands, opcode, &[Immediate(0)])?;
+            if instr.source[3].get_immediate() > 0xF {
+                return Err(format!("{:?} expects the flags #0 to #15, but {} was provided", opcode, instr.source[3]));
+            }
+            instr.condition_code = validate_condition(3, operands, opcode)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = Register(CPSR);
+        }
         Opcode::NEG => {
             validate_operand_count(2, operands, opcode, loc)?;
 
@@ -283,6 +776,17 @@ pub(crate) fn create_instr(
             instr.source_cnt = 1;
             instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
         }
+        Opcode::NEGS => {
+            validate_operand_count(2, operands, opcode, loc)?;
+
+            instr.sink_cnt = 2;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+            instr.sink[1] = Register(CPSR);
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = Register(CPSR);
+        }
         Opcode::MVN => {
             validate_operand_count(2, operands, opcode, loc)?;
 
@@ -292,8 +796,10 @@ pub(crate) fn create_instr(
             instr.source_cnt = 1;
             instr.source[0] = validate_operand(1, operands, opcode, &[Immediate(0), Register(0)])?;
         }
+        Opcode::TST |
+        Opcode::CMN |
         Opcode::CMP => {
-            validate_operand_count(2, operands, opcode, loc)?;
+            instr.shift = validate_shifted_register(2, operands, opcode, loc)?;
 
             instr.source_cnt = 3;
             instr.source[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
@@ -303,23 +809,97 @@ pub(crate) fn create_instr(
             instr.sink_cnt = 1;
             instr.sink[0] = Register(CPSR);
         }
-        Opcode::BEQ |
-        Opcode::BNE |
-        Opcode::BLT |
-        Opcode::BLE |
-        Opcode::BGT |
-        Opcode::BGE => {
-            validate_operand_count(1, operands, opcode, loc)?;
+        Opcode::LSL |
+        Opcode::LSR |
+        Opcode::ASR |
+        Opcode::ROR => {
+            validate_operand_count(3, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 2;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0), Immediate(0)])?;
+            let max_amount = if instr.is_32bit() { 31 } else { 63 };
+            if let Immediate(amount) = instr.source[1] {
+                if amount > max_amount {
+                    return Err(format!("{:?} expects a shift of #0 to #{}, but {} was provided", opcode, max_amount, instr.source[1]));
+                }
+            }
+        }
+        Opcode::CLZ |
+        Opcode::CLS |
+        Opcode::RBIT |
+        Opcode::REV => {
+            validate_operand_count(2, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 1;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+        }
+        Opcode::UBFX |
+        Opcode::SBFX => {
+            validate_operand_count(4, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_immediate_range(2, operands, opcode, 0, 63)?;
+            instr.source[2] = validate_immediate_range(3, operands, opcode, 1, 64 - instr.source[1].get_immediate())?;
+        }
+        Opcode::BFI => {
+            validate_operand_count(4, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            // only the bitfield is replaced, so the destination is also a source
+            instr.source_cnt = 4;
+            instr.source[0] = instr.sink[0];
+            instr.source[1] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[2] = validate_immediate_range(2, operands, opcode, 0, 63)?;
+            instr.source[3] = validate_immediate_range(3, operands, opcode, 1, 64 - instr.source[2].get_immediate())?;
+        }
+        Opcode::EXTR => {
+            validate_operand_count(4, operands, opcode, loc)?;
+
+            instr.sink_cnt = 1;
+            instr.sink[0] = validate_operand(0, operands, opcode, &[Register(0)])?;
+
+            instr.source_cnt = 3;
+            instr.source[0] = validate_operand(1, operands, opcode, &[Register(0)])?;
+            instr.source[1] = validate_operand(2, operands, opcode, &[Register(0)])?;
+            instr.source[2] = validate_immediate_range(3, operands, opcode, 0, 63)?;
+        }
+        Opcode::BCOND => {
+            // the condition is taken from the mnemonic and added as last operand by the loader
+            validate_operand_count(2, operands, opcode, loc)?;
 
             instr.source_cnt = 2;
             instr.source[0] = validate_operand(0, operands, opcode, &[Code(0)])?;
             instr.source[1] = Register(CPSR);
+            instr.condition_code = validate_condition(1, operands, opcode)?;
 
             instr.sink_cnt = 0;
             instr.set_branch();
         }
     }
 
+    // like on ARM, register 31 is the stack pointer when used as base and there is no XZR base
+    if instr.source.iter().any(|op| matches!(op, MemRegisterIndirect(ZR))) {
+        return Err(format!("{:?} can't use XZR as base register", opcode));
+    }
+
+    // a 32 bit instruction can't shift its operand by more than its size
+    if instr.is_32bit() && !matches!(instr.shift, Shift::LSL(amount) | Shift::LSR(amount) | Shift::ASR(amount) | Shift::ROR(amount) if amount < 32) {
+        return Err(format!("{:?} with W registers expects a shift of #0 to #31, but {} was provided", opcode, instr.shift));
+    }
+
     // todo: handling of instructions with control like modifying the IP need to be detected.
     //
     // if !instr.is_branch() && has_control_operands(&instr) {
@@ -353,6 +933,10 @@ fn validate_operand(
             return Ok(operand);
         }
     }
+
+    if matches!(operand, WRegister(_)) && acceptable_types.iter().any(|typ| matches!(typ, Register(_))) {
+        return Err(format!("{:?} doesn't support W registers as argument nr {}", opcode, op_index + 1));
+    }
     let acceptable_names: Vec<&str> = acceptable_types.iter().map(|t| t.base_name()).collect();
     let acceptable_names_str = acceptable_names.join(", ");
 
@@ -360,6 +944,252 @@ fn validate_operand(
                 opcode, acceptable_names_str, op_index + 1, operand.base_name()))
 }
 
+// Validates the register that is loaded or stored and returns it with the number of bytes
+// accessed. LDRB/LDRH/STRB/STRH access the lowest bytes of the register, LDRSW loads a word
+// into an X register and for the other loads and stores a W register selects a word access.
+fn validate_transfer_register(op_index: usize, operands: &Vec<Operand>, opcode: Opcode) -> Result<(Operand, u8), String> {
+    let acceptable_types: &[Operand] = if opcode == Opcode::LDRSW {
+        &[Register(0)]
+    } else {
+        &[Register(0), WRegister(0)]
+    };
+
+    let operand = validate_operand(op_index, operands, opcode, acceptable_types)?;
+    let register = match operand {
+        WRegister(reg) => Register(reg),
+        _ => operand,
+    };
+
+    let size = match (opcode, operand) {
+        (Opcode::LDRB | Opcode::STRB, _) => 1,
+        (Opcode::LDRH | Opcode::STRH, _) => 2,
+        (Opcode::LDRSW, _) | (_, WRegister(_)) => 4,
+        _ => 8,
+    };
+    Ok((register, size))
+}
+
+// Validates the operands of an instruction of which the last operand can be a shifted register
+// like ADD x0, x1, x2, LSL #3; the shift is then an additional operand. Returns LSL #0 if there
+// is no shift.
+fn validate_shifted_register(operand_cnt: usize,
+                             operands: &Vec<Operand>,
+                             opcode: Opcode,
+                             loc: SourceLocation) -> Result<Shift, String> {
+    if operands.len() != operand_cnt + 1 {
+        validate_operand_count(operand_cnt, operands, opcode, loc)?;
+        return Ok(Shift::LSL(0));
+    }
+
+    validate_operand(operand_cnt - 1, operands, opcode, &[Register(0)])?;
+    let shift = match validate_operand(operand_cnt, operands, opcode, &[Operand::Shift(Shift::LSL(0))])? {
+        Operand::Shift(shift) => shift,
+        _ => unreachable!(),
+    };
+
+    // only the logical instructions can rotate
+    let is_logical = matches!(opcode, Opcode::AND | Opcode::ORR | Opcode::EOR | Opcode::ANDS | Opcode::TST);
+    if matches!(shift, Shift::ROR(_)) && !is_logical {
+        return Err(format!("{:?} doesn't support {}", opcode, shift));
+    }
+    Ok(shift)
+}
+
+// True if W registers select the 32 bit variant of the instruction; it uses the lower 32 bits of
+// its source registers, computes the flags and signs on 32 bits and zero extends its result.
+fn has_32bit_variant(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::MOV | Opcode::MVN | Opcode::ADD | Opcode::SUB | Opcode::NEG | Opcode::MUL |
+        Opcode::MADD | Opcode::MSUB | Opcode::SDIV | Opcode::UDIV | Opcode::AND | Opcode::ORR | Opcode::EOR |
+        Opcode::ADDS | Opcode::SUBS | Opcode::ANDS | Opcode::NEGS | Opcode::CMP | Opcode::CMN | Opcode::TST |
+        Opcode::CCMP | Opcode::LSL | Opcode::LSR | Opcode::ASR | Opcode::ROR | Opcode::MOVZ | Opcode::MOVN |
+        Opcode::MOVK | Opcode::CSEL | Opcode::CSINC | Opcode::CSNEG | Opcode::CSET | Opcode::CBZ | Opcode::CBNZ)
+}
+
+// Validates that the operand is an immediate from min to max inclusive.
+fn validate_immediate_range(op_index: usize,
+                            operands: &Vec<Operand>,
+                            opcode: Opcode,
+                            min: DWordType,
+                            max: DWordType) -> Result<Operand, String> {
+    let operand = validate_operand(op_index, operands, opcode, &[Immediate(0)])?;
+    let value = operand.get_immediate();
+    if value < min || value > max {
+        return Err(format!("{:?} expects #{} to #{}, but {} was provided", opcode, min, max, operand));
+    }
+    Ok(operand)
+}
+
+fn validate_condition(op_index: usize, operands: &Vec<Operand>, opcode: Opcode) -> Result<ConditionCode, String> {
+    match validate_operand(op_index, operands, opcode, &[Condition(ConditionCode::AL)])? {
+        Condition(condition_code) => Ok(condition_code),
+        _ => unreachable!(),
+    }
+}
+
+// Validates the number of operands of a load or store that transfers transfer_cnt registers;
+// a post-indexed access has the offset as additional operand.
+fn validate_transfer_operand_count(transfer_cnt: usize,
+                                   operands: &Vec<Operand>,
+                                   opcode: Opcode,
+                                   loc: SourceLocation) -> Result<(), String> {
+    if operands.len() != transfer_cnt + 1 && operands.len() != transfer_cnt + 2 {
+        return Err(format!("Operand count mismatch. {:?} expects {} or {} arguments, but {} are provided at {}:{}",
+                           opcode, transfer_cnt + 1, transfer_cnt + 2, operands.len(), loc.line, loc.column));
+    }
+    Ok(())
+}
+
+// Validates that the registers of a pair are of the same size; size is the size of the register
+// at op_index and first_size the size of the first register.
+fn validate_transfer_size(op_index: usize, size: u8, first_size: u8, opcode: Opcode) -> Result<(), String> {
+    if op_index > 0 && size != first_size {
+        return Err(format!("Operand size mismatch. {:?} expects registers of the same size, but argument nr {} is not",
+                           opcode, op_index + 1));
+    }
+    Ok(())
+}
+
+// Validates the memory operand of a load or store and returns the base register, the offset and
+// the addressing mode. The base register becomes a MemRegisterIndirect source and the offset an
+// Immediate or Register source; [Xn] has an offset of #0. A post-indexed access is written as
+// '[Xn], #imm'; so its offset is the operand after the memory operand. The load-acquire and
+// store-release only accept [Xn] and the pairs don't accept a register offset.
+fn validate_address(op_index: usize,
+                    operands: &Vec<Operand>,
+                    opcode: Opcode,
+                    size: u8,
+                    loc: SourceLocation) -> Result<(Operand, Operand, AddressingMode), String> {
+    if matches!(opcode, Opcode::LDAR | Opcode::STLR) {
+        validate_operand_count(op_index + 1, operands, opcode, loc)?;
+        let base = validate_operand(op_index, operands, opcode, &[MemRegisterIndirect(0)])?;
+        return Ok((base, Immediate(0), AddressingMode::OFFSET(0)));
+    }
+
+    if operands.len() == op_index + 2 {
+        let base = validate_operand(op_index, operands, opcode, &[MemRegisterIndirect(0)])?;
+        let offset = validate_operand(op_index + 1, operands, opcode, &[Immediate(0)])?;
+        return Ok((base, offset, AddressingMode::POSTINDEX));
+    }
+
+    validate_operand_count(op_index + 1, operands, opcode, loc)?;
+    let acceptable_types: &[Operand] = if matches!(opcode, Opcode::LDP | Opcode::STP) {
+        &[MemRegisterIndirect(0), MemRegIndirectWithOffset(0, 0), MemPreIndexed(0, 0)]
+    } else {
+        &[MemRegisterIndirect(0), MemRegIndirectWithOffset(0, 0), MemRegIndirectWithRegOffset(0, 0, 0), MemPreIndexed(0, 0)]
+    };
+    let operand = validate_operand(op_index, operands, opcode, acceptable_types)?;
+    match operand {
+        MemRegIndirectWithOffset(base, offset) => Ok((MemRegisterIndirect(base), Immediate(offset), AddressingMode::OFFSET(0))),
+        MemRegIndirectWithRegOffset(base, offset, shift) => {
+            // the offset register can only be scaled by the number of bytes accessed
+            if shift != 0 && (1 << shift) != size as u32 {
+                return Err(format!("{:?} can only shift the offset register by #0 or #{}, but #{} was provided",
+                                   opcode, size.trailing_zeros(), shift));
+            }
+            Ok((MemRegisterIndirect(base), Register(offset), AddressingMode::OFFSET(shift)))
+        }
+        MemPreIndexed(base, offset) => Ok((MemRegisterIndirect(base), Immediate(offset), AddressingMode::PREINDEX)),
+        _ => Ok((operand, Immediate(0), AddressingMode::OFFSET(0))),
+    }
+}
+
+// Checks if the immediate of the instruction can be encoded by the real instruction. The loader
+// only checks this in strict mode, so that the assembly can also be assembled for hardware.
+pub(crate) fn validate_immediate_encoding(instr: &Instr) -> Result<(), String> {
+    let opcode = instr.opcode;
+    let op_index = match opcode {
+        Opcode::MOV |
+        Opcode::MVN => 0,
+        Opcode::LDR |
+        Opcode::LDRB |
+        Opcode::LDRH |
+        Opcode::LDRSW |
+        Opcode::LDAR |
+        Opcode::LDP |
+        Opcode::STR |
+        Opcode::STRB |
+        Opcode::STRH |
+        Opcode::STLR |
+        Opcode::STP => instr.mem_stores as usize + 1,
+        _ => 1,
+    };
+    let Immediate(value) = instr.source[op_index] else { return Ok(()) };
+    let signed_value = value as i64;
+    let size = instr.mem_size as i64;
+    // a load or store has an unscaled 9 bit offset or, without writeback, a scaled unsigned 12 bit one
+    let is_offset_encodable = || {
+        (-256..=255).contains(&signed_value)
+            || (instr.addressing_mode == AddressingMode::OFFSET(0) && signed_value >= 0 && signed_value % size == 0 && signed_value / size <= 4095)
+    };
+
+    let expected = match opcode {
+        Opcode::ADD |
+        Opcode::SUB |
+        Opcode::ADDS |
+        Opcode::SUBS |
+        Opcode::CMP |
+        Opcode::CMN if !is_arithmetic_immediate(value) && !is_arithmetic_immediate(value.wrapping_neg()) =>
+            "a 12 bit value that is optionally shifted left by 12",
+        Opcode::AND |
+        Opcode::ORR |
+        Opcode::EOR |
+        Opcode::ANDS |
+        Opcode::TST if !is_bitmask_immediate(value) => "a bitmask immediate",
+        Opcode::MOV if !is_wide_immediate(value) && !is_wide_immediate(!value) && !is_bitmask_immediate(value) =>
+            "a value that MOVZ, MOVN or ORR can encode",
+        Opcode::CCMP if value > 31 => "#0 to #31",
+        Opcode::MVN |
+        Opcode::MUL |
+        Opcode::SDIV |
+        Opcode::UDIV |
+        Opcode::SMULH |
+        Opcode::UMULH => "a register",
+        Opcode::LDP |
+        Opcode::STP if signed_value % size != 0 || !(-64..=63).contains(&(signed_value / size)) =>
+            "a multiple of the access size from -64 to 63 times it",
+        Opcode::LDR |
+        Opcode::LDRB |
+        Opcode::LDRH |
+        Opcode::LDRSW |
+        Opcode::STR |
+        Opcode::STRB |
+        Opcode::STRH if !is_offset_encodable() =>
+            "-256 to 255 or, without writeback, a multiple of the access size up to 4095 times it",
+        _ => return Ok(()),
+    };
+    Err(format!("{:?} can't encode the immediate #{}; it expects {}", opcode, signed_value, expected))
+}
+
+// True if the value fits the 12 bit immediate of the arithmetic instructions; it can be shifted left by 12.
+fn is_arithmetic_immediate(value: DWordType) -> bool {
+    value <= 0xFFF || (value & 0xFFF == 0 && value <= 0xFFF000)
+}
+
+// True if the value fits the 16 bit immediate of MOVZ; one of the 4 halfwords can be non zero.
+fn is_wide_immediate(value: DWordType) -> bool {
+    (0..4).any(|k| value & !(0xFFFF << (16 * k)) == 0)
+}
+
+// True if the value fits the bitmask immediate of the logical instructions; it is an element of
+// 2, 4, 8, 16, 32 or 64 bits that is repeated and that contains a rotated run of ones.
+fn is_bitmask_immediate(value: DWordType) -> bool {
+    if value == 0 || value == DWordType::MAX {
+        return false;
+    }
+
+    let mut size = 64;
+    while size > 2 && value.rotate_right(size / 2) == value {
+        size /= 2;
+    }
+
+    let mask = DWordType::MAX >> (64 - size);
+    let element = value & mask;
+    (0..size).any(|r| {
+        let rotated = if r == 0 { element } else { ((element >> r) | (element << (size - r))) & mask };
+        rotated & (rotated + 1) == 0
+    })
+}
+
 fn has_control_operands(instr: &Instr) -> bool {
     instr.source.iter().any(|op| is_control_operand(op)) ||
         instr.sink.iter().any(|op| is_control_operand(op))
@@ -370,29 +1200,35 @@ fn is_control_operand(op: &Operand) -> bool {
 }
 
 pub(crate) const NOP: Instr = Instr {
-    cycles: 1,
     opcode: Opcode::NOP,
     source_cnt: 0,
-    source: [Operand::Unused, Operand::Unused, Operand::Unused],
+    source: [Operand::Unused, Operand::Unused, Operand::Unused, Operand::Unused],
     sink_cnt: 0,
-    sink: [Operand::Unused, Operand::Unused],
+    sink: [Operand::Unused, Operand::Unused, Operand::Unused],
     loc: None,
     mem_stores: 0,
+    mem_loads: 0,
+    mem_size: 0,
+    addressing_mode: AddressingMode::OFFSET(0),
     flags: 0,
     condition_code: ConditionCode::AL,
+    shift: Shift::LSL(0),
 };
 
 pub(crate) const EXIT: Instr = Instr {
-    cycles: 1,
     opcode: Opcode::EXIT,
     source_cnt: 0,
-    source: [Unused, Unused, Unused],
+    source: [Unused, Unused, Unused, Unused],
     sink_cnt: 0,
-    sink: [Unused, Unused],
+    sink: [Unused, Unused, Unused],
     loc: None,
     mem_stores: 0,
+    mem_loads: 0,
+    mem_size: 0,
+    addressing_mode: AddressingMode::OFFSET(0),
     flags: 0,
     condition_code: ConditionCode::AL,
+    shift: Shift::LSL(0),
 };
 
 pub(crate) type RegisterType = u16;
@@ -403,6 +1239,8 @@ pub(crate) struct InstrQueueSlot {
     // The pc of the current instr.
     pub(crate) pc: usize,
     pub(crate) branch_target_predicted: usize,
+    // The state of the RAS after the branch was predicted.
+    pub(crate) ras_checkpoint: RASCheckpoint,
 }
 
 // The InstrQueue sits between frontend and backend
@@ -418,7 +1256,12 @@ impl InstrQueue {
         let mut slots = Vec::with_capacity(capacity as usize);
 
         for _ in 0..capacity {
-            slots.push(InstrQueueSlot { pc: 0, branch_target_predicted: 0, instr: Rc::new(NOP) });
+            slots.push(InstrQueueSlot {
+                pc: 0,
+                branch_target_predicted: 0,
+                ras_checkpoint: RASCheckpoint::default(),
+                instr: Rc::new(NOP),
+            });
         }
 
         InstrQueue {
@@ -468,8 +1311,8 @@ impl InstrQueue {
 }
 
 // The maximum number of source (input) operands for an instruction.
-pub(crate) const MAX_SOURCE_COUNT: u8 = 3;
-pub(crate) const MAX_SINK_COUNT: u8 = 2;
+pub(crate) const MAX_SOURCE_COUNT: u8 = 4;
+pub(crate) const MAX_SINK_COUNT: u8 = 3;
 
 // True if the instruction is a control instruction; so a partly serializing instruction (no other instructions)
 // A control instruction gets issued into the rob, but it will prevent the next instruction to be issued, so
@@ -477,6 +1320,9 @@ pub(crate) const MAX_SINK_COUNT: u8 = 2;
 pub(crate) const INSTR_FLAG_IS_BRANCH: u8 = 0;
 pub(crate) const INSTR_FLAG_SB_SYNC: u8 = 1;
 pub(crate) const INSTR_FLAG_ROB_SYNC: u8 = 2;
+// True if the instruction uses the W registers; it only uses the lower 32 bits of its source
+// registers and the result is zero extended.
+pub(crate) const INSTR_FLAG_32BIT: u8 = 3;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ConditionCode {
@@ -497,9 +1343,86 @@ pub enum ConditionCode {
     AL, // Always (unconditional)
 }
 
+// The shift of a shifted register operand; the register value is shifted by the amount before
+// it is used.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Shift {
+    LSL(u8),
+    LSR(u8),
+    ASR(u8),
+    ROR(u8),
+}
+
+impl Shift {
+    pub(crate) fn apply(&self, value: DWordType) -> DWordType {
+        match *self {
+            Shift::LSL(amount) => value << amount,
+            Shift::LSR(amount) => value >> amount,
+            Shift::ASR(amount) => ((value as i64) >> amount) as DWordType,
+            Shift::ROR(amount) => value.rotate_right(amount as u32),
+        }
+    }
+
+    // Shifts the lower 32 bits of the value like a 32 bit instruction; the amount is below 32.
+    pub(crate) fn apply_32bit(&self, value: DWordType) -> DWordType {
+        let value = value as u32;
+        let result = match *self {
+            Shift::LSL(amount) => value << amount,
+            Shift::LSR(amount) => value >> amount,
+            Shift::ASR(amount) => ((value as i32) >> amount) as u32,
+            Shift::ROR(amount) => value.rotate_right(amount as u32),
+        };
+        result as DWordType
+    }
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shift::LSL(amount) => write!(f, "LSL #{}", amount),
+            Shift::LSR(amount) => write!(f, "LSR #{}", amount),
+            Shift::ASR(amount) => write!(f, "ASR #{}", amount),
+            Shift::ROR(amount) => write!(f, "ROR #{}", amount),
+        }
+    }
+}
+
+// How a load or store computes its address from the base register and the offset.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AddressingMode {
+    // the address is the base plus the offset shifted left by the amount; [Xn], [Xn, #imm] and
+    // [Xn, Xm, LSL #s]
+    OFFSET(u8),
+    // the address is the base plus the offset and is written back to the base; [Xn, #imm]!
+    PREINDEX,
+    // the address is the base and the base plus the offset is written back to the base; [Xn], #imm
+    POSTINDEX,
+}
+
+impl AddressingMode {
+    // True if the base register is updated; it is an additional sink of the load or store.
+    pub(crate) fn has_writeback(&self) -> bool {
+        !matches!(self, AddressingMode::OFFSET(_))
+    }
+
+    // Returns the address that is accessed and the new value of the base register.
+    pub(crate) fn agen(&self, base: DWordType, offset: DWordType) -> (DWordType, DWordType) {
+        match *self {
+            AddressingMode::OFFSET(shift) => {
+                let addr = base.wrapping_add(offset << shift);
+                (addr, base)
+            }
+            AddressingMode::PREINDEX => {
+                let addr = base.wrapping_add(offset);
+                (addr, addr)
+            }
+            AddressingMode::POSTINDEX => (base, base.wrapping_add(offset)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Instr {
-    pub cycles: u8,
     pub opcode: Opcode,
     pub source_cnt: u8,
     pub source: [Operand; MAX_SOURCE_COUNT as usize],
@@ -507,8 +1430,15 @@ pub struct Instr {
     pub sink: [Operand; MAX_SINK_COUNT as usize],
     pub loc: Option<SourceLocation>,
     pub mem_stores: u8,
+    pub mem_loads: u8,
+    // the number of bytes accessed by a load or store
+    pub mem_size: u8,
+    // how a load or store computes its address
+    pub addressing_mode: AddressingMode,
     pub flags: u8,
     pub condition_code: ConditionCode,
+    // the shift of the second source operand if it is a shifted register
+    pub shift: Shift,
 }
 
 impl Instr {
@@ -520,6 +1450,60 @@ impl Instr {
         self.flags |= 1 << INSTR_FLAG_IS_BRANCH;
     }
 
+    // True if the instruction is a subroutine return; so the target can be predicted using the RAS.
+    pub(crate) fn is_return(&self) -> bool {
+        match self.opcode {
+            Opcode::RET |
+            Opcode::BX => matches!(self.source[0], Register(LR)),
+            _ => false,
+        }
+    }
+
+    // True if the instruction is a branch whose direction depends on a condition.
+    pub(crate) fn is_conditional_branch(&self) -> bool {
+        matches!(self.opcode,
+            Opcode::CBZ |
+            Opcode::CBNZ |
+            Opcode::BCOND)
+    }
+
+    // True if the instruction accesses memory atomically; it is performed by the memory subsystem
+    // once the line is owned by the L1D. The address is the first source.
+    pub(crate) fn is_atomic(&self) -> bool {
+        matches!(self.opcode,
+            Opcode::LDXR |
+            Opcode::STXR |
+            Opcode::LDADD |
+            Opcode::SWP |
+            Opcode::CAS)
+    }
+
+    // True if the younger loads can't be performed before the instruction has completed; a DMB
+    // that orders the loads or a load-acquire.
+    pub(crate) fn is_load_barrier(&self) -> bool {
+        match self.opcode {
+            Opcode::LDAR => true,
+            Opcode::DMB => !matches!(self.source[0], BarrierOption(BarrierOptionType::ISHST)),
+            _ => false,
+        }
+    }
+
+    // True if the instruction can't retire before the committed stores have been written to
+    // memory; a DMB that orders the stores with the younger loads.
+    pub(crate) fn is_store_barrier(&self) -> bool {
+        self.opcode == Opcode::DMB &&
+            matches!(self.source[0], BarrierOption(BarrierOptionType::SY | BarrierOptionType::ISH))
+    }
+
+    // Extends the value loaded from memory to a machine word: LDRSW sign extends and the other
+    // loads zero extend.
+    pub(crate) fn extend_loaded(&self, value: DWordType) -> DWordType {
+        match self.opcode {
+            Opcode::LDRSW => value as u32 as i32 as i64 as DWordType,
+            _ => value,
+        }
+    }
+
     pub(crate) fn rob_sync(&self) -> bool {
         (self.flags & (1 << INSTR_FLAG_ROB_SYNC)) != 0
     }
@@ -535,11 +1519,38 @@ impl Instr {
     pub(crate) fn set_sb_sync(&mut self) {
         self.flags |= 1 << INSTR_FLAG_SB_SYNC;
     }
+
+    pub(crate) fn is_32bit(&self) -> bool {
+        (self.flags & (1 << INSTR_FLAG_32BIT)) != 0
+    }
+
+    pub(crate) fn set_32bit(&mut self) {
+        self.flags |= 1 << INSTR_FLAG_32BIT;
+    }
+}
+
+impl Instr {
+    // Formats the memory operand of a load or store; the base register is the source at the
+    // index and the offset the next one.
+    fn fmt_address(&self, f: &mut fmt::Formatter<'_>, base_index: usize) -> fmt::Result {
+        let base = Register(self.source[base_index].get_mem_register());
+        let offset = self.source[base_index + 1];
+        match (self.addressing_mode, offset) {
+            (AddressingMode::OFFSET(_), Immediate(0)) => write!(f, "[{}]", base),
+            (AddressingMode::OFFSET(0), _) => write!(f, "[{}, {}]", base, offset),
+            (AddressingMode::OFFSET(shift), _) => write!(f, "[{}, {}, LSL #{}]", base, offset, shift),
+            (AddressingMode::PREINDEX, _) => write!(f, "[{}, {}]!", base, offset),
+            (AddressingMode::POSTINDEX, _) => write!(f, "[{}], {}", base, offset),
+        }
+    }
 }
 
 impl fmt::Display for Instr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} ", mnemonic(self.opcode))?;
+        match self.opcode {
+            Opcode::BCOND => write!(f, "B.{:?} ", self.condition_code)?,
+            _ => write!(f, "{} ", mnemonic(self.opcode))?,
+        }
 
         match self.opcode {
             Opcode::ADD |
@@ -549,9 +1560,52 @@ impl fmt::Display for Instr {
             Opcode::SDIV |
             Opcode::AND |
             Opcode::ORR |
-            Opcode::EOR => write!(f, "{}, {}, {}", self.sink[0], self.source[0], self.source[1])?,
-            Opcode::LDR => write!(f, "{}, {}", self.sink[0], self.source[0])?,
-            Opcode::STR => write!(f, "{}, {}", self.source[0], self.sink[0])?,
+            Opcode::EOR |
+            Opcode::LSL |
+            Opcode::LSR |
+            Opcode::ASR |
+            Opcode::ROR |
+            Opcode::UDIV |
+            Opcode::SMULH |
+            Opcode::UMULH => write!(f, "{}, {}, {}", self.sink[0], self.source[0], self.source[1])?,
+            Opcode::MADD |
+            Opcode::MSUB => write!(f, "{}, {}, {}, {}", self.sink[0], self.source[0], self.source[1], self.source[2])?,
+            Opcode::CLZ |
+            Opcode::CLS |
+            Opcode::RBIT |
+            Opcode::REV => write!(f, "{}, {}", self.sink[0], self.source[0])?,
+            Opcode::UBFX |
+            Opcode::SBFX |
+            Opcode::EXTR => write!(f, "{}, {}, {}, {}", self.sink[0], self.source[0], self.source[1], self.source[2])?,
+            Opcode::MOVZ |
+            Opcode::MOVN => write!(f, "{}, {}, LSL {}", self.sink[0], self.source[0], self.source[1])?,
+            Opcode::MOVK => write!(f, "{}, {}, LSL {}", self.sink[0], self.source[1], self.source[2])?,
+            Opcode::BFI => write!(f, "{}, {}, {}, {}", self.sink[0], self.source[1], self.source[2], self.source[3])?,
+            Opcode::SMULL |
+            Opcode::UMULL => write!(f, "{}, {}, {}", self.sink[0], WRegister(self.source[0].get_register()), WRegister(self.source[1].get_register()))?,
+            Opcode::LDAR |
+            Opcode::LDR |
+            Opcode::LDRB |
+            Opcode::LDRH |
+            Opcode::LDRSW => {
+                write!(f, "{}, ", self.sink[0])?;
+                self.fmt_address(f, 0)?
+            }
+            Opcode::STLR |
+            Opcode::STR |
+            Opcode::STRB |
+            Opcode::STRH => {
+                write!(f, "{}, ", self.source[0])?;
+                self.fmt_address(f, 1)?
+            }
+            Opcode::LDP => {
+                write!(f, "{}, {}, ", self.sink[0], self.sink[1])?;
+                self.fmt_address(f, 0)?
+            }
+            Opcode::STP => {
+                write!(f, "{}, {}, ", self.source[0], self.source[1])?;
+                self.fmt_address(f, 2)?
+            }
             Opcode::MOV => write!(f, "{}, {}", self.sink[0], self.source[1])?,
             Opcode::NOP => {}
             Opcode::ADR => write!(f, "{}, {}", self.sink[0], self.source[0])?,
@@ -564,15 +1618,34 @@ impl fmt::Display for Instr {
             Opcode::CBNZ => write!(f, "{}, {}", self.source[0], self.source[1])?,
             Opcode::NEG => write!(f, "{}, {}", self.sink[0], self.source[0])?,
             Opcode::MVN => write!(f, "{}, {}", self.sink[0], self.source[0])?,
+            Opcode::MRS => write!(f, "{}, {}", self.sink[0], self.source[0])?,
+            Opcode::TST |
+            Opcode::CMN |
             Opcode::CMP => write!(f, "{}, {}", self.source[0], self.source[1])?,
+            Opcode::ADDS |
+            Opcode::SUBS |
+            Opcode::ANDS => write!(f, "{}, {}, {}", self.sink[0], self.source[0], self.source[1])?,
+            Opcode::NEGS => write!(f, "{}, {}", self.sink[0], self.source[0])?,
             Opcode::EXIT => {}
             Opcode::DSB => {}
-            Opcode::BEQ |
-            Opcode::BNE |
-            Opcode::BLT |
-            Opcode::BLE |
-            Opcode::BGT |
-            Opcode::BGE => write!(f, "{}", self.source[0])?,
+            Opcode::DMB => write!(f, "{}", self.source[0])?,
+            Opcode::ISB => {}
+            Opcode::CLREX => {}
+            Opcode::LDXR => write!(f, "{}, {}", self.sink[0], self.source[0])?,
+            Opcode::STXR => write!(f, "{}, {}, {}", self.sink[0], self.source[1], self.source[0])?,
+            Opcode::LDADD |
+            Opcode::SWP => write!(f, "{}, {}, {}", self.source[1], self.sink[0], self.source[0])?,
+            Opcode::CAS => write!(f, "{}, {}, {}", self.source[1], self.source[2], self.source[0])?,
+            Opcode::BCOND => write!(f, "{}", self.source[0])?,
+            Opcode::CSEL |
+            Opcode::CSINC |
+            Opcode::CSNEG => write!(f, "{}, {}, {}, {:?}", self.sink[0], self.source[0], self.source[1], self.condition_code)?,
+            Opcode::CSET => write!(f, "{}, {:?}", self.sink[0], self.condition_code)?,
+            Opcode::CCMP => write!(f, "{}, {}, {}, {:?}", self.source[0], self.source[1], self.source[3], self.condition_code)?,
+        }
+
+        if self.shift != Shift::LSL(0) {
+            write!(f, ", {}", self.shift)?;
         }
 
         if let Some(loc) = self.loc {
@@ -596,6 +1669,31 @@ pub(crate) enum Operand {
 
     MemRegisterIndirect(RegisterType),
 
+    // The memory operands below are only used by the loader; when the instruction is created,
+    // they are replaced by a MemRegisterIndirect with the base register and an offset operand.
+    // [base, #offset]
+    MemRegIndirectWithOffset(RegisterType, DWordType),
+    // [base, offset register, LSL #shift]
+    MemRegIndirectWithRegOffset(RegisterType, RegisterType, u8),
+    // [base, #offset]!
+    MemPreIndexed(RegisterType, DWordType),
+
+    SystemRegister(SystemRegisterType),
+
+    BarrierOption(BarrierOptionType),
+
+    // The condition of a conditional select or compare. It is only used by the loader; when the
+    // instruction is created, it is replaced by the condition code of the instruction.
+    Condition(ConditionCode),
+
+    // The shift of a shifted register operand. It is only used by the loader; when the
+    // instruction is created, it is replaced by the shift of the instruction.
+    Shift(Shift),
+
+    // The lower 32 bits of a general purpose register. It is only used by the loader; when the
+    // instruction is created, it is replaced by the register.
+    WRegister(RegisterType),
+
     Unused,
 }
 
@@ -608,6 +1706,14 @@ impl Operand {
             Code(_) => "Code",
             Unused => "Unused",
             MemRegisterIndirect(_) => "MemRegisterIndirect",
+            MemRegIndirectWithOffset(_, _) => "MemRegIndirectWithOffset",
+            MemRegIndirectWithRegOffset(_, _, _) => "MemRegIndirectWithRegOffset",
+            MemPreIndexed(_, _) => "MemPreIndexed",
+            SystemRegister(_) => "SystemRegister",
+            BarrierOption(_) => "BarrierOption",
+            Condition(_) => "Condition",
+            Operand::Shift(_) => "Shift",
+            WRegister(_) => "WRegister",
         }
     }
 }
@@ -622,6 +1728,7 @@ impl fmt::Display for Operand {
                     SP => write!(f, "SP"),
                     PC => write!(f, "PC"),
                     CPSR => write!(f, "CPSR"),
+                    ZR => write!(f, "XZR"),
                     _ => write!(f, "R{}", reg),
                 }
             }  // Add a comma here
@@ -631,6 +1738,15 @@ impl fmt::Display for Operand {
             Memory(addr) => write!(f, "[{}]", addr),
             Unused => write!(f, "Unused"),
             MemRegisterIndirect(reg) => write!(f, "[{}]", Register(*reg)),
+            MemRegIndirectWithOffset(reg, offset) => write!(f, "[{}, #{}]", Register(*reg), offset),
+            MemRegIndirectWithRegOffset(reg, offset, shift) => write!(f, "[{}, {}, LSL #{}]", Register(*reg), Register(*offset), shift),
+            MemPreIndexed(reg, offset) => write!(f, "[{}, #{}]!", Register(*reg), offset),
+            SystemRegister(SystemRegisterType::MPIDR) => write!(f, "MPIDR_EL1"),
+            BarrierOption(option) => write!(f, "{:?}", option),
+            Condition(condition_code) => write!(f, "{:?}", condition_code),
+            Operand::Shift(shift) => write!(f, "{}", shift),
+            WRegister(ZR) => write!(f, "WZR"),
+            WRegister(reg) => write!(f, "W{}", reg),
         }
     }
 }
@@ -646,6 +1762,13 @@ impl Operand {
         }
     }
 
+    pub(crate) fn get_mem_register(&self) -> RegisterType {
+        match *self {
+            MemRegisterIndirect(reg) => reg,
+            _ => panic!("Operand is not a MemRegisterIndirect but of type {:?}", self),
+        }
+    }
+
     pub(crate) fn get_immediate(&self) -> DWordType {
         match self {
             Operand::Immediate(constant) => *constant,
@@ -12,18 +12,24 @@ use std::fmt::Debug;
 /// at some point.
 #[derive(Debug)]
 pub enum ASTOperand {
-    // register, position
-    Register(u64, usize),
     // value, position
     Immediate(u64, usize),
-    // label name, position
-    Label(String, usize),
+    // a name that is a register, label, condition or barrier option; it depends on the instruction, position
+    Name(String, usize),
     // the name of the variable
     AddressOf(String, usize),
-    // register, offset, position
-    MemRegisterIndirect(u64, usize),
-    //MemRegIndirectWithOffset(u64, u64, usize),
-    //MemRegIndirectWithRegOffset(u64, u64, usize),
+    // register name, position
+    MemRegisterIndirect(String, usize),
+    // register name, offset, position
+    MemRegIndirectWithOffset(String, u64, usize),
+    // register name, offset register name, the name of the shift, shift, position
+    MemRegIndirectWithRegOffset(String, String, String, u64, usize),
+    // register name, offset, position; the register is updated with the address before the access
+    MemPreIndexed(String, u64, usize),
+    // the name of the system register, position
+    SystemRegister(String, usize),
+    // the name of the shift, amount, position
+    Shift(String, u64, usize),
     Unused(),
 }
 
@@ -40,6 +46,7 @@ pub struct ASTInstr {
     pub op1: ASTOperand,
     pub op2: ASTOperand,
     pub op3: ASTOperand,
+    pub op4: ASTOperand,
     pub pos: usize,
 }
 
@@ -69,7 +76,7 @@ pub struct ASTLabel {
 
 #[derive(Debug)]
 pub enum ASTTextLine {
-    Text(ASTInstr),
+    Text(Box<ASTInstr>),
     Directive(ASTDirective),
     Label(ASTLabel),
 }
@@ -131,6 +138,7 @@ impl ASTInstr {
         if !self.op1.accept(visitor) { return false; }
         if !self.op2.accept(visitor) { return false; }
         if !self.op3.accept(visitor) { return false; }
+        if !self.op4.accept(visitor) { return false; }
         visitor.visit_instr(self)
     }
 }
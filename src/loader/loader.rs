@@ -7,11 +7,12 @@ use lalrpop_util::ParseError;
 use regex::Regex;
 
 use crate::assembly;
-use crate::cpu::{CPUConfig, GENERAL_ARG_REG_CNT};
-use crate::instructions::instructions::{create_instr, Data, DWordType, get_opcode, Instr, Opcode, Operand, Program, RegisterType, SourceLocation};
+use crate::cpu::{CPUConfig, FP, GENERAL_ARG_REG_CNT, LR, PC, SP, ZR};
+use crate::instructions::instructions::{create_instr, validate_immediate_encoding, Data, DWordType, get_barrier_option, get_branch_condition, get_condition_code, get_opcode, get_shift, get_system_register, Instr, Opcode, Operand, Program, RegisterType, SourceLocation};
 use crate::instructions::instructions::Operand::Register;
 use crate::loader::ast::{ASTAssemblyFile, ASTData, ASTDirective, ASTInstr, ASTLabel, ASTOperand, ASTVisitor};
 use crate::loader::loader::LoadError::AnalysisError;
+use crate::memory_subsystem::memory_subsystem::WORD_SIZE;
 
 struct Loader {
     cpu_config: CPUConfig,
@@ -119,7 +120,7 @@ pub struct SymbolScan<'a> {
 
 impl ASTVisitor for SymbolScan<'_> {
     fn visit_data(&mut self, ast_data: &ASTData) -> bool {
-        if self.loader.heap_limit == self.loader.cpu_config.memory_size {
+        if self.loader.heap_limit + WORD_SIZE as u32 > self.loader.cpu_config.memory_size {
             let loc = self.loader.to_source_location(ast_data.pos);
             self.loader.errors.push(format!("Insufficient heap to declare variable '{}' at {}:{}", ast_data.name, loc.line, loc.column));
             return false;
@@ -142,7 +143,7 @@ impl ASTVisitor for SymbolScan<'_> {
 
         self.loader.data_section.insert(ast_data.name.clone(),
                                         Rc::new(Data { value: ast_data.value as DWordType, offset: self.loader.heap_limit as u64 }));
-        self.loader.heap_limit += 1;
+        self.loader.heap_limit += WORD_SIZE as u32;
         true
     }
 
@@ -172,29 +173,20 @@ pub struct ProgramGeneration<'a> {
     operand_stack: Vec<Operand>,
 }
 
-impl ASTVisitor for ProgramGeneration<'_> {
-    fn visit_operand(&mut self, ast_operand: &ASTOperand) -> bool {
+impl ProgramGeneration<'_> {
+    // Converts the operand of the instruction and pushes it on the operand stack. The operands are
+    // converted when the opcode is known, because what a name refers to depends on the instruction.
+    fn push_operand(&mut self, ast_operand: &ASTOperand, opcode: Opcode, is_last: bool) -> bool {
         match ast_operand {
-            ASTOperand::Register(reg, pos) => {
-                if *reg >= GENERAL_ARG_REG_CNT as u64 {
-                    let loc = self.loader.to_source_location(*pos);
-                    self.loader.errors.push(format!("Unknown register r'{}' at {}:{}", *reg, loc.line, loc.column));
-                    return false;
-                }
-
-                self.operand_stack.push(Register(*reg as RegisterType));
-            }
             ASTOperand::Immediate(value, _) => {
                 self.operand_stack.push(Operand::Immediate(*value as DWordType));
             }
-            ASTOperand::Label(label_name, pos) => {
-                match self.loader.labels.get(label_name) {
-                    Some(code_address) => {
-                        self.operand_stack.push(Operand::Code(*code_address as DWordType));
-                    }
-                    None => {
+            ASTOperand::Name(name, pos) => {
+                match self.resolve_name(name, opcode, is_last) {
+                    Ok(operand) => self.operand_stack.push(operand),
+                    Err(msg) => {
                         let loc = self.loader.to_source_location(*pos);
-                        self.loader.errors.push(format!("Unknown label '{}' at {}:{}", label_name, loc.line, loc.column));
+                        self.loader.errors.push(format!("{} at {}:{}", msg, loc.line, loc.column));
                         return false;
                     }
                 }
@@ -213,15 +205,108 @@ impl ASTVisitor for ProgramGeneration<'_> {
             }
 
             ASTOperand::Unused() => {}
-            ASTOperand::MemRegisterIndirect(register, _pos) => {
-                self.operand_stack.push(Operand::MemRegisterIndirect(*register as RegisterType));
+            ASTOperand::MemRegisterIndirect(register, pos) => {
+                let Some(register) = self.address_register(register, *pos) else { return false };
+                self.operand_stack.push(Operand::MemRegisterIndirect(register));
+            }
+            ASTOperand::MemRegIndirectWithOffset(register, offset, pos) => {
+                let Some(register) = self.address_register(register, *pos) else { return false };
+                self.operand_stack.push(Operand::MemRegIndirectWithOffset(register, *offset as DWordType));
+            }
+            ASTOperand::MemRegIndirectWithRegOffset(register, offset, shift_name, shift, pos) => {
+                let Some(register) = self.address_register(register, *pos) else { return false };
+                let Some(offset) = self.address_register(offset, *pos) else { return false };
+                if !shift_name.eq_ignore_ascii_case("LSL") || *shift > 3 {
+                    let loc = self.loader.to_source_location(*pos);
+                    self.loader.errors.push(format!("Illegal shift {} #{} at {}:{}", shift_name, shift, loc.line, loc.column));
+                    return false;
+                }
+                self.operand_stack.push(Operand::MemRegIndirectWithRegOffset(register, offset, *shift as u8));
+            }
+            ASTOperand::MemPreIndexed(register, offset, pos) => {
+                let Some(register) = self.address_register(register, *pos) else { return false };
+                self.operand_stack.push(Operand::MemPreIndexed(register, *offset as DWordType));
+            }
+            ASTOperand::SystemRegister(name, pos) => {
+                match get_system_register(name) {
+                    Some(system_register) => {
+                        self.operand_stack.push(Operand::SystemRegister(system_register));
+                    }
+                    None => {
+                        let loc = self.loader.to_source_location(*pos);
+                        self.loader.errors.push(format!("Unknown system register '{}' at {}:{}", name, loc.line, loc.column));
+                        return false;
+                    }
+                }
+            }
+            ASTOperand::Shift(name, amount, pos) => {
+                if *amount > 63 {
+                    let loc = self.loader.to_source_location(*pos);
+                    self.loader.errors.push(format!("Illegal shift #{} at {}:{}", amount, loc.line, loc.column));
+                    return false;
+                }
+                match get_shift(name, *amount as u8) {
+                    Some(shift) => {
+                        self.operand_stack.push(Operand::Shift(shift));
+                    }
+                    None => {
+                        let loc = self.loader.to_source_location(*pos);
+                        self.loader.errors.push(format!("Unknown shift '{}' at {}:{}", name, loc.line, loc.column));
+                        return false;
+                    }
+                }
             }
-            //ASTOperand::MemoryAccessWithImmediate(_, _, _) => {}
         };
 
         true
     }
 
+    // Returns the X register of a memory operand.
+    fn address_register(&mut self, name: &str, pos: usize) -> Option<RegisterType> {
+        match get_register(name) {
+            Some(Register(register)) => Some(register),
+            _ => {
+                let loc = self.loader.to_source_location(pos);
+                self.loader.errors.push(format!("Unknown register '{}' at {}:{}", name, loc.line, loc.column));
+                None
+            }
+        }
+    }
+
+    // Resolves a name operand. A branch target is preferably a label, so a label can have the
+    // name of a register or condition; in the other operands the register or condition takes
+    // precedence. The operand of a barrier is always a barrier option.
+    fn resolve_name(&self, name: &str, opcode: Opcode, is_last: bool) -> Result<Operand, String> {
+        if matches!(opcode, Opcode::DMB | Opcode::DSB) {
+            return get_barrier_option(name)
+                .map(Operand::BarrierOption)
+                .ok_or(format!("Unknown barrier option '{}'", name));
+        }
+
+        let label = self.loader.labels.get(name).map(|code_address| Operand::Code(*code_address as DWordType));
+        let is_branch_target = is_last && matches!(opcode, Opcode::B | Opcode::BL | Opcode::BCOND | Opcode::CBZ | Opcode::CBNZ);
+        if let (Some(label), true) = (label, is_branch_target) {
+            return Ok(label);
+        }
+
+        if let Some(register) = get_register(name) {
+            return Ok(register);
+        }
+
+        if let Some(condition_code) = get_condition_code(name) {
+            return Ok(Operand::Condition(condition_code));
+        }
+
+        match label {
+            Some(label) => Ok(label),
+            None if is_last && matches!(opcode, Opcode::CSEL | Opcode::CSINC | Opcode::CSNEG | Opcode::CSET | Opcode::CCMP) =>
+                Err(format!("Unknown condition '{}'", name)),
+            None => Err(format!("Unknown label '{}'", name)),
+        }
+    }
+}
+
+impl ASTVisitor for ProgramGeneration<'_> {
     fn visit_instr(&mut self, ast_instr: &ASTInstr) -> bool {
         // todo: this is very inefficient because for every instruction the whole file content is scanned.
         let loc = self.loader.to_source_location(ast_instr.pos);
@@ -233,8 +318,28 @@ impl ASTVisitor for ProgramGeneration<'_> {
         }
 
         let opcode = opcode_option.unwrap();
+        let ast_operands: Vec<&ASTOperand> = [&ast_instr.op1, &ast_instr.op2, &ast_instr.op3, &ast_instr.op4].into_iter()
+            .filter(|ast_operand| !matches!(ast_operand, ASTOperand::Unused()))
+            .collect();
+        for (k, ast_operand) in ast_operands.iter().enumerate() {
+            if !self.push_operand(ast_operand, opcode, k + 1 == ast_operands.len()) {
+                self.operand_stack.clear();
+                return false;
+            }
+        }
+
+        if opcode == Opcode::BCOND {
+            // the condition is part of the mnemonic
+            self.operand_stack.push(Operand::Condition(get_branch_condition(&ast_instr.mnemonic).unwrap()));
+        }
+
         match create_instr(opcode, &self.operand_stack, loc) {
             Ok(instr) => {
+                if self.loader.cpu_config.strict_immediates {
+                    if let Err(msg) = validate_immediate_encoding(&instr) {
+                        self.loader.errors.push(format!("{} at {}:{}", msg, loc.line, loc.column));
+                    }
+                }
                 self.loader.code.push(instr);
             }
             Err(msg) => {
@@ -264,6 +369,34 @@ impl ASTVisitor for ProgramGeneration<'_> {
     }
 }
 
+// Returns the register with the given name: r0..r30, x0..x30, fp, sp, lr, pc and xzr, or one of
+// the 32 bit views w0..w30 and wzr.
+fn get_register(name: &str) -> Option<Operand> {
+    let name = name.to_uppercase();
+    match name.as_str() {
+        "FP" => return Some(Register(FP)),
+        "SP" => return Some(Register(SP)),
+        "LR" => return Some(Register(LR)),
+        "PC" => return Some(Register(PC)),
+        "XZR" => return Some(Register(ZR)),
+        "WZR" => return Some(Operand::WRegister(ZR)),
+        _ => {}
+    }
+
+    let re = Regex::new(r"^([RXW])(0|[1-9][0-9]?)$").unwrap();
+    let captures = re.captures(&name)?;
+    let register = captures[2].parse::<RegisterType>().unwrap();
+    if register >= GENERAL_ARG_REG_CNT {
+        return None;
+    }
+
+    if &captures[1] == "W" {
+        Some(Operand::WRegister(register))
+    } else {
+        Some(Register(register))
+    }
+}
+
 fn is_valid_variable_name(name: &String) -> bool {
     if name.is_empty() {
         return false;
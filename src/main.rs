@@ -7,6 +7,7 @@ use structopt::StructOpt;
 
 use crate::cpu::{CPU, load_cpu_config};
 use crate::loader::loader::{load_from_file, LoadError};
+use crate::system::System;
 
 mod cpu;
 mod loader;
@@ -15,6 +16,7 @@ mod backend;
 mod instructions;
 mod memory_subsystem;
 mod cpu_tests;
+mod system;
 
 
 lalrpop_mod!(pub assembly, "/loader/assembly.rs");
@@ -77,11 +79,13 @@ fn main() {
         }
     };
 
-    let mut cpu = CPU::new(&cpu_config);
-    cpu.run(&program);
+    let mut system = System::new(&cpu_config);
+    system.run(&program);
 
     if opt.stats {
-        show_stats(&mut cpu);
+        for core in &system.cores {
+            show_stats(core);
+        }
     }
 }
 
@@ -98,8 +102,13 @@ fn show_stats(cpu: &CPU) {
         0.0
     };
 
-    println!("-------------------- [ stats ] -------------------------");
+    println!("-------------------- [ stats core {} ] -------------------", cpu.core_id);
     println!("ipc {:.2}", ipc);
+    if perf_counters.threads.len() > 1 {
+        for (thread_id, thread) in perf_counters.threads.iter().enumerate() {
+            println!("thread {} ipc {:.2}", thread_id, thread.retired_cnt as f32 / perf_counters.cycle_cnt as f32);
+        }
+    }
     println!("branch pred {:.2}%", branch_prediction);
     println!("branch miss prediction cnt: {}", perf_counters.branch_miss_prediction_cnt);
     println!("branch good predictions cnt: {}", perf_counters.branch_good_predictions_cnt);
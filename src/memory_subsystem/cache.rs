@@ -0,0 +1,325 @@
+use crate::cpu::{CacheConfig, ReplacementPolicy};
+
+// The state of a cache line in the MESI coherence protocol. Only the L1D uses the
+// SHARED and MODIFIED states; a line in an outer cache is either INVALID or EXCLUSIVE.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum MESIState {
+    // the line isn't in the cache
+    INVALID,
+    // the line can be read; other caches can have a copy as well
+    SHARED,
+    // the line can be read and written; no other cache has a copy
+    EXCLUSIVE,
+    // the line has been written; no other cache has a copy
+    MODIFIED,
+}
+
+// A cache line only tracks the tag; the data itself is kept in main memory. So the cache
+// is used to model the timing of memory accesses.
+#[derive(Clone, Copy)]
+struct CacheLine {
+    tag: u64,
+    state: MESIState,
+    // the value of the access counter of the cache when the line was last accessed (lru)
+    last_access: u64,
+}
+
+struct CacheSet {
+    lines: Vec<CacheLine>,
+    // the bits of the binary tree for the pseudo lru; node 0 is unused and the root is
+    // node 1. A bit that is set means the right subtree is the least recently used one.
+    plru_bits: Vec<bool>,
+}
+
+/// A set associative cache.
+pub(crate) struct Cache {
+    pub(crate) name: String,
+    sets: Vec<CacheSet>,
+    set_count: u64,
+    associativity: usize,
+    line_size: u64,
+    pub(crate) hit_latency: u8,
+    replacement: ReplacementPolicy,
+    access_cnt: u64,
+    // the state of the xorshift random generator used for random replacement
+    random_state: u64,
+    pub(crate) hit_cnt: u64,
+    pub(crate) miss_cnt: u64,
+    pub(crate) eviction_cnt: u64,
+}
+
+impl Cache {
+    pub(crate) fn new(name: &str, cache_config: &CacheConfig) -> Cache {
+        let line_size = cache_config.line_size as u64;
+        let associativity = cache_config.associativity as usize;
+
+        assert!(line_size.is_power_of_two(), "{}: line_size {} should be a power of 2", name, line_size);
+        assert!(associativity.is_power_of_two(), "{}: associativity {} should be a power of 2", name, associativity);
+        assert!((cache_config.size as u64).is_multiple_of(line_size * associativity as u64),
+                "{}: size {} should be a multiple of line_size * associativity", name, cache_config.size);
+
+        let set_count = cache_config.size as u64 / (line_size * associativity as u64);
+        assert!(set_count.is_power_of_two(), "{}: the number of sets {} should be a power of 2", name, set_count);
+
+        let mut sets = Vec::with_capacity(set_count as usize);
+        for _ in 0..set_count {
+            sets.push(CacheSet {
+                lines: vec![CacheLine { tag: 0, state: MESIState::INVALID, last_access: 0 }; associativity],
+                plru_bits: vec![false; associativity],
+            });
+        }
+
+        Cache {
+            name: name.to_string(),
+            sets,
+            set_count,
+            associativity,
+            line_size,
+            hit_latency: cache_config.hit_latency,
+            replacement: cache_config.replacement.clone(),
+            access_cnt: 0,
+            random_state: 0x2545F4914F6CDD1D,
+            hit_cnt: 0,
+            miss_cnt: 0,
+            eviction_cnt: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for set in &mut self.sets {
+            for line in &mut set.lines {
+                line.state = MESIState::INVALID;
+            }
+            set.plru_bits.fill(false);
+        }
+    }
+
+    // Returns the address of the first byte of the line containing the address.
+    pub(crate) fn line_addr(&self, addr: u64) -> u64 {
+        addr & !(self.line_size - 1)
+    }
+
+    fn set_and_tag(&self, addr: u64) -> (usize, u64) {
+        let line_number = addr / self.line_size;
+        ((line_number % self.set_count) as usize, line_number / self.set_count)
+    }
+
+    fn find_way(&self, set_index: usize, tag: u64) -> Option<usize> {
+        self.sets[set_index].lines.iter().position(|line| line.state != MESIState::INVALID && line.tag == tag)
+    }
+
+    // Checks if the line containing the address is in the cache without changing any state.
+    pub(crate) fn contains(&self, addr: u64) -> bool {
+        let (set_index, tag) = self.set_and_tag(addr);
+        self.find_way(set_index, tag).is_some()
+    }
+
+    // Returns the state of the line containing the address without changing any replacement state.
+    pub(crate) fn state(&self, addr: u64) -> MESIState {
+        let (set_index, tag) = self.set_and_tag(addr);
+        match self.find_way(set_index, tag) {
+            Some(way) => self.sets[set_index].lines[way].state,
+            None => MESIState::INVALID,
+        }
+    }
+
+    // Changes the state of the line containing the address; INVALID removes the line. Does
+    // nothing if the line isn't in the cache.
+    pub(crate) fn set_state(&mut self, addr: u64, state: MESIState) {
+        let (set_index, tag) = self.set_and_tag(addr);
+        if let Some(way) = self.find_way(set_index, tag) {
+            self.sets[set_index].lines[way].state = state;
+        }
+    }
+
+    // Accesses the cache. Returns true if the access was a hit. On a miss the line isn't
+    // filled; that is done when the line arrives from the next level.
+    pub(crate) fn access(&mut self, addr: u64) -> bool {
+        let (set_index, tag) = self.set_and_tag(addr);
+        if let Some(way) = self.find_way(set_index, tag) {
+            self.hit_cnt += 1;
+            self.touch(set_index, way);
+            true
+        } else {
+            self.miss_cnt += 1;
+            false
+        }
+    }
+
+    // Puts the line containing the address in the cache in the EXCLUSIVE state. Returns the
+    // address of the evicted line if a valid line needed to be evicted.
+    pub(crate) fn fill(&mut self, addr: u64) -> Option<u64> {
+        let (set_index, tag) = self.set_and_tag(addr);
+        if let Some(way) = self.find_way(set_index, tag) {
+            self.touch(set_index, way);
+            return None;
+        }
+
+        let way = self.victim(set_index);
+        let line = self.sets[set_index].lines[way];
+        let evicted = if line.state != MESIState::INVALID {
+            self.eviction_cnt += 1;
+            Some((line.tag * self.set_count + set_index as u64) * self.line_size)
+        } else {
+            None
+        };
+
+        let line = &mut self.sets[set_index].lines[way];
+        line.tag = tag;
+        line.state = MESIState::EXCLUSIVE;
+        self.touch(set_index, way);
+        evicted
+    }
+
+    fn touch(&mut self, set_index: usize, way: usize) {
+        self.access_cnt += 1;
+        let set = &mut self.sets[set_index];
+        set.lines[way].last_access = self.access_cnt;
+
+        // point every node on the path to the way away from the way.
+        let mut node = 1;
+        let mut low = 0;
+        let mut size = self.associativity;
+        while size > 1 {
+            let half = size / 2;
+            if way < low + half {
+                set.plru_bits[node] = true;
+                node *= 2;
+            } else {
+                set.plru_bits[node] = false;
+                node = 2 * node + 1;
+                low += half;
+            }
+            size = half;
+        }
+    }
+
+    fn victim(&mut self, set_index: usize) -> usize {
+        let set = &self.sets[set_index];
+        if let Some(way) = set.lines.iter().position(|line| line.state == MESIState::INVALID) {
+            return way;
+        }
+
+        match self.replacement {
+            ReplacementPolicy::LRU => {
+                let mut victim = 0;
+                for way in 1..self.associativity {
+                    if set.lines[way].last_access < set.lines[victim].last_access {
+                        victim = way;
+                    }
+                }
+                victim
+            }
+            ReplacementPolicy::PLRU => {
+                let mut node = 1;
+                let mut low = 0;
+                let mut size = self.associativity;
+                while size > 1 {
+                    let half = size / 2;
+                    if set.plru_bits[node] {
+                        node = 2 * node + 1;
+                        low += half;
+                    } else {
+                        node *= 2;
+                    }
+                    size = half;
+                }
+                low
+            }
+            ReplacementPolicy::RANDOM => {
+                self.random_state ^= self.random_state << 13;
+                self.random_state ^= self.random_state >> 7;
+                self.random_state ^= self.random_state << 17;
+                (self.random_state % self.associativity as u64) as usize
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cache(replacement: ReplacementPolicy) -> Cache {
+        // 2 sets, 4 ways, 64 byte lines
+        let cache_config = CacheConfig {
+            size: 512,
+            line_size: 64,
+            associativity: 4,
+            hit_latency: 1,
+            replacement,
+        };
+        Cache::new("test", &cache_config)
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let mut cache = new_cache(ReplacementPolicy::LRU);
+
+        assert!(!cache.access(0));
+        cache.fill(0);
+        assert!(cache.access(8));
+        assert!(cache.access(63));
+        assert!(!cache.access(64));
+        assert_eq!(cache.hit_cnt, 2);
+        assert_eq!(cache.miss_cnt, 2);
+        assert_eq!(cache.eviction_cnt, 0);
+    }
+
+    #[test]
+    fn test_lru() {
+        let mut cache = new_cache(ReplacementPolicy::LRU);
+
+        // lines 0, 128, 256, 384 and 512 all map to set 0
+        for addr in [0, 128, 256, 384] {
+            cache.fill(addr);
+        }
+        cache.access(0);
+
+        assert_eq!(cache.fill(512), Some(128));
+        assert!(cache.access(0));
+        assert_eq!(cache.eviction_cnt, 1);
+    }
+
+    #[test]
+    fn test_plru() {
+        let mut cache = new_cache(ReplacementPolicy::PLRU);
+
+        for addr in [0, 128, 256, 384] {
+            cache.fill(addr);
+        }
+        cache.access(0);
+
+        // the tree points away from the most recently used ways
+        assert_eq!(cache.fill(512), Some(256));
+        assert!(cache.access(0));
+        assert!(cache.access(384));
+    }
+
+    #[test]
+    fn test_state() {
+        let mut cache = new_cache(ReplacementPolicy::LRU);
+
+        assert_eq!(cache.state(0), MESIState::INVALID);
+        cache.fill(0);
+        assert_eq!(cache.state(8), MESIState::EXCLUSIVE);
+        cache.set_state(0, MESIState::SHARED);
+        assert_eq!(cache.state(0), MESIState::SHARED);
+        cache.set_state(0, MESIState::INVALID);
+        assert!(!cache.contains(0));
+        assert!(!cache.access(0));
+    }
+
+    #[test]
+    fn test_random() {
+        let mut cache = new_cache(ReplacementPolicy::RANDOM);
+
+        for addr in [0, 128, 256, 384] {
+            cache.fill(addr);
+        }
+
+        assert!(cache.fill(512).is_some());
+        assert!(cache.access(512));
+        assert_eq!(cache.eviction_cnt, 1);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::cpu::DRAMConfig;
+
+struct Bank {
+    // the row that is currently in the row buffer
+    open_row: Option<u64>,
+    // the cycle at which the bank has completed all its outstanding requests
+    busy_until: u64,
+}
+
+/// A timing model of main memory.
+///
+/// Memory is interleaved over a number of banks on row granularity. Each bank has a row
+/// buffer containing the most recently accessed row; an access to that row is faster than an
+/// access to a different row. A bank processes its requests one at a time.
+pub(crate) struct DRAM {
+    banks: Vec<Bank>,
+    row_size: u64,
+    row_hit_latency: u64,
+    row_miss_latency: u64,
+    pub(crate) row_hit_cnt: u64,
+    pub(crate) row_miss_cnt: u64,
+}
+
+impl DRAM {
+    pub(crate) fn new(dram_config: &DRAMConfig) -> DRAM {
+        assert!(dram_config.bank_count > 0, "DRAM: bank_count should be larger than 0");
+        assert!(dram_config.row_size > 0, "DRAM: row_size should be larger than 0");
+
+        let mut banks = Vec::with_capacity(dram_config.bank_count as usize);
+        for _ in 0..dram_config.bank_count {
+            banks.push(Bank { open_row: None, busy_until: 0 });
+        }
+
+        DRAM {
+            banks,
+            row_size: dram_config.row_size as u64,
+            row_hit_latency: dram_config.row_hit_latency as u64,
+            row_miss_latency: dram_config.row_miss_latency as u64,
+            row_hit_cnt: 0,
+            row_miss_cnt: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for bank in &mut self.banks {
+            bank.open_row = None;
+            bank.busy_until = 0;
+        }
+    }
+
+    // Accesses the byte address at the given cycle. Returns the number of cycles, starting
+    // from that cycle, before the data is available; including the time waiting for the bank.
+    pub(crate) fn access(&mut self, addr: u64, cycle: u64) -> u64 {
+        let row_number = addr / self.row_size;
+        let bank_count = self.banks.len() as u64;
+        let bank = &mut self.banks[(row_number % bank_count) as usize];
+        let row = row_number / bank_count;
+
+        let latency = if bank.open_row == Some(row) {
+            self.row_hit_cnt += 1;
+            self.row_hit_latency
+        } else {
+            self.row_miss_cnt += 1;
+            bank.open_row = Some(row);
+            self.row_miss_latency
+        };
+
+        let start = bank.busy_until.max(cycle);
+        bank.busy_until = start + latency;
+        bank.busy_until - cycle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_dram() -> DRAM {
+        DRAM::new(&DRAMConfig {
+            bank_count: 2,
+            row_size: 1024,
+            row_hit_latency: 10,
+            row_miss_latency: 30,
+        })
+    }
+
+    #[test]
+    fn test_row_buffer() {
+        let mut dram = new_dram();
+
+        assert_eq!(dram.access(0, 0), 30);
+        assert_eq!(dram.access(64, 100), 10);
+        // same bank, different row
+        assert_eq!(dram.access(2048, 200), 30);
+        assert_eq!(dram.row_hit_cnt, 1);
+        assert_eq!(dram.row_miss_cnt, 2);
+    }
+
+    #[test]
+    fn test_bank_conflict() {
+        let mut dram = new_dram();
+
+        assert_eq!(dram.access(0, 0), 30);
+        // the bank is still busy with the first request
+        assert_eq!(dram.access(64, 0), 40);
+        // a different bank can be accessed in parallel
+        assert_eq!(dram.access(1024, 0), 30);
+    }
+}
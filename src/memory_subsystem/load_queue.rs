@@ -0,0 +1,181 @@
+use LQEntryState::{ALLOCATED, EXECUTED, IDLE};
+
+use crate::cpu::CPUConfig;
+use crate::instructions::instructions::DWordType;
+use crate::memory_subsystem::memory_subsystem::overlaps;
+
+enum LQEntryState {
+    // not used.
+    IDLE,
+    // it is allocated for a load
+    ALLOCATED,
+    // the load has read its value; either from the store buffer or from memory
+    EXECUTED,
+}
+
+struct LQEntry {
+    addr: DWordType,
+    // the number of bytes read
+    size: u8,
+    // the sequence of the store buffer tail at the moment the load was allocated;
+    // every store with a smaller sequence is older than the load.
+    sb_seq: u64,
+    // the sequence of the store the value was forwarded from, or None if the value
+    // was read from memory.
+    forwarded_from: Option<u64>,
+    // true if an older store to the same address executed after the load; so the load
+    // has read a stale value.
+    violation: bool,
+    state: LQEntryState,
+}
+
+impl LQEntry {
+    fn reset(&mut self) {
+        self.state = IDLE;
+        self.addr = 0;
+        self.size = 0;
+        self.sb_seq = 0;
+        self.forwarded_from = None;
+        self.violation = false;
+    }
+}
+
+/// The load queue keeps track of the loads in flight in program order so that
+/// memory ordering violations with older stores can be detected.
+pub(crate) struct LQ {
+    head: u64,
+    tail: u64,
+    entries: Vec<LQEntry>,
+    capacity: u16,
+}
+
+impl LQ {
+    pub(crate) fn new(cpu_config: &CPUConfig) -> LQ {
+        let mut entries = Vec::with_capacity(cpu_config.lq_capacity as usize);
+        for _ in 0..cpu_config.lq_capacity {
+            entries.push(LQEntry {
+                addr: 0,
+                size: 0,
+                sb_seq: 0,
+                forwarded_from: None,
+                violation: false,
+                state: IDLE,
+            })
+        }
+
+        LQ {
+            capacity: cpu_config.lq_capacity,
+            head: 0,
+            tail: 0,
+            entries,
+        }
+    }
+
+    pub(crate) fn size(&self) -> u16 {
+        return (self.tail - self.head) as u16;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    pub(crate) fn has_space(&self) -> bool {
+        return self.size() < self.capacity;
+    }
+
+    // True if cnt entries can be allocated; a pair needs two entries.
+    pub(crate) fn has_space_for(&self, cnt: u8) -> bool {
+        self.size() + cnt as u16 <= self.capacity
+    }
+
+    // The index of the entry that is allocated right after the entry at the index.
+    pub(crate) fn next_index(&self, index: u16) -> u16 {
+        (index + 1) % self.capacity
+    }
+
+    fn to_index(&self, seq: u64) -> usize {
+        (seq % self.capacity as u64) as usize
+    }
+
+    pub(crate) fn allocate(&mut self, sb_seq: u64) -> u16 {
+        assert!(self.has_space(), "LoadQueue: can't allocate because there is no space");
+
+        let index = self.to_index(self.tail);
+        let lq_entry = &mut self.entries[index];
+        lq_entry.state = ALLOCATED;
+        lq_entry.sb_seq = sb_seq;
+        self.tail += 1;
+        return index as u16;
+    }
+
+    pub(crate) fn sb_seq(&self, index: u16) -> u64 {
+        self.entries[index as usize].sb_seq
+    }
+
+    pub(crate) fn executed(&mut self, index: u16, addr: DWordType, size: u8, forwarded_from: Option<u64>) {
+        let lq_entry = &mut self.entries[index as usize];
+
+        match lq_entry.state {
+            ALLOCATED => {
+                lq_entry.addr = addr;
+                lq_entry.size = size;
+                lq_entry.forwarded_from = forwarded_from;
+                lq_entry.state = EXECUTED;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Is called when a store has resolved its address. Every younger load that overlaps with
+    // the store and already executed, and didn't get its value from a store younger than
+    // this store, has read a stale value.
+    pub(crate) fn check_violation(&mut self, store_seq: u64, addr: DWordType, size: u8) {
+        for k in self.head..self.tail {
+            let index = self.to_index(k);
+            let lq_entry = &mut self.entries[index];
+            if let EXECUTED = lq_entry.state {
+                if lq_entry.sb_seq > store_seq
+                    && overlaps(lq_entry.addr, lq_entry.size, addr, size)
+                    && lq_entry.forwarded_from.is_none_or(|seq| seq < store_seq) {
+                    lq_entry.violation = true;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn is_violated(&self, index: u16) -> bool {
+        self.entries[index as usize].violation
+    }
+
+    // Deallocates the oldest load. This is done when the load retires.
+    pub(crate) fn deallocate_head(&mut self, index: u16) {
+        debug_assert!(!self.is_empty());
+        debug_assert!(self.to_index(self.head) == index as usize, "LoadQueue: load {} is not at the head", index);
+
+        let lq_entry = &mut self.entries[index as usize];
+        match lq_entry.state {
+            EXECUTED => {
+                lq_entry.reset();
+                self.head += 1;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Deallocates the youngest load. This is done when the load is squashed; younger
+    // instructions are squashed before older ones, so the load is always at the tail.
+    pub(crate) fn deallocate_tail(&mut self, index: u16) {
+        debug_assert!(!self.is_empty());
+        debug_assert!(self.to_index(self.tail - 1) == index as usize, "LoadQueue: load {} is not at the tail", index);
+
+        let lq_entry = &mut self.entries[index as usize];
+        match lq_entry.state {
+            ALLOCATED |
+            EXECUTED => {
+                lq_entry.reset();
+                self.tail -= 1;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
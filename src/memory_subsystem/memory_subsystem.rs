@@ -1,41 +1,393 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::rc::Rc;
 
 use crate::cpu::CPUConfig;
-use crate::instructions::instructions::{DWordType, Program};
-use crate::memory_subsystem::store_buffer::SB;
+use crate::instructions::instructions::DWordType;
+use crate::memory_subsystem::cache::{Cache, MESIState};
+use crate::memory_subsystem::load_queue::LQ;
+use crate::memory_subsystem::prefetcher::{create_prefetcher, Prefetcher};
+use crate::memory_subsystem::store_buffer::{Forwarding, SB};
+use crate::memory_subsystem::uncore::Uncore;
 
+// The size of a machine word in bytes; memory is byte addressable.
+pub(crate) const WORD_SIZE: u64 = 8;
+
+// True if the accesses of size1 bytes at addr1 and of size2 bytes at addr2 have a byte in common.
+pub(crate) fn overlaps(addr1: DWordType, size1: u8, addr2: DWordType, size2: u8) -> bool {
+    addr1 < addr2 + size2 as u64 && addr2 < addr1 + size1 as u64
+}
+
+// Keeps the lowest size bytes of the value.
+pub(crate) fn truncate(value: DWordType, size: u8) -> DWordType {
+    if size as u64 >= WORD_SIZE {
+        value
+    } else {
+        value & ((1 << (size as u64 * 8)) - 1)
+    }
+}
+
+// A line fill buffer tracks an outstanding L1D miss. The way in the L1D is allocated when
+// the miss is issued, but the line can't be used before it has arrived.
+struct LFB {
+    line_addr: u64,
+    // the cycle at which the line has arrived in the L1D
+    ready_cycle: u64,
+    // true if the line is fetched by the prefetcher and no load has asked for it yet
+    prefetch: bool,
+}
+
+// An atomic access to a word in memory. The values are the source operands of the instruction.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum AtomicOp {
+    // loads the word and marks the address in the exclusive monitor of the thread
+    LDXR,
+    // stores the value if the exclusive monitor of the thread still marks the address
+    STXR(DWordType),
+    // adds the value to the word
+    LDADD(DWordType),
+    // replaces the word by the value
+    SWP(DWordType),
+    // replaces the word by the second value if it is equal to the first value
+    CAS(DWordType, DWordType),
+}
+
+// A load that is issued to the memory subsystem; see issue_load.
+pub(crate) struct LoadIssue {
+    pub(crate) thread_id: u8,
+    pub(crate) lq_index: u16,
+    pub(crate) rob_slot_index: u16,
+    pub(crate) addr: DWordType,
+    pub(crate) size: u8,
+    // the number of cycles before the address is computed
+    pub(crate) agen_cycles: u8,
+    // the pc of the load; it is used to train the prefetcher
+    pub(crate) pc: usize,
+}
+
+// A load that has been issued to the memory subsystem and is waiting for its data.
+struct LoadRequest {
+    thread_id: u8,
+    rob_slot_index: u16,
+    addr: DWordType,
+    // the operation if the request is for an atomic instead of a load
+    atomic: Option<AtomicOp>,
+    // the cycle at which the data is available
+    ready_cycle: u64,
+    // true if the load missed the L1D; so it occupies a miss status holding register
+    miss: bool,
+}
+
+/// The private part of the memory hierarchy of a single core: the store buffers, load queues,
+/// L1D and its line fill buffers. Main memory and the outer caches are shared with the other
+/// cores through the Uncore.
+///
+/// Every hardware thread of the core has its own store buffer and load queue; the L1D is
+/// shared by the threads.
 pub(crate) struct MemorySubsystem {
-    pub(crate) memory: Vec<DWordType>,
-    pub(crate) sb: SB,
+    core_id: u8,
+    pub(crate) uncore: Rc<RefCell<Uncore>>,
+    // the store buffer of every hardware thread; indexed by thread id
+    pub(crate) sbs: Vec<SB>,
+    // the load queue of every hardware thread; indexed by thread id
+    pub(crate) lqs: Vec<LQ>,
+    pub(crate) l1d: Rc<RefCell<Cache>>,
+    prefetcher: Option<Box<dyn Prefetcher>>,
+    // the lines in the L1D that have been prefetched and haven't been accessed yet
+    prefetched_lines: HashSet<u64>,
+    lfbs: Vec<LFB>,
+    lfb_count: u8,
+    load_requests: Vec<LoadRequest>,
+    mshr_count: u8,
+    cycle_cnt: u64,
+    // the sum over all cycles of the number of occupied miss status holding registers
+    pub(crate) mshr_occupied_sum: u64,
+    // the number of cycles with at least one occupied miss status holding register
+    pub(crate) mshr_busy_cycle_cnt: u64,
+    // the number of lines fetched by the prefetcher
+    pub(crate) prefetch_issued_cnt: u64,
+    // the number of prefetched lines that were accessed after they arrived in the L1D
+    pub(crate) prefetch_useful_cnt: u64,
+    // the number of prefetched lines that were accessed while they were still on their way
+    pub(crate) prefetch_late_cnt: u64,
 }
 
 impl MemorySubsystem {
-    pub fn new(cpu_config: &CPUConfig) -> MemorySubsystem {
-        let mut memory = Vec::with_capacity(cpu_config.memory_size as usize);
+    pub fn new(cpu_config: &CPUConfig, core_id: u8, uncore: &Rc<RefCell<Uncore>>) -> MemorySubsystem {
+        let sbs = (0..cpu_config.thread_count).map(|_| SB::new(cpu_config)).collect();
 
-        for _ in 0..cpu_config.memory_size {
-            memory.push(0);
-        }
+        let lqs = (0..cpu_config.thread_count).map(|_| LQ::new(cpu_config)).collect();
 
-        let sb = SB::new(cpu_config);
+        let l1d = Rc::new(RefCell::new(Cache::new("L1D", &cpu_config.l1d)));
+        uncore.borrow_mut().register_l1d(core_id, &l1d);
+
+        let prefetcher = create_prefetcher(&cpu_config.prefetcher, cpu_config.l1d.line_size as u64);
 
         MemorySubsystem {
-            memory,
-            sb,
+            core_id,
+            uncore: Rc::clone(uncore),
+            sbs,
+            lqs,
+            l1d,
+            prefetcher,
+            prefetched_lines: HashSet::new(),
+            lfbs: Vec::with_capacity(cpu_config.lfb_count as usize),
+            lfb_count: cpu_config.lfb_count,
+            load_requests: Vec::new(),
+            mshr_count: cpu_config.mshr_count,
+            cycle_cnt: 0,
+            mshr_occupied_sum: 0,
+            mshr_busy_cycle_cnt: 0,
+            prefetch_issued_cnt: 0,
+            prefetch_useful_cnt: 0,
+            prefetch_late_cnt: 0,
+        }
+    }
+
+    pub(crate) fn init(&mut self) {
+        self.l1d.borrow_mut().reset();
+        self.lfbs.clear();
+        self.prefetched_lines.clear();
+        self.load_requests.clear();
+    }
+
+    fn mshr_occupied_cnt(&self) -> usize {
+        self.load_requests.iter().filter(|request| request.miss).count()
+    }
+
+    // Issues a load that will have computed its address after agen_cycles. The load is
+    // completed once the data is available; see completed_load. If the value can be
+    // forwarded from the store buffer, the L1D isn't accessed. Returns false if the load
+    // misses the L1D and there is no miss status holding register or line fill buffer
+    // available; the load needs to be issued again.
+    pub(crate) fn issue_load(&mut self, load: &LoadIssue) -> bool {
+        let &LoadIssue { thread_id, lq_index, rob_slot_index, addr, size, agen_cycles, pc } = load;
+        let sb_seq = self.lqs[thread_id as usize].sb_seq(lq_index);
+        let (data_cycles, miss) = if let Forwarding::FULL(..) = self.sbs[thread_id as usize].forward(sb_seq, addr, size) {
+            (0, false)
+        } else {
+            // an access that crosses a line boundary is timed as an access to its first line
+            let miss = !self.is_l1d_hit(addr);
+            if miss && self.mshr_occupied_cnt() == self.mshr_count as usize {
+                return false;
+            }
+
+            let Some(cycles) = self.request_line(addr, false) else {
+                return false;
+            };
+
+            self.prefetch(pc, addr, miss);
+            (cycles, miss)
+        };
+
+        let ready_cycle = self.cycle_cnt + agen_cycles as u64 + self.l1d.borrow().hit_latency as u64 + data_cycles;
+        self.load_requests.push(LoadRequest { thread_id, rob_slot_index, addr, atomic: None, ready_cycle, miss });
+        true
+    }
+
+    // Issues an atomic that will have computed its address after agen_cycles. The line is
+    // requested in the MODIFIED state, and once it has arrived the atomic is completed like a
+    // load; see completed_load and atomic. Returns false if there is no line fill buffer available;
+    // the atomic needs to be issued again.
+    pub(crate) fn issue_atomic(&mut self, thread_id: u8, addr: DWordType, rob_slot_index: u16, agen_cycles: u8, op: AtomicOp) -> bool {
+        debug_assert!(self.sbs[thread_id as usize].is_empty(), "An atomic should wait for the store buffer to drain");
+
+        let Some(cycles) = self.request_line(addr, true) else {
+            return false;
+        };
+
+        let ready_cycle = self.cycle_cnt + agen_cycles as u64 + self.l1d.borrow().hit_latency as u64 + cycles;
+        self.load_requests.push(LoadRequest { thread_id, rob_slot_index, addr, atomic: Some(op), ready_cycle, miss: false });
+        true
+    }
+
+    // Removes a load or atomic whose data is available and returns its thread id, rob slot
+    // index, address and atomic operation.
+    pub(crate) fn completed_load(&mut self) -> Option<(u8, u16, DWordType, Option<AtomicOp>)> {
+        let pos = self.load_requests.iter().position(|request| request.ready_cycle <= self.cycle_cnt)?;
+        let request = self.load_requests.remove(pos);
+        Some((request.thread_id, request.rob_slot_index, request.addr, request.atomic))
+    }
+
+    // Performs the atomic of the thread on memory and returns the value for its destination register.
+    pub(crate) fn atomic(&mut self, thread_id: u8, addr: DWordType, op: AtomicOp) -> DWordType {
+        self.uncore.borrow_mut().atomic(self.core_id, thread_id, addr, op)
+    }
+
+    // Clears the exclusive monitor of the thread; see CLREX.
+    pub(crate) fn clear_exclusive(&mut self, thread_id: u8) {
+        self.uncore.borrow_mut().clear_exclusive(self.core_id, thread_id);
+    }
+
+    // Removes the load for the rob slot of the thread, if any, because it got squashed.
+    pub(crate) fn cancel_load(&mut self, thread_id: u8, rob_slot_index: u16) {
+        self.load_requests.retain(|request| request.thread_id != thread_id || request.rob_slot_index != rob_slot_index);
+    }
+
+    // Checks if the line containing the byte address can be read from the L1D.
+    fn is_l1d_hit(&self, addr: u64) -> bool {
+        let l1d = self.l1d.borrow();
+        let line_addr = l1d.line_addr(addr);
+        l1d.contains(addr) && !self.lfbs.iter().any(|lfb| lfb.line_addr == line_addr)
+    }
+
+    // Returns the number of cycles before the line containing the byte address is in the L1D.
+    // If exclusive is true, the line is needed in the MODIFIED state because it will be written.
+    // On a miss, a line fill buffer is allocated unless there already is one for the line.
+    // Returns None if the line needs to be requested and there is no line fill buffer available.
+    fn request_line(&mut self, addr: u64, exclusive: bool) -> Option<u64> {
+        let line_addr = self.l1d.borrow().line_addr(addr);
+        if let Some(lfb) = self.lfbs.iter_mut().find(|lfb| lfb.line_addr == line_addr) {
+            // the line is already on its way
+            if lfb.prefetch {
+                lfb.prefetch = false;
+                self.prefetch_late_cnt += 1;
+            }
+            return Some(lfb.ready_cycle - self.cycle_cnt);
         }
+
+        let state = self.l1d.borrow().state(addr);
+        let hit = match state {
+            MESIState::INVALID => false,
+            MESIState::SHARED => !exclusive,
+            MESIState::EXCLUSIVE |
+            MESIState::MODIFIED => true,
+        };
+
+        if hit {
+            let mut l1d = self.l1d.borrow_mut();
+            l1d.access(addr);
+            if exclusive {
+                // no other L1D has a copy; so the line can be written without telling anyone
+                l1d.set_state(addr, MESIState::MODIFIED);
+            }
+            if self.prefetched_lines.remove(&line_addr) {
+                self.prefetch_useful_cnt += 1;
+            }
+            return Some(0);
+        }
+
+        if self.lfbs.len() == self.lfb_count as usize {
+            return None;
+        }
+
+        let upgrade = state != MESIState::INVALID;
+        if !upgrade {
+            // counts the miss
+            self.l1d.borrow_mut().access(addr);
+            self.prefetched_lines.remove(&line_addr);
+        }
+
+        let (cycles, state) = self.uncore.borrow_mut().request_line(self.core_id, line_addr, exclusive, upgrade, self.cycle_cnt);
+        self.fill(line_addr, state);
+        self.lfbs.push(LFB { line_addr, ready_cycle: self.cycle_cnt + cycles, prefetch: false });
+        Some(cycles)
     }
 
-    pub(crate) fn init(&mut self, program: &Rc<Program>) {
-        for k in 0..self.memory.len() {
-            self.memory[k] = 0;
+    fn fill(&mut self, line_addr: u64, state: MESIState) {
+        let mut l1d = self.l1d.borrow_mut();
+        if let Some(evicted) = l1d.fill(line_addr) {
+            self.prefetched_lines.remove(&evicted);
         }
+        l1d.set_state(line_addr, state);
+    }
+
+    // Trains the prefetcher with the load and fetches the lines it returns. A prefetch
+    // needs a free line fill buffer; if there is none, the prefetch is dropped.
+    fn prefetch(&mut self, pc: usize, addr: u64, miss: bool) {
+        let Some(prefetcher) = &mut self.prefetcher else {
+            return;
+        };
 
-        for data in program.data_items.values() {
-            self.memory[data.offset as usize] = data.value;
+        for line_addr in prefetcher.train(pc, addr, miss) {
+            if self.l1d.borrow().contains(line_addr)
+                || self.lfbs.iter().any(|lfb| lfb.line_addr == line_addr)
+                || self.lfbs.len() == self.lfb_count as usize {
+                continue;
+            }
+
+            self.prefetch_issued_cnt += 1;
+            let (cycles, state) = self.uncore.borrow_mut().request_line(self.core_id, line_addr, false, false, self.cycle_cnt);
+            self.fill(line_addr, state);
+            self.lfbs.push(LFB { line_addr, ready_cycle: self.cycle_cnt + cycles, prefetch: true });
         }
     }
 
+    // Performs the load of size bytes for the load queue entry. The value is forwarded from
+    // the youngest older store in the store buffer that contains all its bytes if there is one,
+    // otherwise it is read from memory. Returns the zero extended value and if it was forwarded.
+    pub(crate) fn load(&mut self, thread_id: u8, lq_index: u16, addr: DWordType, size: u8) -> (DWordType, bool) {
+        let lq = &mut self.lqs[thread_id as usize];
+        let sb = &self.sbs[thread_id as usize];
+        let sb_seq = lq.sb_seq(lq_index);
+        let (value, forwarded_from) = match sb.forward(sb_seq, addr, size) {
+            Forwarding::FULL(seq, value) => (value, Some(seq)),
+            Forwarding::NONE => (self.uncore.borrow().read(addr, size), None),
+            // an older store that only partially overlaps resolved its address after the load
+            // was issued; the bytes of memory and the stores are combined.
+            Forwarding::PARTIAL => (sb.merge(sb_seq, addr, size, self.uncore.borrow().read(addr, size)), None),
+        };
+        lq.executed(lq_index, addr, size, forwarded_from);
+        (value, forwarded_from.is_some())
+    }
+
+    // True if the load of the thread has to wait because it is a load-acquire and an older
+    // store-release hasn't been written to memory yet; a store-release followed by a
+    // load-acquire is never reordered.
+    pub(crate) fn is_acquire_blocked(&self, thread_id: u8, lq_index: u16) -> bool {
+        let sb_seq = self.lqs[thread_id as usize].sb_seq(lq_index);
+        self.sbs[thread_id as usize].has_release_before(sb_seq)
+    }
+
+    // True if the load of the thread has to wait because an older store in the store buffer
+    // contains only part of its bytes; so the value can't be forwarded. The load can be
+    // performed once the store has been written to memory.
+    pub(crate) fn is_forward_blocked(&self, thread_id: u8, lq_index: u16, addr: DWordType, size: u8) -> bool {
+        let sb_seq = self.lqs[thread_id as usize].sb_seq(lq_index);
+        matches!(self.sbs[thread_id as usize].forward(sb_seq, addr, size), Forwarding::PARTIAL)
+    }
+
+    // Performs the store of size bytes for the store buffer entry of the thread and checks if a
+    // younger load of the thread that overlaps with it has already executed.
+    pub(crate) fn store(&mut self, thread_id: u8, sb_index: u16, addr: DWordType, size: u8, value: DWordType) {
+        let sb = &mut self.sbs[thread_id as usize];
+        sb.store(sb_index, addr, size, value);
+        self.lqs[thread_id as usize].check_violation(sb.seq(sb_index), addr, size);
+    }
+
     pub(crate) fn do_cycle(&mut self) {
-        self.sb.do_cycle(&mut self.memory);
+        self.cycle_cnt += 1;
+
+        let mshr_occupied_cnt = self.mshr_occupied_cnt() as u64;
+        if mshr_occupied_cnt > 0 {
+            self.mshr_occupied_sum += mshr_occupied_cnt;
+            self.mshr_busy_cycle_cnt += 1;
+        }
+
+        // release the line fill buffers of the lines that have arrived
+        let cycle_cnt = self.cycle_cnt;
+        let l1d = &self.l1d;
+        let prefetched_lines = &mut self.prefetched_lines;
+        self.lfbs.retain(|lfb| {
+            if lfb.ready_cycle <= cycle_cnt {
+                // the line could have been invalidated by another core in the meantime
+                if lfb.prefetch && l1d.borrow().contains(lfb.line_addr) {
+                    prefetched_lines.insert(lfb.line_addr);
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        // for every thread, write the oldest committed store to memory once its line is owned by the L1D
+        for thread_id in 0..self.sbs.len() {
+            if let Some((addr, size, value)) = self.sbs[thread_id].committed_head() {
+                if self.request_line(addr, true) == Some(0) {
+                    self.uncore.borrow_mut().write(self.core_id, thread_id as u8, addr, size, value);
+                    self.sbs[thread_id].deallocate_head();
+                }
+            }
+        }
     }
 }
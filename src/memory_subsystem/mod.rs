@@ -1,2 +1,7 @@
 pub mod memory_subsystem;
-mod store_buffer;
\ No newline at end of file
+mod store_buffer;
+mod load_queue;
+mod cache;
+mod dram;
+mod prefetcher;
+pub(crate) mod uncore;
@@ -0,0 +1,257 @@
+use crate::cpu::{PrefetcherConfig, PrefetcherType};
+
+/// A hardware prefetcher for the L1D.
+///
+/// The prefetcher is trained with the demand loads and returns the lines that should be
+/// brought into the L1D before they are accessed.
+pub(crate) trait Prefetcher {
+    /// Trains the prefetcher with a load at pc to the byte address; miss is true if the load
+    /// missed the L1D. Returns the addresses of the lines to prefetch.
+    fn train(&mut self, pc: usize, addr: u64, miss: bool) -> Vec<u64>;
+}
+
+pub(crate) fn create_prefetcher(config: &PrefetcherConfig, line_size: u64) -> Option<Box<dyn Prefetcher>> {
+    match config.prefetcher_type {
+        PrefetcherType::NONE => None,
+        PrefetcherType::NEXTLINE => Some(Box::new(NextLinePrefetcher::new(config, line_size))),
+        PrefetcherType::STRIDE => Some(Box::new(StridePrefetcher::new(config, line_size))),
+        PrefetcherType::STREAM => Some(Box::new(StreamPrefetcher::new(config, line_size))),
+    }
+}
+
+/// Prefetches the lines following the line of every access.
+pub(crate) struct NextLinePrefetcher {
+    line_size: u64,
+    degree: u64,
+}
+
+impl NextLinePrefetcher {
+    pub(crate) fn new(config: &PrefetcherConfig, line_size: u64) -> Self {
+        NextLinePrefetcher { line_size, degree: config.degree as u64 }
+    }
+}
+
+impl Prefetcher for NextLinePrefetcher {
+    fn train(&mut self, _pc: usize, addr: u64, _miss: bool) -> Vec<u64> {
+        let line_addr = addr & !(self.line_size - 1);
+        (1..=self.degree).map(|k| line_addr + k * self.line_size).collect()
+    }
+}
+
+// The number of times a stride needs to repeat before the stride prefetcher issues prefetches.
+const STRIDE_CONFIDENCE_THRESHOLD: u8 = 1;
+const STRIDE_CONFIDENCE_MAX: u8 = 3;
+
+#[derive(Clone, Copy)]
+struct StrideEntry {
+    pc: Option<usize>,
+    last_addr: u64,
+    stride: i64,
+    confidence: u8,
+}
+
+/// A table indexed by the pc of the load that records the distance between consecutive
+/// accesses of the load. Once the same stride is seen repeatedly, the next accesses are
+/// prefetched.
+pub(crate) struct StridePrefetcher {
+    entries: Vec<StrideEntry>,
+    line_size: u64,
+    degree: i64,
+}
+
+impl StridePrefetcher {
+    pub(crate) fn new(config: &PrefetcherConfig, line_size: u64) -> Self {
+        assert!(config.stride_table_size > 0, "StridePrefetcher: stride_table_size should be larger than 0");
+
+        StridePrefetcher {
+            entries: vec![StrideEntry { pc: None, last_addr: 0, stride: 0, confidence: 0 }; config.stride_table_size as usize],
+            line_size,
+            degree: config.degree as i64,
+        }
+    }
+}
+
+impl Prefetcher for StridePrefetcher {
+    fn train(&mut self, pc: usize, addr: u64, _miss: bool) -> Vec<u64> {
+        let index = pc % self.entries.len();
+        let entry = &mut self.entries[index];
+
+        if entry.pc != Some(pc) {
+            *entry = StrideEntry { pc: Some(pc), last_addr: addr, stride: 0, confidence: 0 };
+            return Vec::new();
+        }
+
+        let stride = addr.wrapping_sub(entry.last_addr) as i64;
+        entry.last_addr = addr;
+        if stride != 0 && stride == entry.stride {
+            entry.confidence = (entry.confidence + 1).min(STRIDE_CONFIDENCE_MAX);
+        } else {
+            entry.stride = stride;
+            entry.confidence = 0;
+        }
+
+        if entry.confidence < STRIDE_CONFIDENCE_THRESHOLD {
+            return Vec::new();
+        }
+
+        // a stride smaller than a line would prefetch the line that is being accessed; so
+        // at least a line is skipped.
+        let line_size = self.line_size as i64;
+        let step = if entry.stride.abs() < line_size { line_size * entry.stride.signum() } else { entry.stride };
+
+        let line_mask = !(self.line_size - 1);
+        let mut line_addrs: Vec<u64> = Vec::new();
+        for k in 1..=self.degree {
+            let line_addr = addr.wrapping_add((step * k) as u64) & line_mask;
+            if line_addr != addr & line_mask && !line_addrs.contains(&line_addr) {
+                line_addrs.push(line_addr);
+            }
+        }
+        line_addrs
+    }
+}
+
+// The number of accesses to consecutive lines before a stream issues prefetches.
+const STREAM_CONFIDENCE_THRESHOLD: u8 = 2;
+const STREAM_CONFIDENCE_MAX: u8 = 3;
+
+struct Stream {
+    // the line number of the most recent access of the stream
+    last_line: u64,
+    // 1 for an ascending stream, -1 for a descending stream and 0 if unknown
+    direction: i64,
+    confidence: u8,
+    // the value of the access counter when the stream was last used (lru)
+    last_access: u64,
+}
+
+/// Tracks streams of accesses to consecutive lines. A stream is started on an L1D miss and
+/// once it has been confirmed by accesses to the next lines, the lines ahead of the stream
+/// are prefetched.
+pub(crate) struct StreamPrefetcher {
+    streams: Vec<Stream>,
+    stream_count: usize,
+    line_size: u64,
+    degree: i64,
+    access_cnt: u64,
+}
+
+impl StreamPrefetcher {
+    pub(crate) fn new(config: &PrefetcherConfig, line_size: u64) -> Self {
+        assert!(config.stream_count > 0, "StreamPrefetcher: stream_count should be larger than 0");
+
+        StreamPrefetcher {
+            streams: Vec::with_capacity(config.stream_count as usize),
+            stream_count: config.stream_count as usize,
+            line_size,
+            degree: config.degree as i64,
+            access_cnt: 0,
+        }
+    }
+}
+
+impl Prefetcher for StreamPrefetcher {
+    fn train(&mut self, _pc: usize, addr: u64, miss: bool) -> Vec<u64> {
+        self.access_cnt += 1;
+        let line = addr / self.line_size;
+
+        let found = self.streams.iter_mut().find(|stream| {
+            let distance = line.wrapping_sub(stream.last_line) as i64;
+            distance == 0 || (distance.abs() == 1 && (stream.direction == 0 || stream.direction == distance))
+        });
+
+        let Some(stream) = found else {
+            if miss {
+                let new_stream = Stream { last_line: line, direction: 0, confidence: 0, last_access: self.access_cnt };
+                if self.streams.len() < self.stream_count {
+                    self.streams.push(new_stream);
+                } else {
+                    let victim = self.streams.iter_mut().min_by_key(|stream| stream.last_access).unwrap();
+                    *victim = new_stream;
+                }
+            }
+            return Vec::new();
+        };
+
+        stream.last_access = self.access_cnt;
+        let distance = line.wrapping_sub(stream.last_line) as i64;
+        if distance == 0 {
+            // the same line is accessed again; this doesn't advance the stream
+            return Vec::new();
+        }
+
+        stream.direction = distance;
+        stream.last_line = line;
+        stream.confidence = (stream.confidence + 1).min(STREAM_CONFIDENCE_MAX);
+
+        if stream.confidence < STREAM_CONFIDENCE_THRESHOLD {
+            return Vec::new();
+        }
+
+        (1..=self.degree)
+            .map(|k| line.wrapping_add((stream.direction * k) as u64) * self.line_size)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_config(prefetcher_type: PrefetcherType) -> PrefetcherConfig {
+        PrefetcherConfig {
+            prefetcher_type,
+            degree: 2,
+            stride_table_size: 16,
+            stream_count: 2,
+        }
+    }
+
+    #[test]
+    fn test_next_line() {
+        let mut prefetcher = NextLinePrefetcher::new(&new_config(PrefetcherType::NEXTLINE), 64);
+
+        assert_eq!(prefetcher.train(0, 72, true), vec![128, 192]);
+    }
+
+    #[test]
+    fn test_stride() {
+        let mut prefetcher = StridePrefetcher::new(&new_config(PrefetcherType::STRIDE), 64);
+
+        assert!(prefetcher.train(10, 0, true).is_empty());
+        assert!(prefetcher.train(10, 256, true).is_empty());
+        // the stride has repeated
+        assert_eq!(prefetcher.train(10, 512, true), vec![768, 1024]);
+        // a different load doesn't see the stride of the first one
+        assert!(prefetcher.train(11, 1024, true).is_empty());
+    }
+
+    #[test]
+    fn test_stride_smaller_than_line() {
+        let mut prefetcher = StridePrefetcher::new(&new_config(PrefetcherType::STRIDE), 64);
+
+        prefetcher.train(10, 0, true);
+        prefetcher.train(10, 8, false);
+        assert_eq!(prefetcher.train(10, 16, false), vec![64, 128]);
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut prefetcher = StreamPrefetcher::new(&new_config(PrefetcherType::STREAM), 64);
+
+        assert!(prefetcher.train(0, 1024, true).is_empty());
+        assert!(prefetcher.train(0, 1032, false).is_empty());
+        assert!(prefetcher.train(0, 960, true).is_empty());
+        // the stream is descending and confirmed
+        assert_eq!(prefetcher.train(0, 896, true), vec![832, 768]);
+    }
+
+    #[test]
+    fn test_stream_starts_on_miss() {
+        let mut prefetcher = StreamPrefetcher::new(&new_config(PrefetcherType::STREAM), 64);
+
+        prefetcher.train(0, 0, false);
+        prefetcher.train(0, 64, false);
+        assert!(prefetcher.train(0, 128, false).is_empty());
+    }
+}
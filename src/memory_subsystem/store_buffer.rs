@@ -2,6 +2,7 @@ use SBEntryState::{ALLOCATED, COMMITTED, IDLE, READY};
 
 use crate::cpu::CPUConfig;
 use crate::instructions::instructions::DWordType;
+use crate::memory_subsystem::memory_subsystem::{overlaps, truncate};
 
 enum SBEntryState {
     // not used.
@@ -19,6 +20,12 @@ enum SBEntryState {
 struct SBEntry {
     value: DWordType,
     addr: DWordType,
+    // the number of bytes written
+    size: u8,
+    // the sequence of the store in the store buffer; it reflects the program order of stores.
+    seq: u64,
+    // true for a store-release (STLR)
+    release: bool,
     state: SBEntryState,
 }
 
@@ -26,16 +33,28 @@ impl SBEntry {
     fn reset(&mut self) {
         self.state = IDLE;
         self.addr = 0;
+        self.size = 0;
         self.value = 0;
+        self.seq = 0;
+        self.release = false;
     }
 }
 
+// The outcome of looking for older stores to forward to a load.
+pub(crate) enum Forwarding {
+    // no older store overlaps with the load
+    NONE,
+    // the youngest overlapping store contains all bytes of the load; its sequence and the value
+    FULL(u64, DWordType),
+    // the youngest overlapping store contains only part of the bytes of the load
+    PARTIAL,
+}
+
 pub(crate) struct SB {
     head: u64,
     tail: u64,
     entries: Vec<SBEntry>,
     capacity: u16,
-    lfb_count: u8,
 }
 
 impl SB {
@@ -45,6 +64,9 @@ impl SB {
             entries.push(SBEntry {
                 value: 0,
                 addr: 0,
+                size: 0,
+                seq: 0,
+                release: false,
                 state: IDLE,
             })
         }
@@ -54,7 +76,6 @@ impl SB {
             head: 0,
             tail: 0,
             entries,
-            lfb_count: cpu_config.lfb_count,
         }
     }
 
@@ -70,11 +91,24 @@ impl SB {
         return self.size() < self.capacity;
     }
 
-    pub(crate) fn allocate(&mut self) -> u16 {
+    // True if cnt entries can be allocated; a pair needs two entries.
+    pub(crate) fn has_space_for(&self, cnt: u8) -> bool {
+        self.size() + cnt as u16 <= self.capacity
+    }
+
+    // The index of the entry that is allocated right after the entry at the index.
+    pub(crate) fn next_index(&self, index: u16) -> u16 {
+        (index + 1) % self.capacity
+    }
+
+    pub(crate) fn allocate(&mut self, release: bool) -> u16 {
         assert!(self.has_space(), "StoreBuffer: can't allocate because there is no space");
 
         let index = self.to_index(self.tail);
-        self.entries[index].state = ALLOCATED;
+        let sb_entry = &mut self.entries[index];
+        sb_entry.state = ALLOCATED;
+        sb_entry.seq = self.tail;
+        sb_entry.release = release;
         self.tail += 1;
         return index as u16;
     }
@@ -83,13 +117,85 @@ impl SB {
         (seq % self.capacity as u64) as usize
     }
 
-    pub(crate) fn store(&mut self, index: u16, addr: DWordType, value: DWordType) {
+    // The sequence the next allocated store will get.
+    pub(crate) fn tail_seq(&self) -> u64 {
+        self.tail
+    }
+
+    pub(crate) fn seq(&self, index: u16) -> u64 {
+        self.entries[index as usize].seq
+    }
+
+    // Looks for the youngest store older than the given sequence that overlaps with the load.
+    // Stores that haven't resolved their address yet are skipped; if such a store turns out
+    // to overlap, the load queue detects the violation.
+    pub(crate) fn forward(&self, before_seq: u64, addr: DWordType, size: u8) -> Forwarding {
+        for k in (self.head..before_seq.min(self.tail)).rev() {
+            let sb_entry = &self.entries[self.to_index(k)];
+            match sb_entry.state {
+                ALLOCATED => {}
+                READY |
+                COMMITTED => {
+                    if !overlaps(sb_entry.addr, sb_entry.size, addr, size) {
+                        continue;
+                    }
+
+                    return if sb_entry.addr <= addr && addr + size as u64 <= sb_entry.addr + sb_entry.size as u64 {
+                        let shift = (addr - sb_entry.addr) * 8;
+                        Forwarding::FULL(k, truncate(sb_entry.value >> shift, size))
+                    } else {
+                        Forwarding::PARTIAL
+                    };
+                }
+                _ => unreachable!(),
+            }
+        }
+        Forwarding::NONE
+    }
+
+    // Combines the value read from memory for the load with the bytes of the older stores
+    // that overlap with it; the younger stores are applied last.
+    pub(crate) fn merge(&self, before_seq: u64, addr: DWordType, size: u8, value: DWordType) -> DWordType {
+        let mut bytes = value.to_le_bytes();
+        for k in self.head..before_seq.min(self.tail) {
+            let sb_entry = &self.entries[self.to_index(k)];
+            match sb_entry.state {
+                ALLOCATED => {}
+                READY |
+                COMMITTED => {
+                    let store_bytes = sb_entry.value.to_le_bytes();
+                    for (offset, byte) in bytes.iter_mut().enumerate().take(size as usize) {
+                        let byte_addr = addr + offset as u64;
+                        if sb_entry.addr <= byte_addr && byte_addr < sb_entry.addr + sb_entry.size as u64 {
+                            *byte = store_bytes[(byte_addr - sb_entry.addr) as usize];
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        DWordType::from_le_bytes(bytes)
+    }
+
+    // True if there is a store-release older than the given sequence that hasn't been written
+    // to memory yet.
+    pub(crate) fn has_release_before(&self, before_seq: u64) -> bool {
+        (self.head..before_seq.min(self.tail)).any(|k| self.entries[self.to_index(k)].release)
+    }
+
+    // True if there is a committed store that hasn't been written to memory yet.
+    pub(crate) fn has_committed(&self) -> bool {
+        self.committed_head().is_some()
+    }
+
+    pub(crate) fn store(&mut self, index: u16, addr: DWordType, size: u8, value: DWordType) {
         let sb_entry = &mut self.entries[index as usize];
 
         match sb_entry.state {
             ALLOCATED => {
                 sb_entry.addr = addr;
-                sb_entry.value = value;
+                sb_entry.size = size;
+                sb_entry.value = truncate(value, size);
                 sb_entry.state = READY;
             }
             _ => unreachable!(),
@@ -105,47 +211,49 @@ impl SB {
         }
     }
 
-    pub(crate) fn flush(&mut self) {
-        // to flush, we go backwards from the tail and 'deallocate' every store
-        // until a committed store is found. Behind that committed store can only
-        // be other committed stores because commits are done in order (retire).
+    // Deallocates the youngest store. This is done when the instruction that allocated
+    // the store is squashed; younger instructions are squashed before older ones, so the
+    // store is always at the tail.
+    pub(crate) fn deallocate_tail(&mut self, index: u16) {
+        debug_assert!(!self.is_empty());
+        debug_assert!(self.to_index(self.tail - 1) == index as usize, "StoreBuffer: store {} is not at the tail", index);
 
-        for k in (self.head..self.tail).rev() {
-            let index = self.to_index(k);
-            let sb_entry = &mut self.entries[index];
-            match sb_entry.state {
-                ALLOCATED |
-                READY => {
-                    sb_entry.reset();
-                    self.tail -= 1;
-                }
-                COMMITTED => {
-                    break;
-                }
-                _ => unreachable!(),
+        let sb_entry = &mut self.entries[index as usize];
+        match sb_entry.state {
+            ALLOCATED |
+            READY => {
+                sb_entry.reset();
+                self.tail -= 1;
             }
+            _ => unreachable!(),
         }
     }
 
-    pub(crate) fn do_cycle(&mut self, memory: &mut Vec<DWordType>) {
-        for _ in 0..self.lfb_count {
-            if self.is_empty() {
-                break;
-            }
+    // Returns the address, size and value of the oldest store if it is committed.
+    pub(crate) fn committed_head(&self) -> Option<(DWordType, u8, DWordType)> {
+        if self.is_empty() {
+            return None;
+        }
 
-            let index = self.to_index(self.head);
-            let sb_entry = &mut self.entries[index];
-            match sb_entry.state {
-                ALLOCATED |
-                READY => {}
-                COMMITTED => {
-                    // write the store to memory
-                    memory[sb_entry.addr as usize] = sb_entry.value;
-                    sb_entry.reset();
-                    self.head += 1;
-                }
-                _ => unreachable!(),
+        let sb_entry = &self.entries[self.to_index(self.head)];
+        match sb_entry.state {
+            ALLOCATED |
+            READY => None,
+            COMMITTED => Some((sb_entry.addr, sb_entry.size, sb_entry.value)),
+            _ => unreachable!(),
+        }
+    }
+
+    // Deallocates the oldest store after it has been written to memory.
+    pub(crate) fn deallocate_head(&mut self) {
+        let index = self.to_index(self.head);
+        let sb_entry = &mut self.entries[index];
+        match sb_entry.state {
+            COMMITTED => {
+                sb_entry.reset();
+                self.head += 1;
             }
+            _ => unreachable!(),
         }
     }
 }
@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::CPUConfig;
+use crate::instructions::instructions::{DWordType, Program};
+use crate::memory_subsystem::cache::{Cache, MESIState};
+use crate::memory_subsystem::dram::DRAM;
+use crate::memory_subsystem::memory_subsystem::{AtomicOp, overlaps, WORD_SIZE};
+
+/// The part of the memory hierarchy that is shared by all cores: main memory, the outer
+/// caches and DRAM.
+///
+/// It also acts as the snooping bus that keeps the private L1Ds of the cores coherent using
+/// the MESI protocol. A core that misses its L1D, or needs to write a line it doesn't own,
+/// snoops the L1Ds of the other cores. A request is handled atomically; so the states of
+/// all the L1Ds are updated at the moment of the request.
+///
+/// The exclusive monitors of the hardware threads, used by LDXR/STXR, are kept here as well.
+/// A write to memory clears the monitors of the other threads on the word, so that their
+/// STXR fails.
+pub(crate) struct Uncore {
+    // the bytes of main memory; values are stored little endian
+    memory: Vec<u8>,
+    pub(crate) outer_caches: Vec<Cache>,
+    pub(crate) dram: DRAM,
+    // the L1D of every core; indexed by core id
+    l1ds: Vec<Rc<RefCell<Cache>>>,
+    snoop_latency: u64,
+    // the number of lines that were invalidated in the L1D of another core
+    pub(crate) invalidation_cnt: u64,
+    // the number of lines that were provided by the L1D of another core
+    pub(crate) cache_to_cache_cnt: u64,
+    thread_count: u8,
+    // the address marked by the exclusive monitor of every hardware thread; indexed by
+    // core_id * thread_count + thread_id
+    exclusive_monitors: Vec<Option<DWordType>>,
+}
+
+impl Uncore {
+    pub(crate) fn new(cpu_config: &CPUConfig) -> Uncore {
+        let outer_caches = cpu_config.outer_caches.iter().enumerate()
+            .map(|(index, cache_config)| Cache::new(&format!("L{}", index + 2), cache_config))
+            .collect();
+
+        Uncore {
+            memory: vec![0; cpu_config.memory_size as usize],
+            outer_caches,
+            dram: DRAM::new(&cpu_config.dram),
+            l1ds: Vec::with_capacity(cpu_config.core_count as usize),
+            snoop_latency: cpu_config.snoop_latency as u64,
+            invalidation_cnt: 0,
+            cache_to_cache_cnt: 0,
+            thread_count: cpu_config.thread_count,
+            exclusive_monitors: vec![None; cpu_config.core_count as usize * cpu_config.thread_count as usize],
+        }
+    }
+
+    pub(crate) fn init(&mut self, program: &Rc<Program>) {
+        self.memory.fill(0);
+
+        for data in program.data_items.values() {
+            self.memory[data.offset as usize..(data.offset + WORD_SIZE) as usize].copy_from_slice(&data.value.to_le_bytes());
+        }
+
+        for cache in &mut self.outer_caches {
+            cache.reset();
+        }
+        self.dram.reset();
+        self.exclusive_monitors.fill(None);
+    }
+
+    fn monitor_index(&self, core_id: u8, thread_id: u8) -> usize {
+        core_id as usize * self.thread_count as usize + thread_id as usize
+    }
+
+    // Reads the size bytes at the address; the value is zero extended.
+    pub(crate) fn read(&self, addr: DWordType, size: u8) -> DWordType {
+        let mut bytes = [0u8; WORD_SIZE as usize];
+        bytes[..size as usize].copy_from_slice(&self.memory[addr as usize..addr as usize + size as usize]);
+        DWordType::from_le_bytes(bytes)
+    }
+
+    // Writes the lowest size bytes of the value on behalf of the thread and clears the exclusive
+    // monitors of the other threads that mark a word the write overlaps with.
+    pub(crate) fn write(&mut self, core_id: u8, thread_id: u8, addr: DWordType, size: u8, value: DWordType) {
+        let writer = self.monitor_index(core_id, thread_id);
+        for (index, monitor) in self.exclusive_monitors.iter_mut().enumerate() {
+            if index != writer && monitor.is_some_and(|marked| overlaps(marked, WORD_SIZE as u8, addr, size)) {
+                *monitor = None;
+            }
+        }
+        self.memory[addr as usize..addr as usize + size as usize].copy_from_slice(&value.to_le_bytes()[..size as usize]);
+    }
+
+    // Performs the atomic of the thread on the word and returns the value for its destination
+    // register: the status for STXR (0 on success, 1 on failure) and the old value otherwise.
+    // The whole read-modify-write happens at once; so no other write can get in between.
+    pub(crate) fn atomic(&mut self, core_id: u8, thread_id: u8, addr: DWordType, op: AtomicOp) -> DWordType {
+        let monitor = self.monitor_index(core_id, thread_id);
+        let old_value = self.read(addr, WORD_SIZE as u8);
+        match op {
+            AtomicOp::LDXR => {
+                self.exclusive_monitors[monitor] = Some(addr);
+                old_value
+            }
+            AtomicOp::STXR(value) => {
+                let success = self.exclusive_monitors[monitor] == Some(addr);
+                self.exclusive_monitors[monitor] = None;
+                if success {
+                    self.write(core_id, thread_id, addr, WORD_SIZE as u8, value);
+                    0
+                } else {
+                    1
+                }
+            }
+            AtomicOp::LDADD(value) => {
+                self.write(core_id, thread_id, addr, WORD_SIZE as u8, old_value.wrapping_add(value));
+                old_value
+            }
+            AtomicOp::SWP(value) => {
+                self.write(core_id, thread_id, addr, WORD_SIZE as u8, value);
+                old_value
+            }
+            AtomicOp::CAS(expected, value) => {
+                if old_value == expected {
+                    self.write(core_id, thread_id, addr, WORD_SIZE as u8, value);
+                }
+                old_value
+            }
+        }
+    }
+
+    pub(crate) fn clear_exclusive(&mut self, core_id: u8, thread_id: u8) {
+        let monitor = self.monitor_index(core_id, thread_id);
+        self.exclusive_monitors[monitor] = None;
+    }
+
+    // Registers the L1D of the core; the cores are registered in order of their core id.
+    pub(crate) fn register_l1d(&mut self, core_id: u8, l1d: &Rc<RefCell<Cache>>) {
+        assert_eq!(core_id as usize, self.l1ds.len(), "Uncore: the L1D of core {} is registered out of order", core_id);
+        self.l1ds.push(Rc::clone(l1d));
+    }
+
+    // Requests the line for the L1D of the core. If exclusive is true, the core wants to write
+    // the line and the copies in the other L1Ds are invalidated. If upgrade is true, the core
+    // already has a shared copy of the line; so only the other copies need to be invalidated.
+    // Returns the number of cycles before the line is available and the state of the line in
+    // the L1D of the core.
+    pub(crate) fn request_line(&mut self, core_id: u8, line_addr: u64, exclusive: bool, upgrade: bool, cycle: u64) -> (u64, MESIState) {
+        let mut shared = false;
+        let mut dirty = false;
+        for (other_core_id, l1d) in self.l1ds.iter().enumerate() {
+            if other_core_id == core_id as usize {
+                continue;
+            }
+
+            let mut l1d = l1d.borrow_mut();
+            let state = l1d.state(line_addr);
+            if state == MESIState::INVALID {
+                continue;
+            }
+
+            shared = true;
+            dirty |= state == MESIState::MODIFIED;
+            if exclusive {
+                l1d.set_state(line_addr, MESIState::INVALID);
+                self.invalidation_cnt += 1;
+            } else {
+                l1d.set_state(line_addr, MESIState::SHARED);
+            }
+        }
+
+        let cycles = if upgrade {
+            self.snoop_latency
+        } else if dirty {
+            // the modified line is provided by the L1D that owned it
+            self.cache_to_cache_cnt += 1;
+            self.snoop_latency
+        } else {
+            self.fetch_line(line_addr, cycle)
+        };
+
+        let state = if exclusive {
+            MESIState::MODIFIED
+        } else if shared {
+            MESIState::SHARED
+        } else {
+            MESIState::EXCLUSIVE
+        };
+        (cycles, state)
+    }
+
+    // Fetches the line from the outer caches or DRAM and returns the number of cycles it takes.
+    // The outer caches that missed are filled with the line.
+    fn fetch_line(&mut self, line_addr: u64, cycle: u64) -> u64 {
+        let mut cycles = 0;
+        let mut missed_cnt = self.outer_caches.len();
+        for (level, cache) in self.outer_caches.iter_mut().enumerate() {
+            cycles += cache.hit_latency as u64;
+            if cache.access(line_addr) {
+                missed_cnt = level;
+                break;
+            }
+        }
+
+        if missed_cnt == self.outer_caches.len() {
+            cycles += self.dram.access(line_addr, cycle + cycles);
+        }
+
+        for cache in &mut self.outer_caches[..missed_cnt] {
+            cache.fill(line_addr);
+        }
+        cycles
+    }
+}
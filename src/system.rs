@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::ops::Add;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cpu::{CPU, CPUConfig};
+use crate::instructions::instructions::Program;
+use crate::memory_subsystem::uncore::Uncore;
+
+/// A number of cores that share main memory.
+///
+/// Every core runs the same program starting at the entry point. A core can find out its
+/// core id by reading the MPIDR_EL1 system register using MRS, so that each core can run
+/// its own part of the program.
+pub struct System {
+    pub(crate) cores: Vec<CPU>,
+    pub(crate) uncore: Rc<RefCell<Uncore>>,
+    pub(crate) cycle_period: Duration,
+    pub(crate) stats_seconds: u32,
+}
+
+impl System {
+    pub fn new(cpu_config: &CPUConfig) -> System {
+        assert!(cpu_config.core_count > 0, "System: core_count should be larger than 0");
+
+        let uncore = Rc::new(RefCell::new(Uncore::new(cpu_config)));
+
+        let mut cores = Vec::with_capacity(cpu_config.core_count as usize);
+        for core_id in 0..cpu_config.core_count {
+            cores.push(CPU::new(cpu_config, core_id, &uncore));
+        }
+
+        System {
+            cores,
+            uncore,
+            cycle_period: Duration::from_micros(1_000_000 / cpu_config.frequency_hz),
+            stats_seconds: cpu_config.stats_seconds,
+        }
+    }
+
+    pub fn run(&mut self, program: &Rc<Program>) {
+        self.uncore.borrow_mut().init(program);
+        for core in &mut self.cores {
+            core.init(program);
+        }
+
+        let log_stats_interval = Duration::new(self.stats_seconds as u64, 0); // n seconds
+        println!("log_stats_interval: {:?}", log_stats_interval);
+        let mut last_log_stats_time = Instant::now().add(log_stats_interval);
+
+        while !self.cores.iter().all(|core| core.is_done()) {
+            for core in &mut self.cores {
+                core.do_cycle();
+            }
+            thread::sleep(self.cycle_period);
+
+            if self.stats_seconds > 0 && last_log_stats_time.elapsed() >= log_stats_interval {
+                self.log_stats();
+                last_log_stats_time = Instant::now();
+            }
+        }
+
+        println!("Program complete!");
+    }
+
+    fn log_stats(&self) {
+        for core in &self.cores {
+            core.log_stats();
+        }
+
+        let uncore = self.uncore.borrow();
+        let mut message = String::new();
+        for cache in &uncore.outer_caches {
+            message.push_str(&format!("[{} Hit={}, Miss={}, Evict={}]", cache.name, cache.hit_cnt, cache.miss_cnt, cache.eviction_cnt));
+        }
+        let dram = &uncore.dram;
+        message.push_str(&format!("[DRAM Row Hit={}, Row Miss={}]", dram.row_hit_cnt, dram.row_miss_cnt));
+        message.push_str(&format!("[Invalidations={}, Cache To Cache={}]", uncore.invalidation_cnt, uncore.cache_to_cache_cnt));
+        println!("{}", message);
+    }
+}